@@ -79,6 +79,9 @@ bios_call_asm:
     /* Load interrupt number */
     movb (%di), %al
     movb %al,   (_int_id)
+    /* Load far call target, 0 if this is an interrupt call */
+    movl 32(%di), %eax
+    movl %eax,    (_far_target)
 
     /* Load parameters */
     movl  4(%di), %eax
@@ -89,11 +92,32 @@ bios_call_asm:
     movl 24(%di), %edi
     /* @note Not loading EFLAGS, not sure any interrupts use any of those bits as input */
 
+    cmpl $0, (_far_target)
+    je   2f
+
+    /* Far call, using the PXE calling convention: parameters are passed both
+     * on the stack (!PXE) and in BX and ES:DI (PXENV+). */
+    pushw %es
+    pushw %di
+    pushw %bx
+    lcallw *(_far_target)
+    addw  $6, %sp
+
+    /* The callee may not preserve data segments */
+    pushl %eax
+    xorw  %ax, %ax
+    movw  %ax, %ds
+    movw  %ax, %es
+    popl  %eax
+    jmp  3f
+
+2:
     /* @note INT only accepts immediates, so we need to modify the code */
     .byte 0xCD /* INT */
 _int_id:
     .byte 0x00 /* imm8 */
 
+3:
     /* Save results */
     pushw %bp
     movw  (_bios_call_ptr), %bp
@@ -164,6 +188,9 @@ _int_id:
 _bios_call_ptr:
     .skip 4
 
+_far_target:
+    .skip 4
+
 _saved_idtr:
     .skip 6
 
@@ -34,6 +34,8 @@ pub struct BiosCall {
     pub edi: u32,
 
     pub eflags: u32,
+
+    pub far_target: u32, //< Real-mode far call target (segment:offset), used instead of `int_n` if non-zero
 }
 impl BiosCall {
     pub unsafe fn call(&mut self) {
@@ -47,6 +49,18 @@ impl BiosCall {
         if int_en { intr::interrupts_enable(); }
     }
 
+    /// Far call a real-mode entry point instead of raising an interrupt. The
+    /// callee receives BX and ES:DI as well as BX, DI and ES pushed on the
+    /// stack, as used by the PXE API.
+    ///
+    /// # Arguments
+    /// * segment - Segment of entry point
+    /// * offset - Offset of entry point
+    pub unsafe fn far_call(&mut self, segment: u16, offset: u16) {
+        self.far_target = ((segment as u32) << 16) | offset as u32;
+        self.call();
+    }
+
     pub fn print(&self) {
         println!("BiosCall {:02x}:", self.int_n);
         println!("  EAX: {:08x} EBX: {:08x}", self.eax, self.ebx);
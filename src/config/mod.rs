@@ -2,8 +2,14 @@ use alloc::{
     string::{String, ToString},
     vec::Vec,
 };
+use core::fmt::Write;
 
-use crate::{errors::ErrorCode, storage::fs::File};
+use crate::{
+    errors::ErrorCode,
+    io::output,
+    io::serial::{SerialConfig, SerialParity, SerialPortBase},
+    storage::fs::File,
+};
 
 #[derive(Default)]
 pub struct ModuleConfig {
@@ -13,6 +19,24 @@ pub struct ModuleConfig {
     pub size: usize,
 }
 
+/// Serial port to mirror loader output to
+pub struct ConsoleConfig {
+    pub port: SerialPortBase,
+    pub baud: Option<u32>, //< Overrides the baud rate of the port's `SERIAL` setting
+}
+
+/// Line settings of a serial port
+#[derive(Clone, Copy)]
+pub struct SerialPortConfig {
+    pub port: SerialPortBase,
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: SerialParity,
+    pub stop_bits: u8,
+    pub use_rts: bool, //< RTS/CTS flow control
+    pub use_dtr: bool, //< DTR/DSR flow control
+}
+
 #[derive(Default)]
 pub struct Config {
     pub version: u8,
@@ -20,6 +44,10 @@ pub struct Config {
     pub kernel_cmdline: String,
 
     pub modules: Vec<ModuleConfig>,
+    pub ramdisks: Vec<String>,          //< Disk images to load into memory and mount
+    pub network: bool,                  //< Configure a native network interface with DHCP, unless booted by PXE
+    pub console: Option<ConsoleConfig>, //< Serial console, in addition to the screen
+    pub serial: Vec<SerialPortConfig>,  //< Line settings of serial ports
 }
 
 impl core::fmt::Display for Config {
@@ -30,12 +58,74 @@ impl core::fmt::Display for Config {
             write!(f, "\n  {{ path: {}, name: {}, addr: 0x{:x}, size: 0x{:x} }}",
                    md.path, md.name, md.addr, md.size)?;
         }
+        write!(f, ", ramdisks: ")?;
+        for rd in &self.ramdisks {
+            write!(f, "\n  {}", rd)?;
+        }
+        write!(f, ", network: {}, console: ", self.network)?;
+        match &self.console {
+            Some(console) => {
+                write!(f, "{}", console.port.name())?;
+                if let Some(baud) = console.baud {
+                    write!(f, " at {} baud", baud)?;
+                }
+            },
+            None          => write!(f, "none")?,
+        }
+        write!(f, ", serial: ")?;
+        for sp in &self.serial {
+            write!(f, "\n  {}", sp)?;
+        }
         write!(f, "}}")?;
 
         Ok(())
     }
 }
 
+impl core::fmt::Display for SerialPortConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} {} {}{}{}", self.port.name(), self.baud, self.data_bits, self.parity.letter(), self.stop_bits)?;
+        if self.use_rts {
+            write!(f, " rtscts")?;
+        }
+        if self.use_dtr {
+            write!(f, " dtrdsr")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl SerialPortConfig {
+    /// Settings of a port without a `SERIAL` line: 115200 baud 8N1, without
+    /// flow control
+    pub fn new(port: SerialPortBase) -> SerialPortConfig {
+        SerialPortConfig {
+            port,
+            baud: 115200,
+            data_bits: 8,
+            parity: SerialParity::None,
+            stop_bits: 1,
+            use_rts: false,
+            use_dtr: false,
+        }
+    }
+
+    /// Driver settings for the port, with buffers sized for its use
+    pub fn serial_config(&self, rxfifo_sz: usize, txfifo_sz: usize) -> SerialConfig {
+        SerialConfig {
+            baud: self.baud,
+            data_bits: self.data_bits,
+            parity: self.parity,
+            stop_bits: self.stop_bits,
+            rxfifo_sz,
+            txfifo_sz,
+            use_rts: self.use_rts,
+            use_dtr: self.use_dtr,
+        }
+    }
+}
+
 impl Config {
     pub fn load(file: &dyn File) -> Result<Config, ErrorCode> {
         let data = match file.read(0, file.get_size()) {
@@ -73,6 +163,29 @@ impl Config {
                             None => { /* TODO */ }
                         }
                     },
+                    "RAMDISK" => conf.ramdisks.push(val.to_string()),
+                    "CONSOLE" => {
+                        match Self::parse_console(val) {
+                            Some(console) => conf.console = Some(console),
+                            None => println!("Ignoring invalid config line: {}", tline),
+                        }
+                    },
+                    "SERIAL" => {
+                        match Self::parse_serial(val) {
+                            Some(sp) => {
+                                /* The last setting for a port wins */
+                                conf.serial.retain(|old| old.port != sp.port);
+                                conf.serial.push(sp);
+                            },
+                            None => println!("Ignoring invalid config line: {}", tline),
+                        }
+                    },
+                    "NETWORK" => {
+                        match val {
+                            "dhcp" => conf.network = true,
+                            _ => println!("Ignoring invalid config line: {}", tline),
+                        }
+                    },
                     _ => {
                         /* TODO: Error */
                     }
@@ -88,6 +201,72 @@ impl Config {
         Ok(conf)
     }
 
+    /// Parse a console setting of the form `serial,<port>[,<baud>]`
+    fn parse_console(cfg: &str) -> Option<ConsoleConfig> {
+        let mut fields = cfg.split(',').map(|field| field.trim());
+
+        if fields.next()? != "serial" {
+            return None;
+        }
+        let port = SerialPortBase::from_name(fields.next()?)?;
+        let baud = match fields.next() {
+            Some(baud) => Some(Self::parse_baud(baud)?),
+            None       => None,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+
+        Some(ConsoleConfig {
+            port,
+            baud,
+        })
+    }
+
+    /// Parse serial port settings of the form
+    /// `<port>[,<baud>[,<data bits><parity><stop bits>[,<flow control>]]]`,
+    /// e.g. `COM2,57600,7E1,rtscts`. Flow control is one of `none`, `rtscts`
+    /// or `dtrdsr`.
+    fn parse_serial(cfg: &str) -> Option<SerialPortConfig> {
+        let mut fields = cfg.split(',').map(|field| field.trim());
+
+        let mut sp = SerialPortConfig::new(SerialPortBase::from_name(fields.next()?)?);
+        if let Some(baud) = fields.next() {
+            sp.baud = Self::parse_baud(baud)?;
+        }
+        if let Some(framing) = fields.next() {
+            let mut chars = framing.chars();
+            sp.data_bits = chars.next()?.to_digit(10).filter(|bits| (5..=8).contains(bits))? as u8;
+            sp.parity = SerialParity::from_letter(chars.next()?)?;
+            sp.stop_bits = chars.next()?.to_digit(10).filter(|bits| (1..=2).contains(bits))? as u8;
+            if chars.next().is_some() {
+                return None;
+            }
+        }
+        match fields.next() {
+            Some("none") | None => {},
+            Some("rtscts")      => sp.use_rts = true,
+            Some("dtrdsr")      => sp.use_dtr = true,
+            Some(_)             => return None,
+        }
+        if fields.next().is_some() {
+            return None;
+        }
+
+        Some(sp)
+    }
+
+    /// Parse a baud rate the UART can be programmed for
+    fn parse_baud(baud: &str) -> Option<u32> {
+        baud.parse().ok().filter(|&baud| baud > 0 && baud <= 115200)
+    }
+
+    /// Get the settings of a serial port, or the defaults if it has no
+    /// `SERIAL` line
+    pub fn serial_port(&self, port: SerialPortBase) -> SerialPortConfig {
+        self.serial.iter().find(|sp| sp.port == port).copied().unwrap_or(SerialPortConfig::new(port))
+    }
+
     fn parse_module(cfg: &str) -> Option<ModuleConfig> {
         let md = ModuleConfig {
             path: cfg.to_string(),
@@ -102,3 +281,65 @@ impl Config {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baud_range() {
+        assert_eq!(Config::parse_baud("115200"), Some(115200));
+        assert_eq!(Config::parse_baud("300"), Some(300));
+        assert_eq!(Config::parse_baud("0"), None);
+        assert_eq!(Config::parse_baud("115201"), None);
+        assert_eq!(Config::parse_baud("230400"), None);
+        assert_eq!(Config::parse_baud("-9600"), None);
+        assert_eq!(Config::parse_baud("fast"), None);
+    }
+
+    #[test]
+    fn serial_settings() {
+        let sp = Config::parse_serial("COM2").unwrap();
+        assert!(sp.port == SerialPortBase::COM2);
+        assert_eq!((sp.baud, sp.data_bits, sp.stop_bits), (115200, 8, 1));
+        assert!(sp.parity == SerialParity::None);
+        assert!(!sp.use_rts && !sp.use_dtr);
+
+        let sp = Config::parse_serial("com1, 57600, 7E1, rtscts").unwrap();
+        assert!(sp.port == SerialPortBase::COM1);
+        assert_eq!((sp.baud, sp.data_bits, sp.stop_bits), (57600, 7, 1));
+        assert!(sp.parity == SerialParity::Even);
+        assert!(sp.use_rts && !sp.use_dtr);
+
+        let sp = Config::parse_serial("COM3,9600,5s2,dtrdsr").unwrap();
+        assert_eq!((sp.data_bits, sp.stop_bits), (5, 2));
+        assert!(sp.parity == SerialParity::Space);
+        assert!(!sp.use_rts && sp.use_dtr);
+
+        assert!(Config::parse_serial("COM4,9600,8N1,none").is_some());
+    }
+
+    #[test]
+    fn serial_settings_rejected() {
+        for cfg in ["COM5", "", "COM1,0", "COM1,115201", "COM1,9600,9N1", "COM1,9600,4N1",
+                    "COM1,9600,8X1", "COM1,9600,8N3", "COM1,9600,8N1x", "COM1,9600,8N",
+                    "COM1,9600,8N1,xonxoff", "COM1,9600,8N1,RTSCTS", "COM1,9600,8N1,none,extra"] {
+            assert!(Config::parse_serial(cfg).is_none(), "{}", cfg);
+        }
+    }
+
+    #[test]
+    fn console_settings() {
+        let console = Config::parse_console("serial,COM2").unwrap();
+        assert!(console.port == SerialPortBase::COM2);
+        assert_eq!(console.baud, None);
+
+        let console = Config::parse_console("serial, COM1, 9600").unwrap();
+        assert!(console.port == SerialPortBase::COM1);
+        assert_eq!(console.baud, Some(9600));
+
+        for cfg in ["", "serial", "vga,COM1", "serial,COM9", "serial,COM1,0", "serial,COM1,230400",
+                    "serial,COM1,9600,8N1"] {
+            assert!(Config::parse_console(cfg).is_none(), "{}", cfg);
+        }
+    }
+}
@@ -77,7 +77,7 @@ impl<T: Copy> FIFO<T> {
         if head <= tail {
             tail - head
         } else {
-            (head - tail) - 1
+            (self.size - head) + tail
         }
     }
 
@@ -92,8 +92,44 @@ impl<T: Copy> FIFO<T> {
         if head <= tail {
             (self.size - (tail - head)) - 1
         } else {
-            self.size - (head - tail)
+            (head - tail) - 1
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_across_wrap() {
+        let mut fifo: FIFO<u8> = FIFO::new(8);
+
+        /* Move head and tail near the end of the buffer */
+        for i in 0..6 {
+            assert!(fifo.enqueue(i).is_ok());
+        }
+        for i in 0..6 {
+            assert_eq!(fifo.dequeue(), Some(i));
+        }
+
+        /* Tail wraps past the end, leaving it below head */
+        for i in 0..5 {
+            assert!(fifo.enqueue(i).is_ok());
+        }
+        assert_eq!(fifo.len(), 5);
+        assert_eq!(fifo.free(), 2);
+
+        assert!(fifo.enqueue(5).is_ok());
+        assert!(fifo.enqueue(6).is_ok());
+        assert_eq!(fifo.free(), 0);
+        assert!(fifo.enqueue(7).is_err());
+
+        for i in 0..7 {
+            assert_eq!(fifo.dequeue(), Some(i));
+        }
+        assert_eq!(fifo.len(), 0);
+        assert_eq!(fifo.dequeue(), None);
+    }
+}
@@ -15,7 +15,11 @@ pub enum ErrorCode {
     FileNotFound,
     FileUnsupported,
     ReadFailure,
-
+    UnsupportedFilesystem,
+    AlreadyExists,
+    NoDevice,
+    Timeout,
+    ConnectionReset,
 }
 impl Display for ErrorCode {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
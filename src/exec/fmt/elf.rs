@@ -9,6 +9,7 @@ use alloc::vec::Vec;
 
 use crate::output;
 use crate::errors::ErrorCode;
+use crate::highmem;
 use crate::{config::Config, storage::fs::File};
 use super::{ExecFmt, ExecFmtTestResult};
 
@@ -19,9 +20,6 @@ pub struct ExecFmtELF {
     data_end: u32,
 }
 
-/// Size in bytes of blocks to read from file
-const BLOCK_READ_SZ: usize = 1024;
-
 impl ExecFmtELF {
     pub fn new() -> Self {
         ExecFmtELF{
@@ -47,10 +45,34 @@ impl ExecFmtELF {
 
     /// Check if program header is compatible with our constreaints.
     ///
+    /// Segments are written straight to their physical address, so one below
+    /// 1 MiB would overwrite the loader, its heap and stack, or the BIOS data
+    /// it still relies on. Files staged in extended memory, which may include
+    /// the kernel itself, must not be overwritten either.
+    ///
     /// Returns true if okay, else false
     fn check_phdr(&self) -> bool {
+        let staged = highmem::staged();
+
         for phent in &self.phdr {
-            if phent.htype == ElfProgramHeaderType::Load && phent.vaddr < 0x100000 {
+            if phent.htype != ElfProgramHeaderType::Load {
+                continue;
+            }
+
+            let (paddr, filesz, memsz) = (phent.paddr, phent.filesz, phent.memsz);
+            let end = match paddr.checked_add(memsz) {
+                Some(end) if phent.vaddr >= ELF_LOAD_MIN_ADDR && paddr >= ELF_LOAD_MIN_ADDR &&
+                             memsz >= filesz => end,
+                _ => {
+                    println!("  Segment at {:x}, {} bytes in memory, {} in file, can't be loaded",
+                             paddr, memsz, filesz);
+                    return false;
+                }
+            };
+
+            if (paddr as usize) < staged.end && (end as usize) > staged.start {
+                println!("  Segment at {:x}, {} bytes in memory, overlaps files received at {:x}",
+                         paddr, memsz, staged.start);
                 return false;
             }
         }
@@ -71,25 +93,12 @@ impl ExecFmtELF {
                 if phdr.filesz > 0 {
                     println!("  Loading {} bytes from file at {:x} into {:x}",
                              phdr.filesz, phdr.offset, phdr.paddr);
-                    for off in (0..phdr.filesz).step_by(BLOCK_READ_SZ) {
-                        let read_sz = if (phdr.filesz - off) as usize > BLOCK_READ_SZ {
-                            BLOCK_READ_SZ
-                        } else {
-                            (phdr.filesz - off) as usize
-                        };
-
-                        match file.read((phdr.offset + off) as isize, read_sz) {
-                            Ok(data) => {
-                                let data = data.as_ptr();
-                                let dest = phdr.paddr as *mut u8;
-                                unsafe {
-                                    //core::ptr::copy_nonoverlapping(data, dest, phdr.filesz as usize);
-                                }
-                            },
-                            Err(e) => return Err(e),
-                        }
-                    }
-                                        }
+                    /* Read directly into the segment's physical destination */
+                    let dest = unsafe {
+                        core::slice::from_raw_parts_mut(phdr.paddr as *mut u8, phdr.filesz as usize)
+                    };
+                    file.read_into(phdr.offset as isize, dest)?;
+                }
                 if phdr.memsz > phdr.filesz {
                     let dest = (phdr.paddr + phdr.filesz) as *mut u8;
                     println!("  Clearing {} bytes at {:x}",
@@ -170,6 +179,8 @@ impl ExecFmt for ExecFmtELF {
  * ELF definitions
  */
 const ELF_IDENT: u32 = 0x464c457f;
+/// Lowest address segments can be loaded at, above the loader and BIOS
+const ELF_LOAD_MIN_ADDR: u32 = 0x100000;
 
 #[derive(Clone, Copy)]
 #[repr(C, packed(1))]
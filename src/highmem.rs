@@ -0,0 +1,169 @@
+use core::fmt::Write;
+use core::ops::Range;
+use core::ptr;
+
+use crate::bios::{self, BiosCall};
+use crate::errors::ErrorCode;
+use crate::io::output;
+
+/// Extended memory, from 1 MiB up to the first hole. Files received whole,
+/// such as over a serial line, are too large for the heap and are staged at
+/// the top of it, leaving the bottom for the kernel, which is loaded last.
+struct HighMem {
+    free_start: usize, //< Lowest free address
+    free_end: usize,   //< End of free memory, where staged files begin
+    top: usize,        //< End of extended memory
+    streaming: bool,   //< A staging buffer holds all free memory
+}
+
+static mut HIGHMEM: HighMem = HighMem {
+    free_start: HIGHMEM_START,
+    free_end: HIGHMEM_START,
+    top: HIGHMEM_START,
+    streaming: false,
+};
+
+/// Find the size of extended memory
+pub fn init() {
+    let top = extended_memory_end() & !(HIGHMEM_ALIGN - 1);
+    unsafe {
+        HIGHMEM.free_end = top;
+        HIGHMEM.top = top;
+    }
+
+    println!("Extended memory: {} KiB", (top - HIGHMEM_START) / 1024);
+}
+
+/// Addresses holding staged files, which nothing else may be loaded over
+pub fn staged() -> Range<usize> {
+    unsafe { HIGHMEM.free_end..HIGHMEM.top }
+}
+
+/// Get the end of the extended memory adjoining 1 MiB
+fn extended_memory_end() -> usize {
+    /* INT 15h, AX = 0xE801: KiB between 1 and 16 MiB, and 64 KiB blocks
+     * above 16 MiB */
+    let mut bcall = BiosCall {
+        int_n: 0x15,
+        eax:   0xE801,
+        ..Default::default()
+    };
+    unsafe { bcall.call(); }
+
+    if (bcall.eflags & bios::EFLAGS_CF) == 0 {
+        let (mut low, mut high) = (bcall.eax & 0xFFFF, bcall.ebx & 0xFFFF);
+        if low == 0 && high == 0 {
+            /* Some BIOSes only report the configured memory in CX/DX */
+            (low, high) = (bcall.ecx & 0xFFFF, bcall.edx & 0xFFFF);
+        }
+
+        if low != 0 {
+            /* Memory above 16 MiB only adjoins ours without a hole below it */
+            return if low >= HIGHMEM_E801_LOW_MAX {
+                (high as usize).saturating_mul(0x10000).saturating_add(HIGHMEM_16M)
+            } else {
+                HIGHMEM_START + (low as usize * 1024)
+            };
+        }
+    }
+
+    /* INT 15h, AH = 0x88: KiB above 1 MiB, up to 64 MiB */
+    let mut bcall = BiosCall {
+        int_n: 0x15,
+        eax:   0x8800,
+        ..Default::default()
+    };
+    unsafe { bcall.call(); }
+
+    if (bcall.eflags & bios::EFLAGS_CF) != 0 {
+        println!("Could not get extended memory size");
+        return HIGHMEM_START;
+    }
+
+    HIGHMEM_START + ((bcall.eax & 0xFFFF) as usize * 1024)
+}
+
+/// Buffer in extended memory that a file of unknown size is received into.
+/// It spans all free memory until it is finished, so only one can exist at a
+/// time.
+pub struct StagingBuffer {
+    base: usize,
+    capacity: usize,
+    len: usize,
+}
+
+impl StagingBuffer {
+    /// Take all free extended memory
+    pub fn new() -> Result<StagingBuffer, ErrorCode> {
+        if unsafe { HIGHMEM.streaming } {
+            println!("Extended memory is already receiving a file");
+            return Err(ErrorCode::NoSpace);
+        }
+
+        unsafe {
+            HIGHMEM.streaming = true;
+
+            Ok(StagingBuffer {
+                base: HIGHMEM.free_start,
+                capacity: HIGHMEM.free_end - HIGHMEM.free_start,
+                len: 0,
+            })
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Largest file the buffer can hold
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Append data, failing if it doesn't fit
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> Result<(), ErrorCode> {
+        if data.len() > (self.capacity - self.len) {
+            return Err(ErrorCode::NoSpace);
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), (self.base + self.len) as *mut u8, data.len());
+        }
+        self.len += data.len();
+
+        Ok(())
+    }
+
+    /// Drop data past `len`
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Move the data to the top of free memory, where it is kept
+    pub fn finish(self) -> &'static [u8] {
+        unsafe {
+            /* Both ends of free memory stay aligned, so this can't fall
+             * below the start of the buffer */
+            let addr = (HIGHMEM.free_end - self.len) & !(HIGHMEM_ALIGN - 1);
+            ptr::copy(self.base as *const u8, addr as *mut u8, self.len);
+            HIGHMEM.free_end = addr;
+
+            core::slice::from_raw_parts(addr as *const u8, self.len)
+        }
+    }
+}
+
+impl Drop for StagingBuffer {
+    fn drop(&mut self) {
+        unsafe { HIGHMEM.streaming = false; }
+    }
+}
+
+const HIGHMEM_START: usize        = 0x100000;
+const HIGHMEM_16M: usize          = 0x1000000;
+const HIGHMEM_ALIGN: usize        = 4096;   //< Alignment of staged files
+const HIGHMEM_E801_LOW_MAX: u32   = 0x3C00; //< KiB between 1 and 16 MiB when there is no hole
@@ -5,6 +5,7 @@ use alloc::boxed::Box;
 
 pub mod pic;
 pub mod idt;
+pub mod pit;
 
 use crate::bios::EFLAGS_IF;
 use crate::io::output;
@@ -28,16 +29,16 @@ pub fn interrupts_disable() {
 
 #[inline(always)]
 pub fn interrupts_enabled() -> bool {
-    let mut eflags: u32;
+    let mut eflags: usize;
     unsafe {
         asm!(
-            "pushfd",
-            "pop eax",
-            out("eax") eflags
+            "pushf",
+            "pop {}",
+            out(reg) eflags
         );
     };
 
-    (eflags & EFLAGS_IF) != 0
+    (eflags as u32 & EFLAGS_IF) != 0
 }
 
 pub fn interrupt_enable(id: InterruptID) {
@@ -66,6 +67,7 @@ pub fn init() {
 
     idt::init();
     pic::remap();
+    pit::init();
 
     interrupts_enable();
 }
@@ -0,0 +1,101 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::intr::{interrupt_enable, interrupt_register, InterruptID};
+use crate::io::ioport::outb;
+
+/// Timer ticks seen by `millis`, not reset at midnight
+static TICKS: AtomicU32 = AtomicU32::new(0);
+/// BIOS tick count when `millis` was last called
+static LAST_BIOS_TICKS: AtomicU32 = AtomicU32::new(0);
+
+/// Keep the BIOS tick count running while in protected mode. The PIT is left
+/// at the rate programmed by the BIOS, which handles the timer itself during
+/// BIOS calls, so the BIOS count covers time spent in both modes.
+///
+/// The floppy motor is also turned off once the BIOS's timeout runs out, as
+/// INT 08h does, so it doesn't keep spinning after booting from a floppy.
+pub fn init() {
+    LAST_BIOS_TICKS.store(bios_ticks(), Ordering::Relaxed);
+
+    _ = interrupt_register(InterruptID::PIT, |_, _| {
+        let mut ticks = bios_ticks() + 1;
+        if ticks >= BIOS_TICKS_PER_DAY {
+            ticks = 0;
+            unsafe { core::ptr::write_volatile(BDA_MIDNIGHT_FLAG as *mut u8, 1); }
+        }
+        unsafe { core::ptr::write_volatile(BDA_TIMER_TICKS as *mut u32, ticks); }
+
+        floppy_motor_tick();
+    });
+    interrupt_enable(InterruptID::PIT);
+}
+
+/// Count down the floppy motor timeout, and turn the motors off when it expires
+fn floppy_motor_tick() {
+    unsafe {
+        let count = core::ptr::read_volatile(BDA_MOTOR_COUNT as *const u8);
+        if count == 0 {
+            return;
+        }
+
+        core::ptr::write_volatile(BDA_MOTOR_COUNT as *mut u8, count - 1);
+        if count == 1 {
+            let status = core::ptr::read_volatile(BDA_MOTOR_STATUS as *const u8);
+            core::ptr::write_volatile(BDA_MOTOR_STATUS as *mut u8, status & !BDA_MOTOR_STATUS_ON);
+            outb(FDC_DOR, FDC_DOR_MOTORS_OFF);
+        }
+    }
+}
+
+/// Milliseconds elapsed since `init`, with a resolution of one timer tick
+/// (~55 ms)
+pub fn millis() -> u32 {
+    let now  = bios_ticks();
+    let last = LAST_BIOS_TICKS.swap(now, Ordering::Relaxed);
+    let delta = if now >= last {
+        now - last
+    } else {
+        /* Count was reset at midnight */
+        (now + BIOS_TICKS_PER_DAY).saturating_sub(last)
+    };
+
+    let ticks = TICKS.fetch_add(delta, Ordering::Relaxed) + delta;
+    ((ticks as u64 * PIT_TICK_US) / 1000) as u32
+}
+
+/// Point in time after which an operation should give up
+#[derive(Clone, Copy)]
+pub struct Deadline {
+    end: u32, //< Value of `millis()` at which the deadline expires
+}
+
+impl Deadline {
+    /// Create a deadline the given number of milliseconds from now
+    pub fn after_ms(ms: u32) -> Deadline {
+        Deadline {
+            end: millis().wrapping_add(ms),
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        (millis().wrapping_sub(self.end) as i32) >= 0
+    }
+}
+
+fn bios_ticks() -> u32 {
+    unsafe { core::ptr::read_volatile(BDA_TIMER_TICKS as *const u32) }
+}
+
+/// Length of a tick at the default BIOS rate of 1193182 Hz / 65536
+const PIT_TICK_US: u64 = 54925;
+
+const BDA_TIMER_TICKS: usize    = 0x46C; //< Ticks since midnight
+const BDA_MIDNIGHT_FLAG: usize  = 0x470; //< Set when the tick count rolls over
+const BDA_MOTOR_STATUS: usize   = 0x43F; //< Floppy motors running, one bit per drive
+const BDA_MOTOR_COUNT: usize    = 0x440; //< Ticks until the floppy motors are turned off
+const BIOS_TICKS_PER_DAY: u32   = 0x1800B0;
+
+const BDA_MOTOR_STATUS_ON: u8   = 0x0F;
+
+const FDC_DOR: u16              = 0x3F2; //< Floppy controller digital output register
+const FDC_DOR_MOTORS_OFF: u8    = 0x0C;  //< Controller enabled, DMA and IRQ on, all motors off
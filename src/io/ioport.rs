@@ -22,3 +22,46 @@ pub fn inb(port: u16) -> u8 {
     value
 }
 
+#[inline(always)]
+pub fn inw(port: u16) -> u16 {
+    let mut value: u16;
+    unsafe {
+        asm!("in ax, dx",
+             in("dx") port,
+             out("ax") value
+        );
+    }
+    value
+}
+
+#[inline(always)]
+pub fn outw(port: u16, value: u16) {
+    unsafe {
+        asm!("out dx, ax",
+             in("dx") port,
+             in("ax") value
+        );
+    }
+}
+
+#[inline(always)]
+pub fn outl(port: u16, value: u32) {
+    unsafe {
+        asm!("out dx, eax",
+             in("dx") port,
+             in("eax") value
+        );
+    }
+}
+
+#[inline(always)]
+pub fn inl(port: u16) -> u32 {
+    let mut value: u32;
+    unsafe {
+        asm!("in eax, dx",
+             in("dx") port,
+             out("eax") value
+        );
+    }
+    value
+}
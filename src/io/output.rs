@@ -3,34 +3,118 @@ extern crate alloc;
 use core::fmt::Write;
 use core::mem;
 
+use alloc::rc::Rc;
 use alloc::slice;
+use core::cell::RefCell;
 
+use crate::io::serial::SerialPort;
 use crate::io::vga;
 
 pub trait IOOutput {
     fn write(&mut self, data: &[u8]);
 }
 
-pub static mut OUTPUT_VGA: vga::VGA = vga::VGA::new();
+/// Sends loader output to the screen, and to a serial console once one is
+/// attached. Output from before then is kept, so the console gets a complete
+/// log.
+///
+/// The console's port may also carry file transfers. While a transfer has it
+/// borrowed, output is held back as well, rather than being sent in the middle
+/// of the protocol.
+pub struct OutputMux {
+    vga: vga::VGA,
+    serial: Option<Rc<RefCell<SerialPort>>>,
+    history: [u8; OUTPUT_HISTORY_SIZE], //< Output not yet sent to a serial console
+    history_len: usize,
+}
+
+impl OutputMux {
+    pub const fn new() -> Self {
+        Self {
+            vga: vga::VGA::new(),
+            serial: None,
+            history: [0; OUTPUT_HISTORY_SIZE],
+            history_len: 0,
+        }
+    }
+
+    /// Mirror output to a serial port, starting with what was output so far
+    pub fn attach_serial(&mut self, port: Rc<RefCell<SerialPort>>) {
+        self.serial = Some(port);
+        /* Sends the history */
+        self.write(&[]);
+    }
+}
+
+impl IOOutput for OutputMux {
+    fn write(&mut self, data: &[u8]) {
+        self.vga.write(data);
+
+        match self.serial.as_ref().and_then(|port| port.try_borrow_mut().ok()) {
+            Some(mut port) => {
+                if self.history_len > 0 {
+                    write_crlf(&mut port, &self.history[..self.history_len]);
+                    if self.history_len == OUTPUT_HISTORY_SIZE {
+                        write_crlf(&mut port, b"[Some output lost]\n");
+                    }
+                    self.history_len = 0;
+                }
+
+                write_crlf(&mut port, data);
+            },
+            None => {
+                /* Once full, the start of the log is kept, as it has the
+                 * hardware found */
+                let len = data.len().min(OUTPUT_HISTORY_SIZE - self.history_len);
+                self.history[self.history_len..(self.history_len + len)].copy_from_slice(&data[..len]);
+                self.history_len += len;
+            }
+        }
+    }
+}
+
+impl Write for OutputMux {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        IOOutput::write(self, s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Write to a serial port, translating line endings for terminals
+fn write_crlf(port: &mut SerialPort, data: &[u8]) {
+    for &ch in data {
+        if ch == b'\n' {
+            port.write_u8(b'\r');
+        }
+        port.write_u8(ch);
+    }
+}
+
+pub static mut OUTPUT: OutputMux = OutputMux::new();
 
 pub fn init() {
     unsafe {
-        OUTPUT_VGA.init();
+        OUTPUT.vga.init();
+    }
+}
+
+/// Mirror all further output to a serial console
+pub fn attach_serial(port: Rc<RefCell<SerialPort>>) {
+    unsafe {
+        OUTPUT.attach_serial(port);
     }
 }
 
 #[allow(dead_code)]
 pub fn puts(line: &str) {
-    /* TODO: Support selecting output */
     unsafe {
-        OUTPUT_VGA.write(line.as_bytes());
+        OUTPUT.write(line.as_bytes());
     }
 }
 
 pub fn putchar(ch: u8) {
-    /* TODO: Support selecting output */
     unsafe {
-        OUTPUT_VGA.write(&[ch]);
+        OUTPUT.write(&[ch]);
     }
 }
 
@@ -49,10 +133,10 @@ pub fn hexdump(data: &[u8], off: usize, mut len: usize) {
             if cnt != 0 {
                 putchar(b'\n');
             }
-            unsafe { _ = write!(OUTPUT_VGA, "{:4x}: ", cnt); }
+            unsafe { _ = write!(OUTPUT, "{:4x}: ", cnt); }
         }
 
-        unsafe { _ = write!(OUTPUT_VGA, "{:2x} ", byte); }
+        unsafe { _ = write!(OUTPUT, "{:2x} ", byte); }
 
         cnt += 1;
 
@@ -81,10 +165,10 @@ pub fn hexdump_obj<T: Sized>(object: &T, off: usize, mut len: usize) {
             if cnt != 0 {
                 putchar(b'\n');
             }
-            unsafe { _ = write!(OUTPUT_VGA, "{:4x}: ", cnt); }
+            unsafe { _ = write!(OUTPUT, "{:4x}: ", cnt); }
         }
 
-        unsafe { _ = write!(OUTPUT_VGA, "{:2x} ", byte); }
+        unsafe { _ = write!(OUTPUT, "{:2x} ", byte); }
 
         cnt += 1;
 
@@ -96,15 +180,24 @@ pub fn hexdump_obj<T: Sized>(object: &T, off: usize, mut len: usize) {
     putchar(b'\n');
 }
 
+#[cfg(not(test))]
 #[macro_export]
 macro_rules! println {
     /* TODO: Make this safer and more portable */
     /* NOTE: Using core::fmt adds a lot of overhead, consider re-implementation */
     ($fmt:expr) => (
-        unsafe { _ = write!(output::OUTPUT_VGA, concat!($fmt, "\n")) }
+        unsafe { _ = write!(output::OUTPUT, concat!($fmt, "\n")) }
     );
     ($fmt:expr, $($arg:tt)*) => (
-        unsafe { _ =  write!(output::OUTPUT_VGA, concat!($fmt, "\n"), $($arg)*) }
+        unsafe { _ =  write!(output::OUTPUT, concat!($fmt, "\n"), $($arg)*) }
     );
 }
 
+/// Unit tests run on the host, which has no screen to write to
+#[cfg(test)]
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => (std::println!($($arg)*));
+}
+
+const OUTPUT_HISTORY_SIZE: usize = 8192;
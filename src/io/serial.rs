@@ -1,72 +1,289 @@
 #![allow(dead_code)]
 
+use alloc::rc::Rc;
 use core::alloc::Layout;
+use core::cell::RefCell;
 use core::mem;
-use core::ptr::NonNull;
+use core::ptr::{addr_of, addr_of_mut, NonNull};
 
 use super::ioport::{outb, inb};
 use crate::data::fifo::FIFO;
-use crate::intr::{interrupt_enable, interrupt_register, interrupts_enabled, InterruptID};
+use crate::errors::ErrorCode;
+use crate::intr::pit::Deadline;
+use crate::intr::{interrupt_enable, interrupt_register, interrupts_disable, interrupts_enable, interrupts_enabled, InterruptID};
 
 #[derive(Copy, Clone)]
 pub struct SerialConfig {
-    pub baud: u32,        //< Desired baud rate
-    pub rxfifo_sz: usize, //< Size of RX FIFO
-    pub txfifo_sz: usize, //< size of TX FIFO
-    pub use_rts: bool,    //< Enable RTS/CTS flow control
-    pub use_dtr: bool,    //< Enable DTR/DSR flow control
+    pub baud: u32,            //< Desired baud rate
+    pub data_bits: u8,        //< Bits per character, 5 to 8
+    pub parity: SerialParity,
+    pub stop_bits: u8,        //< 1 or 2, 2 meaning 1.5 with 5 data bits
+    pub rxfifo_sz: usize,     //< Size of RX FIFO
+    pub txfifo_sz: usize,     //< size of TX FIFO
+    pub use_rts: bool,        //< Enable RTS/CTS flow control
+    pub use_dtr: bool,        //< Enable DTR/DSR flow control
+}
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum SerialParity {
+    None,
+    Odd,
+    Even,
+    Mark,  //< Parity bit always 1
+    Space, //< Parity bit always 0
+}
+
+impl SerialParity {
+    /// Parse the letter used in settings such as `8N1`
+    pub fn from_letter(letter: char) -> Option<SerialParity> {
+        match letter.to_ascii_uppercase() {
+            'N' => Some(SerialParity::None),
+            'O' => Some(SerialParity::Odd),
+            'E' => Some(SerialParity::Even),
+            'M' => Some(SerialParity::Mark),
+            'S' => Some(SerialParity::Space),
+            _   => None,
+        }
+    }
+
+    pub fn letter(&self) -> char {
+        match self {
+            SerialParity::None  => 'N',
+            SerialParity::Odd   => 'O',
+            SerialParity::Even  => 'E',
+            SerialParity::Mark  => 'M',
+            SerialParity::Space => 'S',
+        }
+    }
+}
+
+/// UART chip, as far as can be told from its registers
+#[derive(PartialEq, Clone, Copy)]
+pub enum UartType {
+    U8250,   //< No scratch register
+    U16450,
+    U16550,  //< FIFO doesn't work reliably, and is left disabled
+    U16550A, //< 16 byte FIFOs
+    U16750,  //< 64 byte FIFOs
+}
+
+impl UartType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UartType::U8250   => "8250",
+            UartType::U16450  => "16450",
+            UartType::U16550  => "16550",
+            UartType::U16550A => "16550A",
+            UartType::U16750  => "16750",
+        }
+    }
+
+    /// Bytes that can be written each time the transmitter empties
+    fn tx_fifo_size(&self) -> usize {
+        match self {
+            UartType::U16550A => 16,
+            UartType::U16750  => 64,
+            _                 => 1,
+        }
+    }
+}
+
+/// Line errors and events seen on a port since it was opened
+#[derive(Clone, Copy, Default)]
+pub struct SerialStats {
+    pub overrun: u32,       //< Bytes lost because the UART wasn't read in time
+    pub fifo_overrun: u32,  //< Bytes lost because the RX FIFO was full
+    pub parity: u32,        //< Bytes received with a parity error
+    pub framing: u32,       //< Bytes received without a valid stop bit
+    pub breaks: u32,        //< Breaks received
+    pub modem_changes: u32, //< Changes in the modem status inputs
+}
+
+impl SerialStats {
+    /// Total of the errors that corrupt or lose received data
+    pub fn errors(&self) -> u32 {
+        self.overrun + self.fifo_overrun + self.parity + self.framing
+    }
+}
+
+impl core::fmt::Display for SerialStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "overrun: {}, FIFO overrun: {}, parity: {}, framing: {}, breaks: {}",
+               self.overrun, self.fifo_overrun, self.parity, self.framing, self.breaks)
+    }
 }
 
 /// Structure of data that the serial port interrupt has access to
 struct IntrData {
     port: u16,           //< Base IO port
     mcr_val: u8,         //< Current value of MCR register, for flow control
+    flow_mcr: u8,        //< MCR outputs dropped to stop the other end sending
+    flow_msr: u8,        //< MSR inputs the other end asserts when we may send
+    throttled: bool,     //< Flow control outputs are dropped
+    tx_burst: usize,     //< Bytes to write when the transmitter empties
     fifo_low: usize,     //< Number of FIFO elements under/over which to utilize flow control
     rxfifo: FIFO<u8>,
     txfifo: FIFO<u8>,
-    /* Using separate booleans for now, but may use bit flags in the future */
-    status_overrun: bool, //< HW or FIFO overrun has occured
-    status_framing: bool, //< UART reported a framing error
+    stats: SerialStats,
+    msr_val: u8,          //< Last value read from MSR
+    break_pending: bool,  //< A break was received, and not yet taken
 }
 
 pub struct SerialPort {
     cfg: SerialConfig,
+    base: SerialPortBase,
+    uart: UartType,
     port: u16,                //< Base IO port
     int_id: InterruptID,      //< Interrupt ID used by serial port
     idata: NonNull<IntrData>, //< Pointer to interrupt-accessible data
 }
 
-unsafe fn serial_int_handler(idata: *mut IntrData) {
-    /* NOTE: Currently does not support concurrent use of COM1/3 or COM2/4. */
+/// Interrupt data of each port in use, indexed by `SerialPortBase::index`.
+/// COM1 and COM3 share an IRQ, as do COM2 and COM4, so the handler of each IRQ
+/// services both ports on it.
+static mut SERIAL_PORTS: [Option<NonNull<IntrData>>; 4] = [None; 4];
+
+/// Ports opened so far, indexed by `SerialPortBase::index`. Each is shared by
+/// everything using it, such as the console and file transfers, as a second
+/// instance would take over the interrupts of the first.
+static mut OPEN_PORTS: [Option<Rc<RefCell<SerialPort>>>; 4] = [None, None, None, None];
+
+/// Service every port on an IRQ
+///
+/// # Arguments
+/// * first - Index of the lower numbered port on the IRQ
+unsafe fn serial_irq_handler(first: usize) {
+    /* The IRQ line won't produce another edge while either port has an
+     * interrupt pending, so go round until neither does */
+    loop {
+        let mut serviced = false;
+        for idx in [first, first + 2] {
+            if let Some(idata) = SERIAL_PORTS[idx] {
+                serviced |= serial_int_handler(idata.as_ptr());
+            }
+        }
+
+        if !serviced {
+            break;
+        }
+    }
+}
+
+/// Service the pending conditions of a port, returning whether there were any
+unsafe fn serial_int_handler(idata: *mut IntrData) -> bool {
     let port = (*idata).port;
-    let iir = inb(port + SERIAL_REG_IIR);
+    let mut serviced = false;
 
-    match (iir >> SERIALREG_IIR_INTID_POS) & SERIALREG_IIR_INTID_MSK {
-        SERIALREG_IIR_INTID_TXEMPTY => {
-            match (*idata).txfifo.dequeue() {
-                Some(byte) => outb(port + SERIAL_REG_DATA, byte),
-                None       => { /* TX buffer empty */ }
-            }
-        },
-        SERIALREG_IIR_INTID_RXAVAIL => {
-            let data = inb(port + SERIAL_REG_DATA);
-            if (*idata).rxfifo.enqueue(data).is_err() {
-                (*idata).status_overrun = true;
-            }
-        },
-        SERIALREG_IIR_INTID_RXLINESTATUS => {
-            let lsr = inb(port + SERIAL_REG_LSR);
-            if (lsr & (1 << SERIALREG_LSR_OE_POS)) != 0 {
-                (*idata).status_overrun = true;
-            }
-            if (lsr & (1 << SERIALREG_LSR_FE_POS)) != 0 {
-                (*idata).status_framing = true;
-            }
-        },
-        _ => { /* Unhandled interrupt, we shouldn't be here */ }
+    loop {
+        let iir = inb(port + SERIAL_REG_IIR);
+        if (iir & (1 << SERIALREG_IIR_NOTPENDING_POS)) != 0 {
+            break;
+        }
+        serviced = true;
+
+        match (iir >> SERIALREG_IIR_INTID_POS) & SERIALREG_IIR_INTID_MSK {
+            SERIALREG_IIR_INTID_TXEMPTY => serial_tx_next(idata),
+            SERIALREG_IIR_INTID_RXAVAIL => {
+                /* Also covers the character timeout, which reads as RX
+                 * available with the timeout bit set. Empty the hardware FIFO,
+                 * noting errors as reading LSR clears them. */
+                loop {
+                    let lsr = inb(port + SERIAL_REG_LSR);
+                    serial_line_status(idata, lsr);
+                    if (lsr & (1 << SERIALREG_LSR_DR_POS)) == 0 {
+                        break;
+                    }
+
+                    /* A break arrives as a zero byte, which isn't data */
+                    let data = inb(port + SERIAL_REG_DATA);
+                    if (lsr & (1 << SERIALREG_LSR_BI_POS)) == 0 && (*idata).rxfifo.enqueue(data).is_err() {
+                        (*idata).stats.fifo_overrun += 1;
+                    }
+                }
+
+                /* Ask the other end to stop before the FIFO fills */
+                if (*idata).flow_mcr != 0 && !(*idata).throttled && (*idata).rxfifo.free() < (*idata).fifo_low {
+                    (*idata).mcr_val &= !(*idata).flow_mcr;
+                    (*idata).throttled = true;
+                    outb(port + SERIAL_REG_MCR, (*idata).mcr_val);
+                }
+            },
+            SERIALREG_IIR_INTID_RXLINESTATUS => serial_line_status(idata, inb(port + SERIAL_REG_LSR)),
+            SERIALREG_IIR_INTID_MODEMSTATUS => {
+                /* Cleared by reading MSR. The other end may now be ready for
+                 * more. */
+                serial_modem_status(idata, inb(port + SERIAL_REG_MSR));
+                serial_tx_next(idata);
+            },
+            _ => { /* All IDs are covered by the mask */ }
+        }
+    }
+
+    serviced
+}
+
+/// Record errors and breaks reported in the line status register. These are
+/// cleared when LSR is read, so every read of it must be passed here.
+unsafe fn serial_line_status(idata: *mut IntrData, lsr: u8) {
+    let stats = &mut (*idata).stats;
+
+    if (lsr & (1 << SERIALREG_LSR_OE_POS)) != 0 {
+        stats.overrun += 1;
+    }
+    if (lsr & (1 << SERIALREG_LSR_BI_POS)) != 0 {
+        /* The line held low also looks like a framing error */
+        stats.breaks += 1;
+        (*idata).break_pending = true;
+    } else {
+        if (lsr & (1 << SERIALREG_LSR_PE_POS)) != 0 {
+            stats.parity += 1;
+        }
+        if (lsr & (1 << SERIALREG_LSR_FE_POS)) != 0 {
+            stats.framing += 1;
+        }
     }
 }
 
+/// Record a value read from the modem status register, whose change bits are
+/// cleared by the read
+unsafe fn serial_modem_status(idata: *mut IntrData, msr: u8) {
+    let changes = (1 << SERIALREG_MSR_DCTS_POS) | (1 << SERIALREG_MSR_DDSR_POS) |
+                  (1 << SERIALREG_MSR_TERI_POS) | (1 << SERIALREG_MSR_DDCD_POS);
+
+    (*idata).stats.modem_changes += (msr & changes).count_ones();
+    (*idata).msr_val = msr;
+}
+
+/// Write the next queued bytes, if the UART can take them and flow control
+/// allows. Once the transmitter has emptied, its whole FIFO can be filled.
+unsafe fn serial_tx_next(idata: *mut IntrData) {
+    let port = (*idata).port;
+
+    let lsr = inb(port + SERIAL_REG_LSR);
+    serial_line_status(idata, lsr);
+    if (lsr & (1 << SERIALREG_LSR_THRE_POS)) == 0 || !serial_clear_to_send(idata) {
+        return;
+    }
+    for _ in 0..(*idata).tx_burst {
+        match (*idata).txfifo.dequeue() {
+            Some(byte) => outb(port + SERIAL_REG_DATA, byte),
+            None       => break,
+        }
+    }
+}
+
+/// Check whether the other end is ready to receive
+unsafe fn serial_clear_to_send(idata: *mut IntrData) -> bool {
+    let flow = (*idata).flow_msr;
+    if flow == 0 {
+        return true;
+    }
+
+    let msr = inb((*idata).port + SERIAL_REG_MSR);
+    serial_modem_status(idata, msr);
+    (msr & flow) == flow
+}
+
 impl SerialPort {
     fn init(&mut self) {
         outb(self.port + SERIAL_REG_IER, 0);
@@ -81,14 +298,38 @@ impl SerialPort {
             outb(self.port + SERIAL_REG_DLM, (div >> 8) as u8);
         }
 
-        /* 8N1 */
-        outb(self.port + SERIAL_REG_LCR, SERIALREG_LCR_WORDLEN_8BIT << SERIALREG_LCR_WORDLEN_POS);
-
-        /* Disable FIFO to simplify SW FIFO */
-        outb(self.port + SERIAL_REG_FCR, (0 << SERIALREG_FCR_FIFOEN_POS)    |
+        /* Written while DLAB is still set, as the 16750 only accepts the
+         * 64 byte FIFO enable then. The RX trigger is part way up the FIFO,
+         * leaving room for bytes that arrive before the interrupt is serviced.
+         * The 16550's FIFO is broken, and earlier chips have none. */
+        let fcr = match self.uart {
+            UartType::U16550A => (1 << SERIALREG_FCR_FIFOEN_POS) |
+                                 (SERIALREG_FCR_TRIGLVL_8 << SERIALREG_FCR_TRIGLVL_POS),
+            UartType::U16750  => (1 << SERIALREG_FCR_FIFOEN_POS) |
+                                 (1 << SERIALREG_FCR_64BFIFO_POS) |
+                                 (SERIALREG_FCR_TRIGLVL_8 << SERIALREG_FCR_TRIGLVL_POS),
+            _                 => 0,
+        };
+        outb(self.port + SERIAL_REG_FCR, fcr |
                                          (1 << SERIALREG_FCR_RXFIFORST_POS) |
-                                         (1 << SERIALREG_FCR_TXFIFORST_POS) |
-                                         (0 << SERIALREG_FCR_TRIGLVL_POS));
+                                         (1 << SERIALREG_FCR_TXFIFORST_POS));
+
+        {
+            let parity = match self.cfg.parity {
+                SerialParity::None  => 0,
+                SerialParity::Odd   => 1 << SERIALREG_LCR_PARITY_POS,
+                SerialParity::Even  => (1 << SERIALREG_LCR_PARITY_POS) | (1 << SERIALREG_LCR_EVENPARITY_POS),
+                SerialParity::Mark  => (1 << SERIALREG_LCR_PARITY_POS) | (1 << SERIALREG_LCR_STICKPARITY_POS),
+                SerialParity::Space => (1 << SERIALREG_LCR_PARITY_POS) | (1 << SERIALREG_LCR_EVENPARITY_POS) |
+                                       (1 << SERIALREG_LCR_STICKPARITY_POS),
+            };
+            let wordlen = self.cfg.data_bits.clamp(5, 8) - 5;
+            let stopbits = if self.cfg.stop_bits > 1 { 1 } else { 0 };
+
+            outb(self.port + SERIAL_REG_LCR, ((wordlen & SERIALREG_LCR_WORDLEN_MSK) << SERIALREG_LCR_WORDLEN_POS) |
+                                             (stopbits << SERIALREG_LCR_STOPBITS_POS) |
+                                             parity);
+        }
 
         /* Clear outputs */
         outb(self.port + SERIAL_REG_MCR, (1 << SERIALREG_MCR_DTR_POS)  |
@@ -96,51 +337,148 @@ impl SerialPort {
                                          (1 << SERIALREG_MCR_OUT1_POS) |
                                          (1 << SERIALREG_MCR_OUT2_POS));
 
+        let idata = self.idata.as_ptr();
         unsafe {
-            let idata = self.idata.as_ptr();
-            let _ = interrupt_register(self.int_id, move |_id, _err| {
-                serial_int_handler(idata);
-            });
+            (*idata).mcr_val = inb(self.port + SERIAL_REG_MCR);
+            (*idata).msr_val = inb(self.port + SERIAL_REG_MSR);
         }
-        interrupt_enable(self.int_id);
-        outb(self.port + SERIAL_REG_IER, (1 << SERIALREG_IER_RXAVAIL_POS) |
-                                         (1 << SERIALREG_IER_TXEMPTY_POS) |
-                                         (1 << SERIALREG_IER_RXLINESTATUS_POS));
 
-        unsafe {
-            (*self.idata.as_ptr()).mcr_val = inb(self.port + SERIAL_REG_MCR);
+        {
+            let index = self.base.index();
+            let first = index % 2;
+
+            let enabled = interrupts_enabled();
+            interrupts_disable();
+            unsafe {
+                /* Only one instance of each port is created, see `open_port` */
+                SERIAL_PORTS[index] = Some(self.idata);
+                let _ = interrupt_register(self.int_id, move |_id, _err| {
+                    serial_irq_handler(first);
+                });
+            }
+            if enabled {
+                interrupts_enable();
+            }
         }
+        interrupt_enable(self.int_id);
+
+        /* Modem status changes also tell us when the other end is ready
+         * again */
+        outb(self.port + SERIAL_REG_IER, (1 << SERIALREG_IER_RXAVAIL_POS)      |
+                                         (1 << SERIALREG_IER_TXEMPTY_POS)      |
+                                         (1 << SERIALREG_IER_RXLINESTATUS_POS) |
+                                         (1 << SERIALREG_IER_MODEMSTATUS_POS));
     }
 
     fn tx_ready(&self) -> bool {
-        (inb(self.port + SERIAL_REG_LSR) & (1 << SERIALREG_LSR_THRE_POS)) != 0
+        let idata = self.idata.as_ptr();
+        let lsr = inb(self.port + SERIAL_REG_LSR);
+
+        unsafe {
+            serial_line_status(idata, lsr);
+            (lsr & (1 << SERIALREG_LSR_THRE_POS)) != 0 && serial_clear_to_send(idata)
+        }
     }
 
-    fn write_u8(&mut self, data: u8) {
+    pub fn write_u8(&mut self, data: u8) {
         let idata = self.idata.as_ptr();
 
         if interrupts_enabled() {
+            /* Wait for room rather than dropping output, the interrupt drains
+             * the FIFO. Output is dropped if the other end holds off flow
+             * control for too long. */
+            let deadline = Deadline::after_ms(SERIAL_TX_TIMEOUT_MS);
+            while unsafe { (*idata).txfifo.free() } == 0 {
+                if deadline.expired() {
+                    return;
+                }
+                self.kick_tx();
+            }
+
             unsafe {
-                /* TODO: Forward error */
                 let _ = (*idata).txfifo.enqueue(data);
             }
-
-            if self.tx_ready() {
-                /* Last interrupt occured while FIFO empty, or this is the first
-                 * write. */
-                match unsafe { (*idata).txfifo.dequeue() } {
-                    Some(val) => {
-                        outb(self.port + SERIAL_REG_DATA, val);
-                    },
-                    None => { /* This shouldn't happen */ }
+            self.kick_tx();
+        } else {
+            /* Nothing will drain the FIFO, write directly. The timer doesn't
+             * run either, so give up after a number of polls rather than hang
+             * on a stuck line. */
+            for _ in 0..SERIAL_TX_POLL_LIMIT {
+                if self.tx_ready() {
+                    outb(self.port + SERIAL_REG_DATA, data);
+                    break;
                 }
             }
         }
     }
 
-    fn read_u8(&self) -> Option<u8> {
+    /// Start transmitting if the UART is idle. The transmit interrupt only
+    /// fires when the holding register empties, so the first byte after the
+    /// FIFO has run dry has to be written here.
+    fn kick_tx(&mut self) {
+        /* Keep the interrupt from dequeueing at the same time */
+        interrupts_disable();
+        unsafe {
+            serial_tx_next(self.idata.as_ptr());
+        }
+        interrupts_enable();
+    }
+
+    pub fn uart(&self) -> UartType {
+        self.uart
+    }
+
+    /// Get the line errors and events seen since the port was opened
+    pub fn stats(&self) -> SerialStats {
+        let enabled = interrupts_enabled();
+        interrupts_disable();
+        let stats = unsafe { (*self.idata.as_ptr()).stats };
+        if enabled {
+            interrupts_enable();
+        }
+
+        stats
+    }
+
+    /// Check whether a break has been received since the last call. Senders
+    /// use a break to get attention, or the line may have been disconnected.
+    pub fn take_break(&self) -> bool {
+        let enabled = interrupts_enabled();
+        interrupts_disable();
+        let pending = unsafe { mem::replace(&mut (*self.idata.as_ptr()).break_pending, false) };
+        if enabled {
+            interrupts_enable();
+        }
+
+        pending
+    }
+
+    /// Last value read from the modem status register, whose upper bits give
+    /// the state of CTS, DSR, RI and DCD
+    pub fn modem_status(&self) -> u8 {
+        unsafe { (*self.idata.as_ptr()).msr_val }
+    }
+
+    /// Take the next received byte, if there is one
+    pub fn read_u8(&self) -> Option<u8> {
         let idata = self.idata.as_ptr();
-        unsafe { (*idata).txfifo.dequeue() }
+        let byte = unsafe { (*idata).rxfifo.dequeue() };
+
+        /* Let the other end send again once the FIFO has drained */
+        if unsafe { (*idata).throttled && (*idata).rxfifo.len() < (*idata).fifo_low } {
+            let enabled = interrupts_enabled();
+            interrupts_disable();
+            unsafe {
+                (*idata).mcr_val |= (*idata).flow_mcr;
+                (*idata).throttled = false;
+                outb(self.port + SERIAL_REG_MCR, (*idata).mcr_val);
+            }
+            if enabled {
+                interrupts_enable();
+            }
+        }
+
+        byte
     }
 }
 
@@ -161,7 +499,89 @@ impl core::fmt::Write for SerialPort {
     }
 }
 
-pub fn create_port(port: SerialPortBase, cfg: &SerialConfig) -> SerialPort {
+/// Check for a UART at a port, and identify it. Interrupts and FIFOs of the
+/// port are left disabled.
+pub fn probe(base: SerialPortBase) -> Option<UartType> {
+    let port = base as u16;
+
+    outb(port + SERIAL_REG_IER, 0);
+
+    /* In loopback mode the modem status inputs follow the modem control
+     * outputs, where nothing on the bus reads back as all ones */
+    {
+        let mcr = inb(port + SERIAL_REG_MCR);
+        let inputs = (1 << SERIALREG_MSR_CTS_POS) | (1 << SERIALREG_MSR_DSR_POS) |
+                     (1 << SERIALREG_MSR_RI_POS)  | (1 << SERIALREG_MSR_DCD_POS);
+
+        outb(port + SERIAL_REG_MCR, 1 << SERIALREG_MCR_LOOP_POS);
+        let idle = inb(port + SERIAL_REG_MSR) & inputs;
+        outb(port + SERIAL_REG_MCR, (1 << SERIALREG_MCR_LOOP_POS) |
+                                    (1 << SERIALREG_MCR_DTR_POS)  |
+                                    (1 << SERIALREG_MCR_RTS_POS)  |
+                                    (1 << SERIALREG_MCR_OUT1_POS) |
+                                    (1 << SERIALREG_MCR_OUT2_POS));
+        let active = inb(port + SERIAL_REG_MSR) & inputs;
+        outb(port + SERIAL_REG_MCR, mcr);
+
+        if idle != 0 || active != inputs {
+            return None;
+        }
+    }
+
+    /* The 8250 has no scratch register */
+    for val in [0x55, 0xAA] {
+        outb(port + SERIAL_REG_SCR, val);
+        if inb(port + SERIAL_REG_SCR) != val {
+            return Some(UartType::U8250);
+        }
+    }
+
+    /* Chips with FIFOs report them enabled in IIR. The 64 byte FIFO can only
+     * be enabled while DLAB is set. */
+    let lcr = inb(port + SERIAL_REG_LCR);
+    outb(port + SERIAL_REG_LCR, lcr | (1 << SERIALREG_LCR_DLAB_POS));
+    outb(port + SERIAL_REG_FCR, (1 << SERIALREG_FCR_FIFOEN_POS) | (1 << SERIALREG_FCR_64BFIFO_POS));
+    outb(port + SERIAL_REG_LCR, lcr);
+    let iir = inb(port + SERIAL_REG_IIR);
+    outb(port + SERIAL_REG_FCR, 0);
+
+    let uart = match (iir >> SERIALREG_IIR_FIFO_POS) & SERIALREG_IIR_FIFO_MSK {
+        SERIALREG_IIR_FIFO_ENABLED  => {
+            if (iir & (1 << SERIALREG_IIR_64BFIFO_POS)) != 0 {
+                UartType::U16750
+            } else {
+                UartType::U16550A
+            }
+        },
+        SERIALREG_IIR_FIFO_UNUSABLE => UartType::U16550,
+        _                           => UartType::U16450,
+    };
+
+    Some(uart)
+}
+
+/// Open a serial port, or get the instance already open, which keeps the
+/// settings it was first opened with. Fails with `NoDevice` if there is no
+/// UART there.
+pub fn open_port(port: SerialPortBase, cfg: &SerialConfig) -> Result<Rc<RefCell<SerialPort>>, ErrorCode> {
+    let index = port.index();
+    if let Some(open) = unsafe { &(*addr_of!(OPEN_PORTS))[index] } {
+        return Ok(Rc::clone(open));
+    }
+
+    let sport = Rc::new(RefCell::new(create_port(port, cfg)?));
+    unsafe {
+        (*addr_of_mut!(OPEN_PORTS))[index] = Some(Rc::clone(&sport));
+    }
+
+    Ok(sport)
+}
+
+/// Create the instance of a serial port, failing with `NoDevice` if there is
+/// no UART there
+fn create_port(port: SerialPortBase, cfg: &SerialConfig) -> Result<SerialPort, ErrorCode> {
+    let uart = probe(port).ok_or(ErrorCode::NoDevice)?;
+
     let layout = Layout::from_size_align(mem::size_of::<IntrData>(), mem::align_of::<IntrData>()).unwrap();
     let buffer = unsafe {
         alloc::alloc::alloc(layout)
@@ -169,6 +589,8 @@ pub fn create_port(port: SerialPortBase, cfg: &SerialConfig) -> SerialPort {
 
     let mut sport = SerialPort {
         cfg: *cfg,
+        base: port,
+        uart,
         port: port as u16,
         int_id:
             if (port == SerialPortBase::COM1) || (port == SerialPortBase::COM3) {
@@ -179,21 +601,31 @@ pub fn create_port(port: SerialPortBase, cfg: &SerialConfig) -> SerialPort {
         idata: NonNull::new(buffer as *mut IntrData).unwrap(),
     };
 
+    let flow_mcr = if cfg.use_rts { 1 << SERIALREG_MCR_RTS_POS } else { 0 } |
+                   if cfg.use_dtr { 1 << SERIALREG_MCR_DTR_POS } else { 0 };
+    let flow_msr = if cfg.use_rts { 1 << SERIALREG_MSR_CTS_POS } else { 0 } |
+                   if cfg.use_dtr { 1 << SERIALREG_MSR_DSR_POS } else { 0 };
+
     unsafe {
-        *sport.idata.as_ptr() = IntrData {
+        sport.idata.as_ptr().write(IntrData {
             port: sport.port,
             mcr_val: 0,
+            flow_mcr,
+            flow_msr,
+            throttled: false,
+            tx_burst: uart.tx_fifo_size(),
             fifo_low: cfg.rxfifo_sz / 4,
             rxfifo: FIFO::new(cfg.rxfifo_sz),
             txfifo: FIFO::new(cfg.txfifo_sz),
-            status_overrun: false,
-            status_framing: false,
-        };
+            stats: SerialStats::default(),
+            msr_val: 0,
+            break_pending: false,
+        });
     }
 
     sport.init();
 
-    sport
+    Ok(sport)
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -205,6 +637,41 @@ pub enum SerialPortBase {
     COM4 = 0x02e8,
 }
 
+impl SerialPortBase {
+    pub const ALL: [SerialPortBase; 4] = [SerialPortBase::COM1, SerialPortBase::COM2,
+                                          SerialPortBase::COM3, SerialPortBase::COM4];
+
+    /// Parse a port name such as `COM1`
+    pub fn from_name(name: &str) -> Option<SerialPortBase> {
+        match name.to_ascii_uppercase().as_str() {
+            "COM1" => Some(SerialPortBase::COM1),
+            "COM2" => Some(SerialPortBase::COM2),
+            "COM3" => Some(SerialPortBase::COM3),
+            "COM4" => Some(SerialPortBase::COM4),
+            _      => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SerialPortBase::COM1 => "COM1",
+            SerialPortBase::COM2 => "COM2",
+            SerialPortBase::COM3 => "COM3",
+            SerialPortBase::COM4 => "COM4",
+        }
+    }
+
+    /// Zero-based port number
+    pub fn index(&self) -> usize {
+        match self {
+            SerialPortBase::COM1 => 0,
+            SerialPortBase::COM2 => 1,
+            SerialPortBase::COM3 => 2,
+            SerialPortBase::COM4 => 3,
+        }
+    }
+}
+
 const SERIAL_REG_DATA: u16 = 0; //< Rx/Tx buffer
 const SERIAL_REG_IER: u16  = 1; //< Interrupt enable register
 const SERIAL_REG_IIR: u16  = 2; //< Interrupt identification
@@ -235,6 +702,11 @@ const SERIALREG_IIR_INTID_TXEMPTY: u8        =   1; //< TX holding register empt
 const SERIALREG_IIR_INTID_RXAVAIL: u8        =   2; //< RX data available
 const SERIALREG_IIR_INTID_RXLINESTATUS: u8   =   3; //< RX line status
 const SERIALREG_IIR_INTID_TIMEOUTPENDING: u8 =   6; //< Timeout interrupt pending
+const SERIALREG_IIR_64BFIFO_POS: u8          =   5; //< 64 byte FIFO enabled
+const SERIALREG_IIR_FIFO_POS: u8             =   6; //< FIFO status
+const SERIALREG_IIR_FIFO_MSK: u8             =0x03;
+const SERIALREG_IIR_FIFO_UNUSABLE: u8        =0x02; //< FIFO enabled, but not working
+const SERIALREG_IIR_FIFO_ENABLED: u8         =0x03; //< FIFO enabled
 
 const SERIALREG_FCR_FIFOEN_POS: u8           =   0; //< FIFO Enable
 const SERIALREG_FCR_RXFIFORST_POS: u8        =   1; //< RX FIFO Reset
@@ -243,6 +715,7 @@ const SERIALREG_FCR_DMAMODE_POS: u8          =   3; //< DMA MODE
 const SERIALREG_FCR_64BFIFO_POS: u8          =   5; //< 64 Byte FIFO
 const SERIALREG_FCR_TRIGLVL_POS: u8          =   6; //< RX FIFO trigger level
 const SERIALREG_FCR_TRIGLVL_MSK: u8          =0x03;
+const SERIALREG_FCR_TRIGLVL_8: u8            =0x02; //< 8 bytes, or 32 with the 64 byte FIFO
 
 const SERIALREG_LCR_WORDLEN_POS: u8          =   0; //< Word length
 const SERIALREG_LCR_WORDLEN_MSK: u8          =0x03;
@@ -280,3 +753,5 @@ const SERIALREG_MSR_DSR_POS: u8              =   5; //< DSR asserteed
 const SERIALREG_MSR_RI_POS: u8               =   6; //< RI asserted
 const SERIALREG_MSR_DCD_POS: u8              =   7; //< DCD asserted
 
+const SERIAL_TX_TIMEOUT_MS: u32    = 2000;    //< Time to wait for room in the TX FIFO
+const SERIAL_TX_POLL_LIMIT: usize  = 1000000; //< Polls of the transmitter with interrupts disabled
@@ -1,13 +1,16 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(panic_info_message)]
+/* Unit tests build for the host, where only the code they exercise is used */
+#![cfg_attr(test, allow(dead_code, unused_imports))]
 
 extern crate alloc;
 
 use alloc::rc::Rc;
+use alloc::vec;
 /* TODO: Write custom allocator */
 use linked_list_allocator::LockedHeap;
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 static HEAP: LockedHeap = LockedHeap::empty();
 
 use core::cell::RefCell;
@@ -24,15 +27,38 @@ mod storage;
 mod config;
 mod exec;
 mod errors;
+mod highmem;
+mod pci;
+mod net;
+mod xfer;
 
-use crate::config::Config;
+use crate::config::{Config, ConsoleConfig};
+use crate::errors::ErrorCode;
 use crate::exec::ExecFile;
+use crate::pci::PciBus;
+use crate::net::{dhcp, http::HttpFilesystem, iface::NetInterface, nic, pxe::{Pxe, PxeTftpFilesystem}, tftp::TftpFilesystem, Ipv4Addr, UdpTransport};
 use crate::io::output;
+use crate::io::serial::{self, SerialPortBase};
+use crate::xfer::{SerialXferFilesystem, XferPorts, XferProtocol, XFER_RXFIFO_SZ};
 use crate::storage::{
-    block::{bios::BiosBlockDevice, BlockDevice},
-    fs::{Filesystem, fat::FATFilesystem},
+    block::{ahci::AhciBlockDevice, ata::AtaBlockDevice, bios::{BiosBlockDevice, BiosDriveController}, cache::CachedBlockDevice, ram::RamBlockDevice, virtio::VirtioBlockDevice, BlockDevice},
+    fs,
+    mount::MountTable,
 };
 
+/// Number of device blocks kept in the boot device cache
+const BLOCK_CACHE_SIZE: usize = 32;
+
+/// Bytes read at a time when loading a file into extended memory
+const STAGE_CHUNK_SIZE: usize = 0x10000;
+
+/// Boot drive number passed by the PXE stage 1, as there is no boot disk
+const PXE_BOOT_DRIVE: u32 = 0x7F;
+
+/// Disk image embedded at build time, from the file named by `RLBOOT_RAMDISK`
+#[cfg(feature = "ramdisk")]
+static RAMDISK_IMAGE: &[u8] = include_bytes!(env!("RLBOOT_RAMDISK"));
+
 extern "C" {
     static mut __lboot_end: u8;
     static mut __lboot_bss_begin: u8;
@@ -48,6 +74,7 @@ fn init_data() {
     }
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn ruststart(boot_drive: u32) -> ! {
     init_data();
@@ -70,24 +97,39 @@ pub extern "C" fn ruststart(boot_drive: u32) -> ! {
     intr::init();
     println!("Interrupts enabled");
 
-    let blk = match BiosBlockDevice::new(boot_drive as u8) {
-        Ok(bbd) => Rc::new(RefCell::new(bbd)) as Rc<RefCell<dyn BlockDevice>>,
-        Err(e) => {
-            println!("Could not create block device: {}", e);
-            loop {}
-        },
-    };
-    println!("Block device created");
-    let fs = match FATFilesystem::init(&blk, 0) {
-        Ok(fs) => fs,
-        Err(e) => {
-            println!("Could not open filesystem: {}", e);
+    highmem::init();
+
+    let mut mounts = MountTable::new();
+    let (boot_cache, boot_controller) = if boot_drive == PXE_BOOT_DRIVE {
+        /* Loaded over the network, files come from the boot server */
+        let pxe = match Pxe::find() {
+            Some(pxe) => pxe,
+            None => {
+                println!("Could not find PXE API");
+                loop {}
+            }
+        };
+        if let Err(e) = mount_pxe_tftp(&mut mounts, pxe) {
+            println!("Could not mount TFTP filesystem: {}", e);
             loop {}
         }
+        (None, None)
+    } else {
+        let (cache, controller) = mount_boot_device(&mut mounts, boot_drive as u8);
+        if let Some(pxe) = Pxe::find() {
+            _ = mount_pxe_tftp(&mut mounts, pxe);
+        }
+        (Some(cache), Some(controller))
     };
-    println!("Filesystem created");
 
-    let cfg_file = match fs.borrow().find_file(None, "RLBOOT/RLBOOT.CFG") {
+    let pci = PciBus::scan();
+    pci.dump();
+    dump_serial_ports();
+
+    mount_native_devices(&mut mounts, &pci, boot_controller);
+    let xfer_ports = mount_serial_xfer(&mut mounts);
+
+    let cfg_file = match mounts.find_file("RLBOOT/RLBOOT.CFG") {
         Ok(file) => file,
         Err(e) => {
             println!("Could not find config file: {}", e);
@@ -106,8 +148,19 @@ pub extern "C" fn ruststart(boot_drive: u32) -> ! {
 
     println!("{}", config);
 
+    if let Some(console) = &config.console {
+        start_console(&config, console);
+    }
+    xfer_ports.borrow_mut().configure(&config);
+
+    load_ramdisks(&mut mounts, &config);
+
+    if config.network {
+        start_network(&mut mounts, &pci);
+    }
+
     println!("Loading kernel {}", config.kernel_path);
-    let exec_file = match fs.borrow().find_file(None, &config.kernel_path) {
+    let exec_file = match mounts.find_file(&config.kernel_path) {
         Ok(file) => file,
         Err(e) => {
             println!("Could not find kernel `{}`: {}", config.kernel_path, e);
@@ -128,26 +181,260 @@ pub extern "C" fn ruststart(boot_drive: u32) -> ! {
         loop {}
     }
 
+    if let Some(cache) = boot_cache {
+        let stats = cache.borrow().stats();
+        println!("Block cache: {} hits, {} misses, {} bypassed", stats.hits, stats.misses, stats.bypassed);
+    }
+
     if let Err(e) = exec.load(&config) {
         println!("Could not load kernel: {}", e);
         loop {}
     }
 
-    /*let mut port = serial::create_port(serial::SerialPortBase::COM1, &serial::SerialConfig {
-        baud: 115200,
-        rxfifo_sz: 32,
-        txfifo_sz: 32,
-        use_rts: false,
-        use_dtr: false,
-    });
+    loop {}
+}
 
-    let _ = write!(port, "This is a test!\n");*/
+/// Mount the filesystem on the BIOS boot drive, which becomes the default mount.
+/// Returns the drive's cache and the controller it is attached to.
+fn mount_boot_device(mounts: &mut MountTable, boot_drive: u8)
+        -> (Rc<RefCell<CachedBlockDevice>>, BiosDriveController) {
+    let (bios_blk, controller) = match BiosBlockDevice::new(boot_drive) {
+        Ok(bbd) => {
+            let controller = bbd.controller();
+            (Rc::new(RefCell::new(bbd)) as Rc<RefCell<dyn BlockDevice>>, controller)
+        },
+        Err(e) => {
+            println!("Could not create block device: {}", e);
+            loop {}
+        },
+    };
+    let cache = Rc::new(RefCell::new(CachedBlockDevice::new(bios_blk, BLOCK_CACHE_SIZE)));
+    let blk = Rc::clone(&cache) as Rc<RefCell<dyn BlockDevice>>;
+    println!("Block device created");
+    let fs = match fs::probe(&blk, 0) {
+        Ok(fs) => fs,
+        Err(e) => {
+            println!("Could not open filesystem: {}", e);
+            loop {}
+        }
+    };
 
-    loop {}
+    let dev_name = BiosBlockDevice::drive_name(boot_drive);
+    if let Err(e) = mounts.mount(&dev_name, fs) {
+        println!("Could not mount filesystem: {}", e);
+        loop {}
+    }
+    println!("Filesystem mounted as {}", dev_name);
+
+    (cache, controller)
+}
+
+/// Mount files served by the PXE boot server as `tftp`, using the PXE ROM's
+/// TFTP interface
+fn mount_pxe_tftp(mounts: &mut MountTable, pxe: Pxe) -> Result<(), ErrorCode> {
+    mounts.mount("tftp", PxeTftpFilesystem::new(pxe)?)?;
+    println!("TFTP filesystem mounted as tftp");
+
+    Ok(())
+}
+
+/// Mount files served by a TFTP server as `tftp`
+fn mount_tftp(mounts: &mut MountTable, transport: Rc<RefCell<dyn UdpTransport>>,
+              server: Ipv4Addr) -> Result<(), ErrorCode> {
+    mounts.mount("tftp", TftpFilesystem::new(transport, server))?;
+    println!("TFTP filesystem mounted as tftp, server {}", server);
+
+    Ok(())
+}
+
+/// Wrap a block device in a cache
+fn cached(dev: Rc<RefCell<dyn BlockDevice>>) -> Rc<RefCell<dyn BlockDevice>> {
+    Rc::new(RefCell::new(CachedBlockDevice::new(dev, BLOCK_CACHE_SIZE)))
+}
+
+/// Mount the filesystem on a block device, if one is found
+fn mount_device(mounts: &mut MountTable, name: &str, dev: Rc<RefCell<dyn BlockDevice>>) {
+    match fs::probe(&dev, 0) {
+        Ok(fs) => match mounts.mount(name, fs) {
+            Ok(()) => println!("Filesystem mounted as {}", name),
+            Err(e) => println!("Could not mount {}: {}", name, e),
+        },
+        Err(_) => println!("No filesystem found on {}", name),
+    }
+}
+
+/// Mount filesystems on disks that are accessed directly rather than through
+/// BIOS disk services
+///
+/// # Arguments
+/// * mounts - Mount table to add filesystems to
+/// * pci - Functions found on the PCI bus
+/// * boot_controller - Controller of the BIOS boot drive, if booted from one
+fn mount_native_devices(mounts: &mut MountTable, pci: &PciBus,
+                        boot_controller: Option<BiosDriveController>) {
+    /* Native drivers reset and reprogram their controller, which pulls the
+     * boot drive out from under INT 13h. Leave the boot drive's controller and
+     * IDE channel to the BIOS, or all disk controllers if the BIOS didn't say
+     * which. */
+    let (probe_native, exclude, exclude_channel) = match boot_controller {
+        Some(BiosDriveController::Pci(addr, channel)) => (true, Some(addr), channel),
+        Some(BiosDriveController::Isa(channel)) => (true, None, channel),
+        Some(BiosDriveController::Unknown) => {
+            println!("Boot drive controller unknown, not probing native disks");
+            (false, None, None)
+        },
+        _ => (true, None, None),
+    };
+
+    #[cfg(feature = "ramdisk")]
+    mount_device(mounts, &RamBlockDevice::name(0),
+                 Rc::new(RefCell::new(RamBlockDevice::from_static(RAMDISK_IMAGE))));
+
+    if probe_native {
+        for (name, dev) in AtaBlockDevice::probe_all(exclude_channel) {
+            mount_device(mounts, &name, cached(Rc::new(RefCell::new(dev))));
+        }
+
+        for (name, dev) in VirtioBlockDevice::probe_all(pci, exclude) {
+            mount_device(mounts, &name, cached(Rc::new(RefCell::new(dev))));
+        }
+
+        for (name, dev) in AhciBlockDevice::probe_all(pci, exclude) {
+            mount_device(mounts, &name, cached(Rc::new(RefCell::new(dev))));
+        }
+    }
+}
+
+/// List the serial ports found
+fn dump_serial_ports() {
+    for base in SerialPortBase::ALL {
+        if let Some(uart) = serial::probe(base) {
+            println!("{}: {} UART", base.name(), uart.name());
+        }
+    }
+}
+
+/// Mirror output to a serial console, with the line settings the config
+/// gives the port
+fn start_console(config: &Config, console: &ConsoleConfig) {
+    let mut settings = config.serial_port(console.port);
+    if let Some(baud) = console.baud {
+        settings.baud = baud;
+    }
+
+    /* Transfers may share the port, so it receives into their FIFO size */
+    match serial::open_port(console.port, &settings.serial_config(XFER_RXFIFO_SZ, 1024)) {
+        Ok(port) => {
+            output::attach_serial(port);
+            println!("Serial console on {}", settings);
+        },
+        Err(e) => println!("Could not open serial console {}: {}", console.port.name(), e),
+    }
+}
+
+/// Mount filesystems that receive files over serial ports, e.g. `xmodem`.
+/// The ports are returned so they can be configured once the config is
+/// loaded.
+fn mount_serial_xfer(mounts: &mut MountTable) -> Rc<RefCell<XferPorts>> {
+    let ports = Rc::new(RefCell::new(XferPorts::default()));
+
+    for protocol in [XferProtocol::Xmodem, XferProtocol::Ymodem, XferProtocol::Zmodem] {
+        let fs = SerialXferFilesystem::new(protocol, Rc::clone(&ports));
+        if let Err(e) = mounts.mount(protocol.name(), Rc::new(RefCell::new(fs))) {
+            println!("Could not mount {}: {}", protocol.name(), e);
+        }
+    }
+
+    ports
+}
+
+/// Configure a native network interface with DHCP, mount the boot server it
+/// names as `tftp`, and mount `http` for fetching files from web servers.
+///
+/// On PXE boots the ROM keeps driving the NIC for `tftp`, and HTTP needs a
+/// native driver, so `http` isn't available.
+fn start_network(mounts: &mut MountTable, pci: &PciBus) {
+    /* A native driver would reset the NIC out from under the PXE ROM */
+    if mounts.get("tftp").is_some() {
+        println!("Network already configured by PXE, http is not available");
+        return;
+    }
+
+    let dev = match nic::probe(pci) {
+        Some(dev) => dev,
+        None => {
+            println!("No supported network interface found");
+            return;
+        }
+    };
+
+    let mut iface = NetInterface::new(dev);
+    println!("Network interface {}, waiting for DHCP", iface.mac());
+
+    let lease = match dhcp::configure(&mut iface) {
+        Ok(lease) => lease,
+        Err(e) => {
+            println!("DHCP failed: {}", e);
+            return;
+        }
+    };
+    println!("{}", lease);
+
+    let iface = Rc::new(RefCell::new(iface));
+    if let Err(e) = mount_tftp(mounts, iface.clone(), lease.next_server) {
+        println!("Could not mount TFTP filesystem: {}", e);
+    }
+
+    match mounts.mount("http", HttpFilesystem::new(iface)) {
+        Ok(()) => println!("HTTP filesystem mounted as http"),
+        Err(e) => println!("Could not mount HTTP filesystem: {}", e),
+    }
+}
+
+/// Load disk images named in the config into memory and mount them
+fn load_ramdisks(mounts: &mut MountTable, config: &Config) {
+    /* ram0 is taken by the embedded image, if there is one */
+    let first = if cfg!(feature = "ramdisk") { 1 } else { 0 };
+
+    for (idx, path) in config.ramdisks.iter().enumerate() {
+        let image = match mounts.find_file(path).and_then(|file| stage_file(file.as_ref())) {
+            Ok(image) => image,
+            Err(e) => {
+                println!("Could not load RAM disk `{}`: {}", path, e);
+                continue;
+            }
+        };
+
+        mount_device(mounts, &RamBlockDevice::name(first + idx),
+                     Rc::new(RefCell::new(RamBlockDevice::from_static(image))));
+    }
+}
+
+/// Read a whole file into extended memory, where it is kept
+///
+/// # Arguments
+/// * file - File to read
+fn stage_file(file: &dyn fs::File) -> Result<&'static [u8], ErrorCode> {
+    let mut data = highmem::StagingBuffer::new()?;
+    let size = file.get_size();
+    if size > data.capacity() {
+        return Err(ErrorCode::NoSpace);
+    }
+
+    /* Bounce through the heap, which only has room for part of the file */
+    let mut chunk = vec![0u8; STAGE_CHUNK_SIZE.min(size)];
+    while data.len() < size {
+        let len = chunk.len().min(size - data.len());
+        file.read_into(data.len() as isize, &mut chunk[..len])?;
+        data.extend_from_slice(&chunk[..len])?;
+    }
+
+    Ok(data.finish())
 }
 
 #[allow(unused_variables)]
 #[inline(never)]
+#[cfg(not(test))]
 #[panic_handler]
 fn cust_panic(info: &PanicInfo) -> ! {
     #[cfg(feature = "verbose_panic")]
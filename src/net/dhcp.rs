@@ -0,0 +1,273 @@
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::mem;
+
+use crate::errors::ErrorCode;
+use crate::intr::pit::{self, Deadline};
+use crate::io::output;
+use crate::net::iface::{as_bytes, read_struct, NetInterface};
+use crate::net::{Ipv4Addr, UdpTransport};
+
+/// Configuration received from a DHCP server
+pub struct DhcpLease {
+    pub addr: Ipv4Addr,     //< Address assigned to this interface
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,  //< Default router, unspecified if none was given
+    pub dhcp_server: Ipv4Addr,
+    pub next_server: Ipv4Addr,  //< Server to load boot files from
+    pub bootfile: String,       //< Boot file name, empty if none was given
+}
+
+impl fmt::Display for DhcpLease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DhcpLease {{ addr: {}, netmask: {}, gateway: {}, server: {}, next-server: {}, bootfile: {} }}",
+               self.addr, self.netmask, self.gateway, self.dhcp_server, self.next_server, self.bootfile)
+    }
+}
+
+/// Obtain an address with DHCP, and configure the interface with it
+pub fn configure(iface: &mut NetInterface) -> Result<DhcpLease, ErrorCode> {
+    let mac = iface.mac();
+    let xid = u32::from_be_bytes([mac.0[2], mac.0[3], mac.0[4], mac.0[5]]) ^ pit::millis();
+
+    for attempt in 0..DHCP_RETRIES {
+        let timeout = DHCP_TIMEOUT_MS << attempt.min(2);
+
+        send(iface, xid, DHCP_DISCOVER, None)?;
+        let offer = match wait_reply(iface, xid, &[DHCP_OFFER], timeout) {
+            Ok(offer) => offer,
+            Err(ErrorCode::Timeout) => continue,
+            Err(e) => return Err(e),
+        };
+
+        send(iface, xid, DHCP_REQUEST, Some(&offer))?;
+        let ack = match wait_reply(iface, xid, &[DHCP_ACK, DHCP_NAK], timeout) {
+            Ok(ack) => ack,
+            Err(ErrorCode::Timeout) => continue,
+            Err(e) => return Err(e),
+        };
+        if ack.msg_type == DHCP_NAK {
+            println!("DHCP server {} declined request", offer.server_id);
+            continue;
+        }
+
+        let lease = ack.into_lease();
+        iface.addr    = lease.addr;
+        iface.netmask = lease.netmask;
+        iface.gateway = lease.gateway;
+
+        return Ok(lease);
+    }
+
+    Err(ErrorCode::Timeout)
+}
+
+/// Parsed DHCP reply
+struct DhcpReply {
+    msg_type: u8,
+    yiaddr: Ipv4Addr,
+    siaddr: Ipv4Addr,
+    server_id: Ipv4Addr,
+    netmask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    tftp_server: Option<String>,
+    bootfile: Option<String>,
+}
+
+impl DhcpReply {
+    fn parse(data: &[u8], xid: u32) -> Option<DhcpReply> {
+        let hdr: BootpHeader = read_struct(data)?;
+        if hdr.op != BOOTP_REPLY || u32::from_be(hdr.xid) != xid || u32::from_be(hdr.magic) != DHCP_MAGIC {
+            return None;
+        }
+
+        let mut reply = DhcpReply {
+            msg_type: 0,
+            yiaddr: Ipv4Addr(hdr.yiaddr),
+            siaddr: Ipv4Addr(hdr.siaddr),
+            server_id: Ipv4Addr::UNSPECIFIED,
+            netmask: None,
+            router: None,
+            tftp_server: None,
+            bootfile: None,
+        };
+        let mut overload = 0;
+
+        let mut opts = &data[mem::size_of::<BootpHeader>()..];
+        while let Some(&code) = opts.first() {
+            match code {
+                DHCP_OPT_PAD => {
+                    opts = &opts[1..];
+                    continue;
+                },
+                DHCP_OPT_END => break,
+                _            => {}
+            }
+
+            let len = *opts.get(1)? as usize;
+            let val = opts.get(2..(2 + len))?;
+            match code {
+                DHCP_OPT_MSG_TYPE if len == 1    => reply.msg_type = val[0],
+                DHCP_OPT_SERVER_ID if len == 4   => reply.server_id = Ipv4Addr::from_slice(val),
+                DHCP_OPT_SUBNET_MASK if len == 4 => reply.netmask = Some(Ipv4Addr::from_slice(val)),
+                DHCP_OPT_ROUTER if len >= 4      => reply.router = Some(Ipv4Addr::from_slice(val)),
+                DHCP_OPT_TFTP_SERVER             => reply.tftp_server = Some(c_string(val)),
+                DHCP_OPT_BOOTFILE                => reply.bootfile = Some(c_string(val)),
+                DHCP_OPT_OVERLOAD if len == 1    => overload = val[0],
+                _                                => {}
+            }
+            opts = &opts[(2 + len)..];
+        }
+
+        /* The file field holds options instead if it's overloaded */
+        if reply.bootfile.is_none() && (overload & DHCP_OVERLOAD_FILE) == 0 {
+            let file = c_string(&hdr.file);
+            if !file.is_empty() {
+                reply.bootfile = Some(file);
+            }
+        }
+
+        Some(reply)
+    }
+
+    fn into_lease(self) -> DhcpLease {
+        /* siaddr is the standard way to name the boot server, some servers
+         * only send the TFTP server name option */
+        let next_server = if !self.siaddr.is_unspecified() {
+            self.siaddr
+        } else if let Some(addr) = self.tftp_server.as_deref().and_then(Ipv4Addr::parse) {
+            addr
+        } else {
+            self.server_id
+        };
+
+        DhcpLease {
+            addr: self.yiaddr,
+            netmask: self.netmask.unwrap_or(Ipv4Addr([255, 255, 255, 0])),
+            gateway: self.router.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            dhcp_server: self.server_id,
+            next_server,
+            bootfile: self.bootfile.unwrap_or_default(),
+        }
+    }
+}
+
+/// Broadcast a DISCOVER, or a REQUEST for an offered address
+fn send(iface: &mut NetInterface, xid: u32, msg_type: u8, offer: Option<&DhcpReply>) -> Result<(), ErrorCode> {
+    let mut chaddr = [0u8; 16];
+    chaddr[..6].copy_from_slice(&iface.mac().0);
+
+    let hdr = BootpHeader {
+        op: BOOTP_REQUEST,
+        htype: BOOTP_HTYPE_ETHERNET,
+        hlen: 6,
+        hops: 0,
+        xid: xid.to_be(),
+        secs: 0,
+        flags: BOOTP_FLAG_BROADCAST.to_be(),
+        ciaddr: [0; 4],
+        yiaddr: [0; 4],
+        siaddr: [0; 4],
+        giaddr: [0; 4],
+        chaddr,
+        sname: [0; 64],
+        file: [0; 128],
+        magic: DHCP_MAGIC.to_be(),
+    };
+
+    let mut packet = Vec::with_capacity(DHCP_MIN_PACKET);
+    packet.extend_from_slice(as_bytes(&hdr));
+    packet.extend_from_slice(&[DHCP_OPT_MSG_TYPE, 1, msg_type]);
+    if let Some(offer) = offer {
+        packet.extend_from_slice(&[DHCP_OPT_REQUESTED_ADDR, 4]);
+        packet.extend_from_slice(&offer.yiaddr.0);
+        packet.extend_from_slice(&[DHCP_OPT_SERVER_ID, 4]);
+        packet.extend_from_slice(&offer.server_id.0);
+    }
+    packet.extend_from_slice(&[DHCP_OPT_PARAM_LIST, 4,
+                               DHCP_OPT_SUBNET_MASK, DHCP_OPT_ROUTER, DHCP_OPT_TFTP_SERVER, DHCP_OPT_BOOTFILE]);
+    packet.push(DHCP_OPT_END);
+    /* Some servers ignore requests shorter than a BOOTP packet */
+    packet.resize(packet.len().max(DHCP_MIN_PACKET), 0);
+
+    iface.send_udp(Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, &packet)
+}
+
+/// Wait for a reply of one of the given message types
+fn wait_reply(iface: &mut NetInterface, xid: u32, types: &[u8], timeout_ms: u32) -> Result<DhcpReply, ErrorCode> {
+    let deadline = Deadline::after_ms(timeout_ms);
+
+    loop {
+        let dgram = iface.recv_udp(DHCP_CLIENT_PORT, deadline)?;
+        if let Some(reply) = DhcpReply::parse(&dgram.data, xid) {
+            if types.contains(&reply.msg_type) {
+                return Ok(reply);
+            }
+        }
+    }
+}
+
+/// Convert a possibly NUL-terminated field to a string
+fn c_string(data: &[u8]) -> String {
+    let len = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..len]).into_owned()
+}
+
+/// BOOTP message, followed by the DHCP magic cookie. Multi-byte fields are in
+/// network byte order.
+#[repr(C, packed(1))]
+struct BootpHeader {
+    op: u8,             //< BOOTP_REQUEST or BOOTP_REPLY
+    htype: u8,          //< Hardware address type
+    hlen: u8,           //< Hardware address length
+    hops: u8,
+    xid: u32,           //< Transaction ID
+    secs: u16,
+    flags: u16,
+    ciaddr: [u8; 4],    //< Client address, if already known
+    yiaddr: [u8; 4],    //< Address assigned to client
+    siaddr: [u8; 4],    //< Next server to use in bootstrap
+    giaddr: [u8; 4],    //< Relay agent address
+    chaddr: [u8; 16],   //< Client hardware address
+    sname: [u8; 64],    //< Server host name
+    file: [u8; 128],    //< Boot file name
+    magic: u32,         //< DHCP_MAGIC
+}
+const _BOOTP_HEADER_SZ_TEST: [u8; 240] = [0; mem::size_of::<BootpHeader>()];
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+
+const DHCP_RETRIES: u32    = 4;
+const DHCP_TIMEOUT_MS: u32 = 2000; //< Initial reply timeout, doubled on retries
+const DHCP_MIN_PACKET: usize = 300;
+
+const BOOTP_REQUEST: u8         = 1;
+const BOOTP_REPLY: u8           = 2;
+const BOOTP_HTYPE_ETHERNET: u8  = 1;
+const BOOTP_FLAG_BROADCAST: u16 = 1 << 15; //< Ask for replies to be broadcast
+
+const DHCP_MAGIC: u32 = 0x63825363;
+
+const DHCP_OPT_PAD: u8            = 0;
+const DHCP_OPT_SUBNET_MASK: u8    = 1;
+const DHCP_OPT_ROUTER: u8         = 3;
+const DHCP_OPT_REQUESTED_ADDR: u8 = 50;
+const DHCP_OPT_OVERLOAD: u8       = 52;
+const DHCP_OPT_MSG_TYPE: u8       = 53;
+const DHCP_OPT_SERVER_ID: u8      = 54;
+const DHCP_OPT_PARAM_LIST: u8     = 55;
+const DHCP_OPT_TFTP_SERVER: u8    = 66;
+const DHCP_OPT_BOOTFILE: u8       = 67;
+const DHCP_OPT_END: u8            = 255;
+
+const DHCP_OVERLOAD_FILE: u8 = 1 << 0; //< `file` field holds options
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8    = 2;
+const DHCP_REQUEST: u8  = 3;
+const DHCP_ACK: u8      = 5;
+const DHCP_NAK: u8      = 6;
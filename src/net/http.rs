@@ -0,0 +1,352 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::{Rc, Weak};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use crate::errors::ErrorCode;
+use crate::io::output;
+use crate::net::iface::NetInterface;
+use crate::net::tcp::TcpStream;
+use crate::net::Ipv4Addr;
+use crate::storage::fs::{File, FileAttribute, Filesystem};
+
+/// Files fetched from HTTP servers with HTTP/1.0 GET requests.
+///
+/// Paths have the form `//<IPv4 address>[:<port>]/<path>`, as in
+/// `http://10.0.2.2:8000/kernel`. Host names aren't supported, as there is no
+/// DNS resolver.
+///
+/// As with TFTP, one download is kept open and reads continue from where the
+/// previous one left off. Reading earlier data, or another file, restarts it.
+///
+/// Only native network drivers are supported, so it isn't mounted when the
+/// PXE ROM is driving the NIC.
+pub struct HttpFilesystem {
+    iface: Rc<RefCell<NetInterface>>,
+
+    rootdir: HttpFile,
+
+    session: RefCell<Option<HttpSession>>,
+
+    rc: Weak<RefCell<Self>>,
+}
+
+/// Download in progress
+struct HttpSession {
+    url: HttpUrl,
+    stream: TcpStream,
+    length: Option<usize>, //< Content-Length, if the server sent one
+    pos: usize,            //< Offset into body of next byte to be received
+    reported: usize,       //< Progress last reported, in tenths of the file
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct HttpUrl {
+    server: Ipv4Addr,
+    port: u16,
+    path: String, //< Path on server, starting with '/'
+}
+
+impl Filesystem for HttpFilesystem {
+    fn get_root(&self) -> &dyn File {
+        &self.rootdir
+    }
+
+    fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        /* HTTP has no directories to be relative to */
+        let _ = start_dir;
+
+        let url = HttpUrl::parse(path)?;
+
+        let mut iface = self.iface.borrow_mut();
+        let mut session = self.session.borrow_mut();
+        if let Some(old) = session.take() {
+            old.stream.close(&mut iface);
+        }
+        let new = session.insert(HttpSession::open(&mut iface, url.clone())?);
+
+        let size = match new.length {
+            Some(size) => size,
+            None       => {
+                /* No Content-Length, count the whole body */
+                let mut buf = vec![0u8; HTTP_SKIP_BUF_SIZE];
+                while new.recv(&mut iface, &mut buf)? != 0 {}
+                new.pos
+            }
+        };
+
+        Ok(Box::new(HttpFile {
+            url,
+            size,
+            attr: FileAttribute::File as u32,
+            fs: self.rc.clone(),
+        }))
+    }
+}
+
+impl HttpFilesystem {
+    /// Create a filesystem for files on HTTP servers
+    ///
+    /// # Arguments
+    /// * iface - Configured interface to reach servers through
+    pub fn new(iface: Rc<RefCell<NetInterface>>) -> Rc<RefCell<Self>> {
+        Rc::new_cyclic(|me| {
+            RefCell::new(HttpFilesystem {
+                iface,
+                rootdir: HttpFile {
+                    url: HttpUrl {
+                        server: Ipv4Addr::UNSPECIFIED,
+                        port: HTTP_PORT,
+                        path: "/".to_string(),
+                    },
+                    size: 0,
+                    attr: FileAttribute::Directory as u32,
+                    fs: me.clone(),
+                },
+                session: RefCell::new(None),
+                rc: me.clone(),
+            })
+        })
+    }
+
+    /// Read from a file, restarting the download if data before the current
+    /// position is requested
+    fn read(&self, url: &HttpUrl, offset: usize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let mut iface = self.iface.borrow_mut();
+        let mut session = self.session.borrow_mut();
+
+        let reopen = match &*session {
+            Some(cur) => cur.url != *url || offset < cur.pos,
+            None      => true
+        };
+        if reopen {
+            if let Some(old) = session.take() {
+                old.stream.close(&mut iface);
+            }
+            *session = Some(HttpSession::open(&mut iface, url.clone())?);
+        }
+        let session = session.as_mut().unwrap();
+
+        /* Skip forward to the data wanted */
+        if session.pos < offset {
+            let mut skip = vec![0u8; HTTP_SKIP_BUF_SIZE.min(offset - session.pos)];
+            while session.pos < offset {
+                let len = skip.len().min(offset - session.pos);
+                if session.recv(&mut iface, &mut skip[..len])? == 0 {
+                    return Err(ErrorCode::OutOfBounds);
+                }
+            }
+        }
+
+        let mut pos = 0;
+        while pos < buf.len() {
+            match session.recv(&mut iface, &mut buf[pos..])? {
+                0   => return Err(ErrorCode::OutOfBounds),
+                len => pos += len,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl HttpSession {
+    /// Connect to the server, send the request, and read the response headers
+    fn open(iface: &mut NetInterface, url: HttpUrl) -> Result<HttpSession, ErrorCode> {
+        let mut stream = match TcpStream::connect(iface, url.server, url.port) {
+            Ok(stream) => stream,
+            Err(e)     => {
+                println!("HTTP: Could not connect to {}:{}: {}", url.server, url.port, e);
+                return Err(e);
+            }
+        };
+
+        let request = format!("GET {} HTTP/1.0\r\nHost: {}:{}\r\nUser-Agent: RLBoot/{}\r\n\r\n",
+                              url.path, url.server, url.port, env!("CARGO_PKG_VERSION"));
+        stream.send(iface, request.as_bytes())?;
+
+        /* Headers are read a byte at a time, so none of the body is consumed */
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= HTTP_MAX_HEADER_SIZE {
+                println!("HTTP: Response headers too long");
+                return Err(ErrorCode::ReadFailure);
+            }
+
+            let mut byte = [0u8];
+            if stream.recv(iface, &mut byte)? == 0 {
+                println!("HTTP: Connection closed before end of headers");
+                return Err(ErrorCode::ReadFailure);
+            }
+            head.push(byte[0]);
+        }
+
+        let head = String::from_utf8_lossy(&head);
+        let mut lines = head.split("\r\n");
+
+        let status = lines.next().unwrap_or("");
+        let code = status.split(' ').nth(1).and_then(|code| code.parse::<u16>().ok()).unwrap_or(0);
+
+        let mut length = None;
+        let mut location = None;
+        for line in lines {
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().ok();
+                } else if name.trim().eq_ignore_ascii_case("location") {
+                    location = Some(value.trim());
+                }
+            }
+        }
+
+        match code {
+            HTTP_STATUS_OK        => {},
+            HTTP_STATUS_NOT_FOUND => {
+                stream.close(iface);
+                return Err(ErrorCode::FileNotFound);
+            },
+            _ => {
+                println!("HTTP: {} failed: {}", url.path, status);
+                if let Some(location) = location {
+                    println!("HTTP: Redirects aren't followed, try {}", location);
+                }
+                stream.close(iface);
+                return Err(ErrorCode::ReadFailure);
+            }
+        }
+
+        Ok(HttpSession {
+            url,
+            stream,
+            length,
+            pos: 0,
+            reported: 0,
+        })
+    }
+
+    /// Receive part of the body, reporting progress through it
+    fn recv(&mut self, iface: &mut NetInterface, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        let len = self.stream.recv(iface, buf)?;
+        self.pos += len;
+
+        if let Some(length) = self.length {
+            let tenths = ((self.pos as u64 * 10) / (length.max(1) as u64)) as usize;
+            if length >= HTTP_PROGRESS_MIN_SIZE && tenths > self.reported {
+                self.reported = tenths;
+                println!("  {}: {}% of {} KiB", self.url.path, tenths * 10, length / 1024);
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+impl HttpUrl {
+    /// Parse the part of a URL after the scheme
+    fn parse(path: &str) -> Result<HttpUrl, ErrorCode> {
+        let rest = path.strip_prefix("//").ok_or(ErrorCode::FileNotFound)?;
+        let (host, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None      => (rest, "/"),
+        };
+        let (host, port) = match host.split_once(':') {
+            Some((host, port)) => (host, port.parse().ok().filter(|&port| port != 0).ok_or(ErrorCode::FileNotFound)?),
+            None               => (host, HTTP_PORT),
+        };
+
+        match Ipv4Addr::parse(host) {
+            Some(server) => Ok(HttpUrl { server, port, path: path.to_string() }),
+            None         => {
+                println!("HTTP server must be an IPv4 address: {}", host);
+                Err(ErrorCode::FileNotFound)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HttpFile {
+    url: HttpUrl,
+    size: usize,
+    attr: u32,
+
+    fs: Weak<RefCell<HttpFilesystem>>,
+}
+
+impl File for HttpFile {
+    fn get_size(&self) -> usize {
+        self.size
+    }
+
+    fn get_attr(&self) -> u32 {
+        self.attr
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if (offset as usize + buf.len()) > self.size {
+            println!("HttpFile: Attempt to read past end of file");
+            return Err(ErrorCode::OutOfBounds);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let fs = self.fs.upgrade().expect("Could not upgrade HttpFile::fs");
+        let fs = fs.borrow();
+        fs.read(&self.url, offset as usize, buf)
+    }
+
+    fn close(&self) {
+
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+const HTTP_PORT: u16 = 80;
+
+const HTTP_MAX_HEADER_SIZE: usize   = 8192;
+const HTTP_SKIP_BUF_SIZE: usize     = 4096;
+const HTTP_PROGRESS_MIN_SIZE: usize = 256 * 1024; //< Smaller files are not worth reporting progress on
+
+const HTTP_STATUS_OK: u16        = 200;
+const HTTP_STATUS_NOT_FOUND: u16 = 404;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(path: &str) -> Option<([u8; 4], u16, String)> {
+        HttpUrl::parse(path).ok().map(|url| (url.server.0, url.port, url.path))
+    }
+
+    #[test]
+    fn url_host_and_port() {
+        assert_eq!(parse("//10.0.2.2/kernel"), Some(([10, 0, 2, 2], HTTP_PORT, "/kernel".to_string())));
+        assert_eq!(parse("//10.0.2.2:8000/boot/kernel"), Some(([10, 0, 2, 2], 8000, "/boot/kernel".to_string())));
+    }
+
+    #[test]
+    fn url_without_path() {
+        assert_eq!(parse("//192.168.1.1"), Some(([192, 168, 1, 1], HTTP_PORT, "/".to_string())));
+        assert_eq!(parse("//192.168.1.1:8080"), Some(([192, 168, 1, 1], 8080, "/".to_string())));
+    }
+
+    #[test]
+    fn url_rejected() {
+        for path in ["//10.0.2.2:/kernel", "//10.0.2.2:http/kernel", "//10.0.2.2:65536/kernel",
+                     "//10.0.2.2:0/kernel", "//10.0.2.2:-1/kernel", "//example.com/kernel",
+                     "//10.0.2/kernel", "10.0.2.2/kernel", "/kernel", ""] {
+            assert!(parse(path).is_none(), "{}", path);
+        }
+    }
+}
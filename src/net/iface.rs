@@ -0,0 +1,397 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::errors::ErrorCode;
+use crate::intr::pit::Deadline;
+use crate::net::nic::{NetDevice, ETH_MAX_FRAME};
+use crate::net::{Ipv4Addr, MacAddr, UdpDatagram, UdpTransport};
+
+/// IPv4 network interface on top of a native NIC driver.
+///
+/// Everything is done synchronously by polling: ARP requests are answered
+/// whenever frames are received, and frames not matching what the caller is
+/// waiting for are dropped. IP fragments are not supported.
+pub struct NetInterface {
+    dev: Box<dyn NetDevice>,
+    mac: MacAddr,
+
+    pub addr: Ipv4Addr,    //< Address of this interface, unspecified until configured
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr, //< Default router, unspecified if there is none
+
+    arp_cache: Vec<(Ipv4Addr, MacAddr)>,
+    ip_ident: u16,  //< Identification field of next IPv4 packet sent
+    next_port: u16, //< Next ephemeral UDP port
+    rx_buf: Vec<u8>,
+}
+
+/// Received IPv4 packet addressed to this interface
+pub struct Ipv4Packet {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub payload: Vec<u8>,
+}
+
+impl NetInterface {
+    pub fn new(dev: Box<dyn NetDevice>) -> NetInterface {
+        let mac = dev.mac();
+
+        NetInterface {
+            dev,
+            mac,
+            addr: Ipv4Addr::UNSPECIFIED,
+            netmask: Ipv4Addr::UNSPECIFIED,
+            gateway: Ipv4Addr::UNSPECIFIED,
+            arp_cache: Vec::new(),
+            ip_ident: 1,
+            next_port: UDP_EPHEMERAL_START + (u16::from_be_bytes([mac.0[4], mac.0[5]]) % 0x1000),
+            rx_buf: vec![0; ETH_MAX_FRAME],
+        }
+    }
+
+    pub fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    /// Send an IPv4 packet, resolving the next hop with ARP
+    pub fn send_ipv4(&mut self, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), ErrorCode> {
+        let mut hdr = Ipv4Header {
+            version_ihl: IPV4_VERSION_IHL,
+            tos: 0,
+            total_length: ((mem::size_of::<Ipv4Header>() + payload.len()) as u16).to_be(),
+            ident: self.ip_ident.to_be(),
+            flags_frag: IPV4_FLAG_DF.to_be(),
+            ttl: IPV4_TTL,
+            protocol,
+            checksum: 0,
+            src: self.addr.0,
+            dst: dst.0,
+        };
+        hdr.checksum = checksum(as_bytes(&hdr), 0).to_be();
+        self.ip_ident = self.ip_ident.wrapping_add(1);
+
+        let dst_mac = self.resolve(dst)?;
+
+        let mut packet = Vec::with_capacity(mem::size_of::<Ipv4Header>() + payload.len());
+        packet.extend_from_slice(as_bytes(&hdr));
+        packet.extend_from_slice(payload);
+
+        self.send_frame(dst_mac, ETHERTYPE_IPV4, &packet)
+    }
+
+    /// Wait for an IPv4 packet addressed to this interface
+    pub fn recv_ipv4(&mut self, deadline: Deadline) -> Result<Ipv4Packet, ErrorCode> {
+        while !deadline.expired() {
+            if let Some(pkt) = self.poll() {
+                return Ok(pkt);
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Get the MAC address to send a packet for `dst` to
+    fn resolve(&mut self, dst: Ipv4Addr) -> Result<MacAddr, ErrorCode> {
+        if dst == Ipv4Addr::BROADCAST || self.addr.is_unspecified() {
+            return Ok(MacAddr::BROADCAST);
+        }
+
+        let next_hop = if dst.same_subnet(self.addr, self.netmask) || self.gateway.is_unspecified() {
+            dst
+        } else {
+            self.gateway
+        };
+
+        for _ in 0..ARP_RETRIES {
+            if let Some((_, mac)) = self.arp_cache.iter().find(|(ip, _)| *ip == next_hop) {
+                return Ok(*mac);
+            }
+
+            self.send_arp(ARP_OP_REQUEST, MacAddr::BROADCAST, MacAddr::default(), next_hop)?;
+
+            let deadline = Deadline::after_ms(ARP_TIMEOUT_MS);
+            while !deadline.expired() && !self.arp_cache.iter().any(|(ip, _)| *ip == next_hop) {
+                /* Anything other than ARP is dropped while waiting */
+                self.poll();
+            }
+        }
+
+        match self.arp_cache.iter().find(|(ip, _)| *ip == next_hop) {
+            Some((_, mac)) => Ok(*mac),
+            None           => Err(ErrorCode::Timeout),
+        }
+    }
+
+    fn send_arp(&mut self, op: u16, eth_dst: MacAddr, target_mac: MacAddr, target_ip: Ipv4Addr) -> Result<(), ErrorCode> {
+        let arp = ArpPacket {
+            htype: ARP_HTYPE_ETHERNET.to_be(),
+            ptype: ETHERTYPE_IPV4.to_be(),
+            hlen: 6,
+            plen: 4,
+            oper: op.to_be(),
+            sha: self.mac.0,
+            spa: self.addr.0,
+            tha: target_mac.0,
+            tpa: target_ip.0,
+        };
+
+        self.send_frame(eth_dst, ETHERTYPE_ARP, as_bytes(&arp))
+    }
+
+    fn send_frame(&mut self, dst: MacAddr, ethertype: u16, payload: &[u8]) -> Result<(), ErrorCode> {
+        let hdr = EthHeader {
+            dst: dst.0,
+            src: self.mac.0,
+            ethertype: ethertype.to_be(),
+        };
+
+        let mut frame = Vec::with_capacity(mem::size_of::<EthHeader>() + payload.len());
+        frame.extend_from_slice(as_bytes(&hdr));
+        frame.extend_from_slice(payload);
+
+        self.dev.transmit(&frame)
+    }
+
+    /// Receive and process one frame, returning it if it is an IPv4 packet for
+    /// this interface
+    fn poll(&mut self) -> Option<Ipv4Packet> {
+        let len = self.dev.receive(&mut self.rx_buf)?;
+        let eth: EthHeader = read_struct(&self.rx_buf[..len])?;
+        let payload = &self.rx_buf[mem::size_of::<EthHeader>()..len];
+
+        match u16::from_be(eth.ethertype) {
+            ETHERTYPE_ARP  => {
+                let arp: ArpPacket = read_struct(payload)?;
+                self.handle_arp(&arp);
+                None
+            },
+            ETHERTYPE_IPV4 => self.parse_ipv4(payload),
+            _              => None
+        }
+    }
+
+    fn handle_arp(&mut self, arp: &ArpPacket) {
+        if u16::from_be(arp.htype) != ARP_HTYPE_ETHERNET || u16::from_be(arp.ptype) != ETHERTYPE_IPV4 {
+            return;
+        }
+
+        let sender_ip  = Ipv4Addr(arp.spa);
+        let sender_mac = MacAddr(arp.sha);
+        if !sender_ip.is_unspecified() {
+            match self.arp_cache.iter_mut().find(|(ip, _)| *ip == sender_ip) {
+                Some(entry) => entry.1 = sender_mac,
+                None        => self.arp_cache.push((sender_ip, sender_mac)),
+            }
+        }
+
+        if u16::from_be(arp.oper) == ARP_OP_REQUEST && !self.addr.is_unspecified() && Ipv4Addr(arp.tpa) == self.addr {
+            _ = self.send_arp(ARP_OP_REPLY, sender_mac, sender_mac, sender_ip);
+        }
+    }
+
+    fn parse_ipv4(&self, data: &[u8]) -> Option<Ipv4Packet> {
+        let hdr: Ipv4Header = read_struct(data)?;
+        let hdr_len = ((hdr.version_ihl & 0x0F) as usize) * 4;
+        let total   = u16::from_be(hdr.total_length) as usize;
+
+        if (hdr.version_ihl >> 4) != 4 || hdr_len < mem::size_of::<Ipv4Header>() ||
+           total < hdr_len || total > data.len() {
+            return None;
+        }
+        if checksum(&data[..hdr_len], 0) != 0 {
+            return None;
+        }
+        if (u16::from_be(hdr.flags_frag) & (IPV4_FLAG_MF | IPV4_FRAG_OFFSET_MASK)) != 0 {
+            /* Fragments aren't reassembled */
+            return None;
+        }
+
+        let dst = Ipv4Addr(hdr.dst);
+        if !self.addr.is_unspecified() && dst != self.addr && dst != Ipv4Addr::BROADCAST {
+            return None;
+        }
+
+        Some(Ipv4Packet {
+            src: Ipv4Addr(hdr.src),
+            dst,
+            protocol: hdr.protocol,
+            payload: data[hdr_len..total].to_vec(),
+        })
+    }
+}
+
+impl UdpTransport for NetInterface {
+    fn ephemeral_port(&mut self) -> u16 {
+        let port = self.next_port;
+        self.next_port = match self.next_port.checked_add(1) {
+            Some(next) => next,
+            None       => UDP_EPHEMERAL_START,
+        };
+        port
+    }
+
+    fn send_udp(&mut self, dst: Ipv4Addr, src_port: u16, dst_port: u16, data: &[u8]) -> Result<(), ErrorCode> {
+        let len = mem::size_of::<UdpHeader>() + data.len();
+        let hdr = UdpHeader {
+            src_port: src_port.to_be(),
+            dst_port: dst_port.to_be(),
+            length: (len as u16).to_be(),
+            checksum: 0,
+        };
+
+        let mut segment = Vec::with_capacity(len);
+        segment.extend_from_slice(as_bytes(&hdr));
+        segment.extend_from_slice(data);
+
+        let csum = match pseudo_checksum(self.addr, dst, IP_PROTO_UDP, &segment) {
+            0    => 0xFFFF,
+            csum => csum,
+        };
+        segment[6..8].copy_from_slice(&csum.to_be_bytes());
+
+        self.send_ipv4(dst, IP_PROTO_UDP, &segment)
+    }
+
+    fn recv_udp(&mut self, port: u16, deadline: Deadline) -> Result<UdpDatagram, ErrorCode> {
+        loop {
+            let pkt = self.recv_ipv4(deadline)?;
+            if pkt.protocol != IP_PROTO_UDP {
+                continue;
+            }
+
+            let hdr: UdpHeader = match read_struct(&pkt.payload) {
+                Some(hdr) => hdr,
+                None      => continue,
+            };
+            let len = u16::from_be(hdr.length) as usize;
+            if u16::from_be(hdr.dst_port) != port || len < mem::size_of::<UdpHeader>() || len > pkt.payload.len() {
+                continue;
+            }
+            if hdr.checksum != 0 && pseudo_checksum(pkt.src, pkt.dst, IP_PROTO_UDP, &pkt.payload[..len]) != 0 {
+                continue;
+            }
+
+            return Ok(UdpDatagram {
+                src: pkt.src,
+                src_port: u16::from_be(hdr.src_port),
+                data: pkt.payload[mem::size_of::<UdpHeader>()..len].to_vec(),
+            });
+        }
+    }
+}
+
+/// Compute the Internet checksum of some data
+///
+/// # Arguments
+/// * data - Data to sum
+/// * initial - Partial sum to continue from
+pub fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for word in data.chunks(2) {
+        sum += u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u32;
+    }
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// Compute the checksum of a UDP or TCP segment, including the IPv4
+/// pseudo-header
+pub fn pseudo_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, segment: &[u8]) -> u16 {
+    let sum = u16::from_be_bytes([src.0[0], src.0[1]]) as u32 + u16::from_be_bytes([src.0[2], src.0[3]]) as u32 +
+              u16::from_be_bytes([dst.0[0], dst.0[1]]) as u32 + u16::from_be_bytes([dst.0[2], dst.0[3]]) as u32 +
+              protocol as u32 + segment.len() as u32;
+
+    checksum(segment, sum)
+}
+
+/// View a packed header structure as bytes
+pub fn as_bytes<T>(val: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(val as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+/// Read a packed header structure from the start of some data, if it is long
+/// enough
+pub fn read_struct<T>(data: &[u8]) -> Option<T> {
+    if data.len() < mem::size_of::<T>() {
+        return None;
+    }
+
+    Some(unsafe { core::ptr::read_unaligned(data.as_ptr() as *const T) })
+}
+
+#[repr(C, packed(1))]
+struct EthHeader {
+    dst: [u8; 6],
+    src: [u8; 6],
+    ethertype: u16, //< Network byte order
+}
+const _ETH_HEADER_SZ_TEST: [u8; 14] = [0; mem::size_of::<EthHeader>()];
+
+/// ARP packet for IPv4 over Ethernet
+#[repr(C, packed(1))]
+struct ArpPacket {
+    htype: u16,    //< Hardware type, network byte order
+    ptype: u16,    //< Protocol type, network byte order
+    hlen: u8,      //< Hardware address length
+    plen: u8,      //< Protocol address length
+    oper: u16,     //< Operation, network byte order
+    sha: [u8; 6],  //< Sender hardware address
+    spa: [u8; 4],  //< Sender protocol address
+    tha: [u8; 6],  //< Target hardware address
+    tpa: [u8; 4],  //< Target protocol address
+}
+const _ARP_PACKET_SZ_TEST: [u8; 28] = [0; mem::size_of::<ArpPacket>()];
+
+/// IPv4 header, without options. Multi-byte fields are in network byte order.
+#[repr(C, packed(1))]
+struct Ipv4Header {
+    version_ihl: u8,  //< Version and header length in words
+    tos: u8,
+    total_length: u16,
+    ident: u16,
+    flags_frag: u16,  //< Flags and fragment offset
+    ttl: u8,
+    protocol: u8,
+    checksum: u16,
+    src: [u8; 4],
+    dst: [u8; 4],
+}
+const _IPV4_HEADER_SZ_TEST: [u8; 20] = [0; mem::size_of::<Ipv4Header>()];
+
+/// UDP header. Fields are in network byte order.
+#[repr(C, packed(1))]
+struct UdpHeader {
+    src_port: u16,
+    dst_port: u16,
+    length: u16,   //< Length of header and data
+    checksum: u16, //< 0 if not computed
+}
+const _UDP_HEADER_SZ_TEST: [u8; 8] = [0; mem::size_of::<UdpHeader>()];
+
+pub const IP_PROTO_TCP: u8 = 6;
+pub const IP_PROTO_UDP: u8 = 17;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16  = 0x0806;
+
+const ARP_HTYPE_ETHERNET: u16 = 1;
+const ARP_OP_REQUEST: u16     = 1;
+const ARP_OP_REPLY: u16       = 2;
+const ARP_RETRIES: usize      = 3;
+const ARP_TIMEOUT_MS: u32     = 1000;
+
+const IPV4_VERSION_IHL: u8       = 0x45; //< IPv4, 5-word header
+const IPV4_TTL: u8               = 64;
+const IPV4_FLAG_DF: u16          = 1 << 14; //< Don't fragment
+const IPV4_FLAG_MF: u16          = 1 << 13; //< More fragments
+const IPV4_FRAG_OFFSET_MASK: u16 = 0x1FFF;
+
+const UDP_EPHEMERAL_START: u16 = 49152;
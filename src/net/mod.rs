@@ -0,0 +1,104 @@
+extern crate alloc;
+
+pub mod dhcp;
+pub mod http;
+pub mod iface;
+pub mod nic;
+pub mod pxe;
+pub mod tcp;
+pub mod tftp;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::errors::ErrorCode;
+use crate::intr::pit::Deadline;
+
+/// Something that can send and receive UDP datagrams, such as a native network
+/// interface
+pub trait UdpTransport {
+    /// Allocate a local port number for a new conversation
+    fn ephemeral_port(&mut self) -> u16;
+
+    /// Send a UDP datagram
+    ///
+    /// # Arguments
+    /// * dst - Destination address, may be the broadcast address
+    /// * src_port - Local port
+    /// * dst_port - Remote port
+    /// * data - Payload
+    fn send_udp(&mut self, dst: Ipv4Addr, src_port: u16, dst_port: u16, data: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Wait for a UDP datagram sent to a local port
+    ///
+    /// # Arguments
+    /// * port - Local port
+    /// * deadline - Time after which to give up
+    fn recv_udp(&mut self, port: u16, deadline: Deadline) -> Result<UdpDatagram, ErrorCode>;
+}
+
+/// Received UDP datagram
+pub struct UdpDatagram {
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    pub data: Vec<u8>,
+}
+
+/// IPv4 address, in network byte order
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0, 0, 0, 0]);
+    pub const BROADCAST: Ipv4Addr   = Ipv4Addr([255, 255, 255, 255]);
+
+    /// Read an address from the first four bytes of a slice
+    pub fn from_slice(data: &[u8]) -> Ipv4Addr {
+        Ipv4Addr([data[0], data[1], data[2], data[3]])
+    }
+
+    /// Parse an address in dotted-decimal form
+    pub fn parse(s: &str) -> Option<Ipv4Addr> {
+        let mut addr = [0u8; 4];
+        let mut parts = s.split('.');
+
+        for octet in addr.iter_mut() {
+            *octet = parts.next()?.parse().ok()?;
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None    => Some(Ipv4Addr(addr))
+        }
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    /// Check whether two addresses are on the same subnet
+    pub fn same_subnet(&self, other: Ipv4Addr, netmask: Ipv4Addr) -> bool {
+        (0..4).all(|idx| (self.0[idx] & netmask.0[idx]) == (other.0[idx] & netmask.0[idx]))
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+/// Ethernet MAC address
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xFF; 6]);
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+               self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5])
+    }
+}
@@ -0,0 +1,333 @@
+extern crate alloc;
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::alloc::Layout;
+use core::mem;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::errors::ErrorCode;
+use crate::net::MacAddr;
+use crate::net::nic::{NetDevice, ETH_MIN_FRAME};
+use crate::pci::{self, PciDevice};
+
+/// Intel 8254x (e1000) gigabit Ethernet controller.
+///
+/// Interrupts are masked, and both descriptor rings are polled. Only one frame
+/// is transmitted at a time.
+pub struct E1000NetDevice {
+    regs: usize,      //< Address of device registers
+    mac: MacAddr,
+    mem: NonNull<u8>, //< Descriptor rings and packet buffers
+    rx_cur: usize,    //< Next receive descriptor to be filled by the device
+    tx_cur: usize,    //< Next transmit descriptor to be used
+}
+
+impl E1000NetDevice {
+    pub fn new(pci_dev: &PciDevice) -> Result<E1000NetDevice, ErrorCode> {
+        let regs = match pci_dev.mem_bar(0) {
+            Some(addr) if addr <= u32::MAX as u64 => addr as usize,
+            _                                     => return Err(ErrorCode::Unsupported)
+        };
+        pci_dev.addr.enable(pci::PCI_COMMAND_MEMORY | pci::PCI_COMMAND_BUS_MASTER);
+
+        /* Reset, then wait for the EEPROM to be reloaded */
+        mmio_write(regs + E1000_REG_IMC, 0xFFFFFFFF);
+        mmio_write(regs + E1000_REG_CTRL, mmio_read(regs + E1000_REG_CTRL) | E1000_CTRL_RST);
+        let mut reset = false;
+        for _ in 0..E1000_TIMEOUT {
+            if (mmio_read(regs + E1000_REG_CTRL) & E1000_CTRL_RST) == 0 {
+                reset = true;
+                break;
+            }
+        }
+        if !reset {
+            return Err(ErrorCode::Timeout);
+        }
+        mmio_write(regs + E1000_REG_IMC, 0xFFFFFFFF);
+
+        let ctrl = mmio_read(regs + E1000_REG_CTRL);
+        mmio_write(regs + E1000_REG_CTRL, (ctrl | E1000_CTRL_SLU | E1000_CTRL_ASDE) &
+                                          !(E1000_CTRL_LRST | E1000_CTRL_PHY_RST));
+
+        let mac = Self::read_mac(regs)?;
+
+        let mem = NonNull::new(unsafe { alloc_zeroed(Self::mem_layout()) }).ok_or(ErrorCode::NoSpace)?;
+        let dev = E1000NetDevice {
+            regs,
+            mac,
+            mem,
+            rx_cur: 0,
+            tx_cur: 0,
+        };
+
+        /* Accept only frames to our address, and broadcasts */
+        for idx in 0..E1000_MTA_ENTRIES {
+            mmio_write(regs + E1000_REG_MTA + (idx * 4), 0);
+        }
+        mmio_write(regs + E1000_REG_RAL, u32::from_le_bytes([mac.0[0], mac.0[1], mac.0[2], mac.0[3]]));
+        mmio_write(regs + E1000_REG_RAH, u16::from_le_bytes([mac.0[4], mac.0[5]]) as u32 | E1000_RAH_AV);
+
+        let base = mem.as_ptr() as usize;
+        for idx in 0..E1000_RX_DESCS {
+            unsafe {
+                ptr::write_volatile(dev.rx_desc(idx), E1000RxDesc {
+                    addr: (base + E1000_MEM_RX_BUFS + (idx * E1000_BUF_SIZE)) as u64,
+                    ..Default::default()
+                });
+            }
+        }
+
+        mmio_write(regs + E1000_REG_RDBAL, (base + E1000_MEM_RX_DESCS) as u32);
+        mmio_write(regs + E1000_REG_RDBAH, 0);
+        mmio_write(regs + E1000_REG_RDLEN, (E1000_RX_DESCS * mem::size_of::<E1000RxDesc>()) as u32);
+        mmio_write(regs + E1000_REG_RDH,   0);
+        mmio_write(regs + E1000_REG_RDT,   (E1000_RX_DESCS - 1) as u32);
+        mmio_write(regs + E1000_REG_RCTL,  E1000_RCTL_EN | E1000_RCTL_BAM | E1000_RCTL_SECRC | E1000_RCTL_BSIZE_2048);
+
+        mmio_write(regs + E1000_REG_TDBAL, (base + E1000_MEM_TX_DESCS) as u32);
+        mmio_write(regs + E1000_REG_TDBAH, 0);
+        mmio_write(regs + E1000_REG_TDLEN, (E1000_TX_DESCS * mem::size_of::<E1000TxDesc>()) as u32);
+        mmio_write(regs + E1000_REG_TDH,   0);
+        mmio_write(regs + E1000_REG_TDT,   0);
+        mmio_write(regs + E1000_REG_TIPG,  E1000_TIPG_DEFAULT);
+        mmio_write(regs + E1000_REG_TCTL,  E1000_TCTL_EN | E1000_TCTL_PSP | E1000_TCTL_CT | E1000_TCTL_COLD);
+
+        Ok(dev)
+    }
+
+    fn mem_layout() -> Layout {
+        Layout::from_size_align(E1000_MEM_SIZE, E1000_MEM_ALIGN).unwrap()
+    }
+
+    /// Get the MAC address, from the receive address registers if the EEPROM
+    /// has loaded them, otherwise from the EEPROM directly
+    fn read_mac(regs: usize) -> Result<MacAddr, ErrorCode> {
+        let rah = mmio_read(regs + E1000_REG_RAH);
+        if (rah & E1000_RAH_AV) != 0 {
+            let ral = mmio_read(regs + E1000_REG_RAL).to_le_bytes();
+            let rah = rah.to_le_bytes();
+            return Ok(MacAddr([ral[0], ral[1], ral[2], ral[3], rah[0], rah[1]]));
+        }
+
+        let mut mac = [0u8; 6];
+        for (idx, word) in mac.chunks_exact_mut(2).enumerate() {
+            mmio_write(regs + E1000_REG_EERD, ((idx as u32) << E1000_EERD_ADDR_SHIFT) | E1000_EERD_START);
+
+            let mut data = None;
+            for _ in 0..E1000_TIMEOUT {
+                let eerd = mmio_read(regs + E1000_REG_EERD);
+                if (eerd & E1000_EERD_DONE) != 0 {
+                    data = Some((eerd >> 16) as u16);
+                    break;
+                }
+            }
+            word.copy_from_slice(&data.ok_or(ErrorCode::Timeout)?.to_le_bytes());
+        }
+
+        Ok(MacAddr(mac))
+    }
+
+    fn rx_desc(&self, idx: usize) -> *mut E1000RxDesc {
+        unsafe { (self.mem.as_ptr().add(E1000_MEM_RX_DESCS) as *mut E1000RxDesc).add(idx) }
+    }
+
+    fn tx_desc(&self, idx: usize) -> *mut E1000TxDesc {
+        unsafe { (self.mem.as_ptr().add(E1000_MEM_TX_DESCS) as *mut E1000TxDesc).add(idx) }
+    }
+}
+
+impl Drop for E1000NetDevice {
+    fn drop(&mut self) {
+        /* Stop DMA before releasing memory the device may access */
+        mmio_write(self.regs + E1000_REG_RCTL, 0);
+        mmio_write(self.regs + E1000_REG_TCTL, 0);
+        unsafe { dealloc(self.mem.as_ptr(), Self::mem_layout()); }
+    }
+}
+
+impl NetDevice for E1000NetDevice {
+    fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), ErrorCode> {
+        if frame.len() > E1000_BUF_SIZE {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let buf = unsafe { self.mem.as_ptr().add(E1000_MEM_TX_BUF) };
+        let len = frame.len().max(ETH_MIN_FRAME);
+        unsafe {
+            ptr::copy_nonoverlapping(frame.as_ptr(), buf, frame.len());
+            ptr::write_bytes(buf.add(frame.len()), 0, len - frame.len());
+
+            ptr::write_volatile(self.tx_desc(self.tx_cur), E1000TxDesc {
+                addr: buf as usize as u64,
+                length: len as u16,
+                cmd: E1000_TXD_CMD_EOP | E1000_TXD_CMD_IFCS | E1000_TXD_CMD_RS,
+                ..Default::default()
+            });
+        }
+
+        let desc = self.tx_desc(self.tx_cur);
+        self.tx_cur = (self.tx_cur + 1) % E1000_TX_DESCS;
+        fence(Ordering::SeqCst);
+        mmio_write(self.regs + E1000_REG_TDT, self.tx_cur as u32);
+
+        for _ in 0..E1000_TIMEOUT {
+            if (unsafe { ptr::read_volatile(ptr::addr_of!((*desc).status)) } & E1000_TXD_STAT_DD) != 0 {
+                return Ok(());
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize> {
+        loop {
+            let desc = unsafe { ptr::read_volatile(self.rx_desc(self.rx_cur)) };
+            if (desc.status & E1000_RXD_STAT_DD) == 0 {
+                return None;
+            }
+            fence(Ordering::SeqCst);
+
+            let idx = self.rx_cur;
+            let len = (desc.length as usize).min(buf.len());
+            let good = (desc.status & E1000_RXD_STAT_EOP) != 0 && desc.errors == 0;
+            if good {
+                unsafe {
+                    ptr::copy_nonoverlapping(self.mem.as_ptr().add(E1000_MEM_RX_BUFS + (idx * E1000_BUF_SIZE)),
+                                             buf.as_mut_ptr(), len);
+                }
+            }
+
+            /* Hand the descriptor back to the device */
+            unsafe { ptr::write_volatile(ptr::addr_of_mut!((*self.rx_desc(idx)).status), 0); }
+            self.rx_cur = (idx + 1) % E1000_RX_DESCS;
+            mmio_write(self.regs + E1000_REG_RDT, idx as u32);
+
+            if good {
+                return Some(len);
+            }
+        }
+    }
+}
+
+fn mmio_read(addr: usize) -> u32 {
+    unsafe { ptr::read_volatile(addr as *const u32) }
+}
+
+fn mmio_write(addr: usize, value: u32) {
+    unsafe { ptr::write_volatile(addr as *mut u32, value) }
+}
+
+/// Legacy receive descriptor
+#[derive(Clone, Copy, Default)]
+#[repr(C, packed(1))]
+struct E1000RxDesc {
+    addr: u64,     //< Physical address of buffer
+    length: u16,   //< Length of received data
+    checksum: u16, //< Packet checksum
+    status: u8,    //< E1000_RXD_STAT_*
+    errors: u8,    //< Receive errors
+    special: u16,  //< VLAN tag
+}
+const _E1000_RX_DESC_SZ_TEST: [u8; 16] = [0; mem::size_of::<E1000RxDesc>()];
+
+/// Legacy transmit descriptor
+#[derive(Clone, Copy, Default)]
+#[repr(C, packed(1))]
+struct E1000TxDesc {
+    addr: u64,    //< Physical address of buffer
+    length: u16,  //< Length of data to send
+    cso: u8,      //< Checksum offset
+    cmd: u8,      //< E1000_TXD_CMD_*
+    status: u8,   //< E1000_TXD_STAT_*
+    css: u8,      //< Checksum start
+    special: u16, //< VLAN tag
+}
+const _E1000_TX_DESC_SZ_TEST: [u8; 16] = [0; mem::size_of::<E1000TxDesc>()];
+
+pub const E1000_PCI_VENDOR: u16    = 0x8086;
+pub const E1000_PCI_DEVICES: &[u16] = &[
+    0x100E, //< 82540EM, as emulated by QEMU
+    0x100F, //< 82545EM (copper)
+    0x1004, //< 82543GC (copper)
+    0x1015, //< 82540EM (LOM)
+    0x1016, //< 82540EP (LOM)
+    0x1017, //< 82540EP
+    0x101E, //< 82540EP (mobile)
+    0x1026, //< 82545GM (copper)
+    0x1076, //< 82541GI
+    0x107C, //< 82541PI
+    0x10B9, //< 82572EI
+];
+
+const E1000_RX_DESCS: usize = 16;   //< Receive descriptors, multiple of 8
+const E1000_TX_DESCS: usize = 8;    //< Transmit descriptors, multiple of 8
+const E1000_BUF_SIZE: usize = 2048; //< Size of each packet buffer
+const E1000_TIMEOUT: usize  = 1_000_000;
+
+/* Layout of driver memory */
+const E1000_MEM_RX_DESCS: usize = 0;
+const E1000_MEM_TX_DESCS: usize = E1000_MEM_RX_DESCS + (E1000_RX_DESCS * 16);
+const E1000_MEM_TX_BUF: usize   = E1000_MEM_TX_DESCS + (E1000_TX_DESCS * 16);
+const E1000_MEM_RX_BUFS: usize  = E1000_MEM_TX_BUF + E1000_BUF_SIZE;
+const E1000_MEM_SIZE: usize     = E1000_MEM_RX_BUFS + (E1000_RX_DESCS * E1000_BUF_SIZE);
+const E1000_MEM_ALIGN: usize    = 128; //< Descriptor rings must be 128-byte aligned
+
+const E1000_REG_CTRL: usize  = 0x0000; //< Device control
+const E1000_REG_EERD: usize  = 0x0014; //< EEPROM read
+const E1000_REG_IMC: usize   = 0x00D8; //< Interrupt mask clear
+const E1000_REG_RCTL: usize  = 0x0100; //< Receive control
+const E1000_REG_TCTL: usize  = 0x0400; //< Transmit control
+const E1000_REG_TIPG: usize  = 0x0410; //< Transmit inter-packet gap
+const E1000_REG_RDBAL: usize = 0x2800; //< Receive descriptor base (low)
+const E1000_REG_RDBAH: usize = 0x2804; //< Receive descriptor base (high)
+const E1000_REG_RDLEN: usize = 0x2808; //< Receive descriptor ring length
+const E1000_REG_RDH: usize   = 0x2810; //< Receive descriptor head
+const E1000_REG_RDT: usize   = 0x2818; //< Receive descriptor tail
+const E1000_REG_TDBAL: usize = 0x3800; //< Transmit descriptor base (low)
+const E1000_REG_TDBAH: usize = 0x3804; //< Transmit descriptor base (high)
+const E1000_REG_TDLEN: usize = 0x3808; //< Transmit descriptor ring length
+const E1000_REG_TDH: usize   = 0x3810; //< Transmit descriptor head
+const E1000_REG_TDT: usize   = 0x3818; //< Transmit descriptor tail
+const E1000_REG_MTA: usize   = 0x5200; //< Multicast table array
+const E1000_REG_RAL: usize   = 0x5400; //< Receive address 0 (low)
+const E1000_REG_RAH: usize   = 0x5404; //< Receive address 0 (high)
+
+const E1000_MTA_ENTRIES: usize = 128;
+
+const E1000_CTRL_ASDE: u32    = 1 << 5;  //< Auto-speed detection
+const E1000_CTRL_SLU: u32     = 1 << 6;  //< Set link up
+const E1000_CTRL_LRST: u32    = 1 << 3;  //< Link reset
+const E1000_CTRL_RST: u32     = 1 << 26; //< Device reset
+const E1000_CTRL_PHY_RST: u32 = 1 << 31; //< PHY reset
+
+const E1000_EERD_START: u32      = 1 << 0;
+const E1000_EERD_DONE: u32       = 1 << 4;
+const E1000_EERD_ADDR_SHIFT: u32 = 8;
+
+const E1000_RAH_AV: u32 = 1 << 31; //< Address valid
+
+const E1000_RCTL_EN: u32         = 1 << 1;  //< Receiver enable
+const E1000_RCTL_BAM: u32        = 1 << 15; //< Accept broadcasts
+const E1000_RCTL_BSIZE_2048: u32 = 0 << 16; //< 2048 byte buffers
+const E1000_RCTL_SECRC: u32      = 1 << 26; //< Strip CRC
+
+const E1000_TCTL_EN: u32   = 1 << 1;     //< Transmitter enable
+const E1000_TCTL_PSP: u32  = 1 << 3;     //< Pad short packets
+const E1000_TCTL_CT: u32   = 0x0F << 4;  //< Collision threshold
+const E1000_TCTL_COLD: u32 = 0x40 << 12; //< Collision distance, full duplex
+
+const E1000_TIPG_DEFAULT: u32 = 10 | (8 << 10) | (6 << 20); //< IEEE 802.3 inter-packet gap, copper
+
+const E1000_RXD_STAT_DD: u8  = 1 << 0; //< Descriptor done
+const E1000_RXD_STAT_EOP: u8 = 1 << 1; //< End of packet
+
+const E1000_TXD_CMD_EOP: u8  = 1 << 0; //< End of packet
+const E1000_TXD_CMD_IFCS: u8 = 1 << 1; //< Insert FCS
+const E1000_TXD_CMD_RS: u8   = 1 << 3; //< Report status
+
+const E1000_TXD_STAT_DD: u8 = 1 << 0; //< Descriptor done
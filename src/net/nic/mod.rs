@@ -0,0 +1,65 @@
+extern crate alloc;
+
+pub mod e1000;
+pub mod ne2000;
+pub mod rtl8139;
+
+use alloc::boxed::Box;
+use core::fmt::Write;
+
+use crate::errors::ErrorCode;
+use crate::io::output;
+use crate::net::MacAddr;
+use crate::pci::PciBus;
+
+use self::{e1000::E1000NetDevice, ne2000::Ne2000NetDevice, rtl8139::Rtl8139NetDevice};
+
+/// Ethernet interface, operated by polling
+pub trait NetDevice {
+    /// Get the MAC address of the interface
+    fn mac(&self) -> MacAddr;
+
+    /// Send a frame, waiting until the device has taken it
+    ///
+    /// # Arguments
+    /// * frame - Complete Ethernet frame, without FCS
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), ErrorCode>;
+
+    /// Receive a frame, if one is waiting, returning its length
+    ///
+    /// # Arguments
+    /// * buf - Buffer to receive into, frames longer than it are truncated
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize>;
+}
+
+/// Find the first supported network interface on the PCI bus. Drivers are
+/// tried in order of preference.
+pub fn probe(pci: &PciBus) -> Option<Box<dyn NetDevice>> {
+    for pci_dev in pci.find_by_id(e1000::E1000_PCI_VENDOR, e1000::E1000_PCI_DEVICES) {
+        match E1000NetDevice::new(pci_dev) {
+            Ok(dev) => return Some(Box::new(dev)),
+            Err(e)  => println!("Could not initialize e1000 {}: {}", pci_dev.addr, e),
+        }
+    }
+
+    for pci_dev in pci.find_by_id(rtl8139::RTL8139_PCI_VENDOR, &[rtl8139::RTL8139_PCI_DEVICE]) {
+        match Rtl8139NetDevice::new(pci_dev) {
+            Ok(dev) => return Some(Box::new(dev)),
+            Err(e)  => println!("Could not initialize RTL8139 {}: {}", pci_dev.addr, e),
+        }
+    }
+
+    for pci_dev in pci.find_by_id(ne2000::NE2000_PCI_VENDOR, &[ne2000::NE2000_PCI_DEVICE]) {
+        match Ne2000NetDevice::new(pci_dev) {
+            Ok(dev) => return Some(Box::new(dev)),
+            Err(e)  => println!("Could not initialize NE2000 {}: {}", pci_dev.addr, e),
+        }
+    }
+
+    None
+}
+
+/// Smallest Ethernet frame, without FCS. Shorter frames are padded.
+pub const ETH_MIN_FRAME: usize = 60;
+/// Largest Ethernet frame, without FCS
+pub const ETH_MAX_FRAME: usize = 1514;
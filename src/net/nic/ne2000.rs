@@ -0,0 +1,268 @@
+use crate::errors::ErrorCode;
+use crate::io::ioport::{inb, inw, outb, outw};
+use crate::net::MacAddr;
+use crate::net::nic::{NetDevice, ETH_MAX_FRAME, ETH_MIN_FRAME};
+use crate::pci::{self, PciDevice};
+
+/// NE2000-compatible (DP8390) Ethernet controller on PCI, such as the RTL8029.
+///
+/// Frames are copied to and from the card's buffer memory with remote DMA
+/// through the data port, 16 bits at a time. Interrupts are masked.
+pub struct Ne2000NetDevice {
+    io_base: u16, //< Base I/O port of registers
+    mac: MacAddr,
+}
+
+impl Ne2000NetDevice {
+    pub fn new(pci_dev: &PciDevice) -> Result<Ne2000NetDevice, ErrorCode> {
+        let io_base = pci_dev.io_bar(0).ok_or(ErrorCode::Unsupported)?;
+        pci_dev.addr.enable(pci::PCI_COMMAND_IO);
+
+        let mut dev = Ne2000NetDevice {
+            io_base,
+            mac: MacAddr::default(),
+        };
+
+        /* Reading the reset port triggers a reset */
+        outb(io_base + NE_RESET, inb(io_base + NE_RESET));
+        dev.wait_isr(NE_ISR_RST)?;
+
+        outb(io_base + NE_CR,    NE_CR_PAGE0 | NE_CR_NODMA | NE_CR_STP);
+        outb(io_base + NE_DCR,   NE_DCR_WTS | NE_DCR_LS | NE_DCR_FT1);
+        outb(io_base + NE_RBCR0, 0);
+        outb(io_base + NE_RBCR1, 0);
+        outb(io_base + NE_IMR,   0);
+        outb(io_base + NE_ISR,   0xFF);
+        outb(io_base + NE_RCR,   NE_RCR_MON);
+        outb(io_base + NE_TCR,   NE_TCR_LOOPBACK);
+
+        /* Each byte of the station address PROM is stored twice */
+        let mut prom = [0u8; 12];
+        dev.remote_read(0, &mut prom)?;
+        let mut mac = [0u8; 6];
+        for (byte, word) in mac.iter_mut().zip(prom.chunks_exact(2)) {
+            *byte = word[0];
+        }
+        dev.mac = MacAddr(mac);
+
+        outb(io_base + NE_PSTART, NE_RX_START);
+        outb(io_base + NE_PSTOP,  NE_RX_STOP);
+        outb(io_base + NE_BNRY,   NE_RX_START);
+        outb(io_base + NE_TPSR,   NE_TX_START);
+
+        outb(io_base + NE_CR, NE_CR_PAGE1 | NE_CR_NODMA | NE_CR_STP);
+        for (idx, byte) in mac.iter().enumerate() {
+            outb(io_base + NE_PAR0 + idx as u16, *byte);
+        }
+        for idx in 0..8 {
+            outb(io_base + NE_MAR0 + idx, 0);
+        }
+        outb(io_base + NE_CURR, NE_RX_START + 1);
+
+        outb(io_base + NE_CR,  NE_CR_PAGE0 | NE_CR_NODMA | NE_CR_STA);
+        outb(io_base + NE_ISR, 0xFF);
+        outb(io_base + NE_TCR, 0);
+        outb(io_base + NE_RCR, NE_RCR_AB);
+
+        Ok(dev)
+    }
+
+    /// Wait for, then acknowledge, an interrupt status bit
+    fn wait_isr(&self, bit: u8) -> Result<(), ErrorCode> {
+        for _ in 0..NE_TIMEOUT {
+            if (inb(self.io_base + NE_ISR) & bit) != 0 {
+                outb(self.io_base + NE_ISR, bit);
+                return Ok(());
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Set up a remote DMA transfer
+    ///
+    /// # Arguments
+    /// * addr - Address in card memory
+    /// * len - Length of transfer, even
+    /// * cmd - NE_CR_RREAD or NE_CR_RWRITE
+    fn remote_start(&self, addr: u16, len: usize, cmd: u8) {
+        outb(self.io_base + NE_RBCR0, len as u8);
+        outb(self.io_base + NE_RBCR1, (len >> 8) as u8);
+        outb(self.io_base + NE_RSAR0, addr as u8);
+        outb(self.io_base + NE_RSAR1, (addr >> 8) as u8);
+        outb(self.io_base + NE_CR,    NE_CR_PAGE0 | cmd | NE_CR_STA);
+    }
+
+    /// Copy data out of card memory. Reads wrap around the receive ring.
+    fn remote_read(&self, addr: u16, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let len = buf.len().next_multiple_of(2);
+        self.remote_start(addr, len, NE_CR_RREAD);
+
+        for idx in (0..len).step_by(2) {
+            let word = inw(self.io_base + NE_DATA).to_le_bytes();
+            buf[idx] = word[0];
+            if (idx + 1) < buf.len() {
+                buf[idx + 1] = word[1];
+            }
+        }
+
+        self.wait_isr(NE_ISR_RDC)
+    }
+
+    /// Copy data into card memory
+    fn remote_write(&self, addr: u16, data: &[u8], len: usize) -> Result<(), ErrorCode> {
+        let len = len.next_multiple_of(2);
+        self.remote_start(addr, len, NE_CR_RWRITE);
+
+        for idx in (0..len).step_by(2) {
+            let lo = data.get(idx).copied().unwrap_or(0);
+            let hi = data.get(idx + 1).copied().unwrap_or(0);
+            outw(self.io_base + NE_DATA, u16::from_le_bytes([lo, hi]));
+        }
+
+        self.wait_isr(NE_ISR_RDC)
+    }
+
+    /// Discard all frames in the receive ring
+    fn reset_rx(&self, curr: u8) {
+        let bnry = if curr == NE_RX_START { NE_RX_STOP - 1 } else { curr - 1 };
+        outb(self.io_base + NE_BNRY, bnry);
+    }
+}
+
+impl Drop for Ne2000NetDevice {
+    fn drop(&mut self) {
+        outb(self.io_base + NE_CR, NE_CR_PAGE0 | NE_CR_NODMA | NE_CR_STP);
+    }
+}
+
+impl NetDevice for Ne2000NetDevice {
+    fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), ErrorCode> {
+        if frame.len() > ETH_MAX_FRAME {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let len = frame.len().max(ETH_MIN_FRAME);
+        self.remote_write((NE_TX_START as u16) << 8, frame, len)?;
+
+        outb(self.io_base + NE_TPSR,  NE_TX_START);
+        outb(self.io_base + NE_TBCR0, len as u8);
+        outb(self.io_base + NE_TBCR1, (len >> 8) as u8);
+        outb(self.io_base + NE_CR,    NE_CR_PAGE0 | NE_CR_NODMA | NE_CR_TXP | NE_CR_STA);
+
+        for _ in 0..NE_TIMEOUT {
+            let isr = inb(self.io_base + NE_ISR);
+            if (isr & (NE_ISR_PTX | NE_ISR_TXE)) != 0 {
+                outb(self.io_base + NE_ISR, NE_ISR_PTX | NE_ISR_TXE);
+                return if (isr & NE_ISR_PTX) != 0 { Ok(()) } else { Err(ErrorCode::Unspecified) };
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize> {
+        outb(self.io_base + NE_CR, NE_CR_PAGE1 | NE_CR_NODMA | NE_CR_STA);
+        let curr = inb(self.io_base + NE_CURR);
+        outb(self.io_base + NE_CR, NE_CR_PAGE0 | NE_CR_NODMA | NE_CR_STA);
+
+        let mut page = inb(self.io_base + NE_BNRY) + 1;
+        if page >= NE_RX_STOP {
+            page = NE_RX_START;
+        }
+        if page == curr {
+            return None;
+        }
+
+        /* Each frame is preceded by status, next page, and a count that
+         * includes this header */
+        let mut hdr = [0u8; 4];
+        self.remote_read((page as u16) << 8, &mut hdr).ok()?;
+        let next  = hdr[1];
+        let count = u16::from_le_bytes([hdr[2], hdr[3]]) as usize;
+
+        if !(NE_RX_START..NE_RX_STOP).contains(&next) {
+            /* Ring is corrupt, drop everything */
+            self.reset_rx(curr);
+            return None;
+        }
+
+        let mut result = None;
+        if (hdr[0] & NE_RSR_PRX) != 0 && count > 4 {
+            let len = (count - 4).min(buf.len()).min(ETH_MAX_FRAME);
+            if self.remote_read(((page as u16) << 8) + 4, &mut buf[..len]).is_ok() {
+                result = Some(len);
+            }
+        }
+
+        /* Boundary trails the next frame by one page */
+        let bnry = if next == NE_RX_START { NE_RX_STOP - 1 } else { next - 1 };
+        outb(self.io_base + NE_BNRY, bnry);
+
+        result
+    }
+}
+
+pub const NE2000_PCI_VENDOR: u16 = 0x10EC;
+pub const NE2000_PCI_DEVICE: u16 = 0x8029; //< RTL8029
+
+const NE_TIMEOUT: usize = 1_000_000;
+
+/* Card memory, in 256-byte pages. 16 KiB of memory starts at 0x4000. */
+const NE_TX_START: u8 = 0x40; //< Transmit buffer, 6 pages
+const NE_RX_START: u8 = 0x46; //< First page of receive ring
+const NE_RX_STOP: u8  = 0x80; //< Page after end of receive ring
+
+/* Page 0 registers */
+const NE_CR: u16     = 0x00; //< Command, all pages
+const NE_PSTART: u16 = 0x01; //< Receive ring start page (write)
+const NE_PSTOP: u16  = 0x02; //< Receive ring stop page (write)
+const NE_BNRY: u16   = 0x03; //< Boundary
+const NE_TPSR: u16   = 0x04; //< Transmit page start (write)
+const NE_TBCR0: u16  = 0x05; //< Transmit byte count (write)
+const NE_TBCR1: u16  = 0x06;
+const NE_ISR: u16    = 0x07; //< Interrupt status
+const NE_RSAR0: u16  = 0x08; //< Remote start address (write)
+const NE_RSAR1: u16  = 0x09;
+const NE_RBCR0: u16  = 0x0A; //< Remote byte count (write)
+const NE_RBCR1: u16  = 0x0B;
+const NE_RCR: u16    = 0x0C; //< Receive configuration (write)
+const NE_TCR: u16    = 0x0D; //< Transmit configuration (write)
+const NE_DCR: u16    = 0x0E; //< Data configuration (write)
+const NE_IMR: u16    = 0x0F; //< Interrupt mask (write)
+const NE_DATA: u16   = 0x10; //< Remote DMA data port
+const NE_RESET: u16  = 0x1F; //< Reset port
+
+/* Page 1 registers */
+const NE_PAR0: u16 = 0x01; //< Physical address
+const NE_CURR: u16 = 0x07; //< Current receive page
+const NE_MAR0: u16 = 0x08; //< Multicast address filter
+
+const NE_CR_STP: u8    = 1 << 0; //< Stop
+const NE_CR_STA: u8    = 1 << 1; //< Start
+const NE_CR_TXP: u8    = 1 << 2; //< Transmit packet
+const NE_CR_RREAD: u8  = 1 << 3; //< Remote read
+const NE_CR_RWRITE: u8 = 2 << 3; //< Remote write
+const NE_CR_NODMA: u8  = 4 << 3; //< Abort/complete remote DMA
+const NE_CR_PAGE0: u8  = 0 << 6;
+const NE_CR_PAGE1: u8  = 1 << 6;
+
+const NE_ISR_PTX: u8 = 1 << 1; //< Packet transmitted
+const NE_ISR_TXE: u8 = 1 << 3; //< Transmit error
+const NE_ISR_RDC: u8 = 1 << 6; //< Remote DMA complete
+const NE_ISR_RST: u8 = 1 << 7; //< Reset status
+
+const NE_DCR_WTS: u8 = 1 << 0; //< Word-wide transfers
+const NE_DCR_LS: u8  = 1 << 3; //< Normal operation, not loopback
+const NE_DCR_FT1: u8 = 1 << 6; //< FIFO threshold of 8 bytes
+
+const NE_RCR_AB: u8  = 1 << 2; //< Accept broadcast
+const NE_RCR_MON: u8 = 1 << 5; //< Monitor mode, don't buffer frames
+
+const NE_TCR_LOOPBACK: u8 = 1 << 1; //< Internal loopback
+
+const NE_RSR_PRX: u8 = 1 << 0; //< Packet received intact
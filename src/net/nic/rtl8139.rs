@@ -0,0 +1,190 @@
+extern crate alloc;
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::errors::ErrorCode;
+use crate::io::ioport::{inb, inl, outb, outl, outw};
+use crate::net::MacAddr;
+use crate::net::nic::{NetDevice, ETH_MAX_FRAME, ETH_MIN_FRAME};
+use crate::pci::{self, PciDevice};
+
+/// Realtek RTL8139 Fast Ethernet controller, accessed through its I/O BAR.
+///
+/// Interrupts are masked; the receive ring and transmit status registers are
+/// polled. Only one frame is transmitted at a time.
+pub struct Rtl8139NetDevice {
+    io_base: u16,     //< Base I/O port of registers
+    mac: MacAddr,
+    mem: NonNull<u8>, //< Receive ring followed by transmit buffer
+    rx_offset: usize, //< Offset into receive ring of next frame
+    tx_cur: usize,    //< Next transmit descriptor to be used
+}
+
+impl Rtl8139NetDevice {
+    pub fn new(pci_dev: &PciDevice) -> Result<Rtl8139NetDevice, ErrorCode> {
+        let io_base = pci_dev.io_bar(0).ok_or(ErrorCode::Unsupported)?;
+        pci_dev.addr.enable(pci::PCI_COMMAND_IO | pci::PCI_COMMAND_BUS_MASTER);
+
+        /* Wake up, then reset */
+        outb(io_base + RTL_REG_CONFIG1, 0);
+        outb(io_base + RTL_REG_CR, RTL_CR_RST);
+        let mut reset = false;
+        for _ in 0..RTL_TIMEOUT {
+            if (inb(io_base + RTL_REG_CR) & RTL_CR_RST) == 0 {
+                reset = true;
+                break;
+            }
+        }
+        if !reset {
+            return Err(ErrorCode::Timeout);
+        }
+
+        let mut mac = [0u8; 6];
+        for (idx, byte) in mac.iter_mut().enumerate() {
+            *byte = inb(io_base + RTL_REG_IDR0 + idx as u16);
+        }
+
+        let mem = NonNull::new(unsafe { alloc_zeroed(Self::mem_layout()) }).ok_or(ErrorCode::NoSpace)?;
+
+        outw(io_base + RTL_REG_IMR, 0);
+        outl(io_base + RTL_REG_RBSTART, mem.as_ptr() as u32);
+        outb(io_base + RTL_REG_CR, RTL_CR_RE | RTL_CR_TE);
+        outl(io_base + RTL_REG_RCR, RTL_RCR_APM | RTL_RCR_AB | RTL_RCR_WRAP | RTL_RCR_MXDMA_UNLIMITED |
+                                    RTL_RCR_RXFTH_NONE);
+        outl(io_base + RTL_REG_TCR, RTL_TCR_IFG_STD | RTL_TCR_MXDMA_2048);
+
+        Ok(Rtl8139NetDevice {
+            io_base,
+            mac: MacAddr(mac),
+            mem,
+            rx_offset: 0,
+            tx_cur: 0,
+        })
+    }
+
+    fn mem_layout() -> Layout {
+        Layout::from_size_align(RTL_MEM_SIZE, RTL_MEM_ALIGN).unwrap()
+    }
+}
+
+impl Drop for Rtl8139NetDevice {
+    fn drop(&mut self) {
+        /* Stop DMA before releasing memory the device may access */
+        outb(self.io_base + RTL_REG_CR, 0);
+        unsafe { dealloc(self.mem.as_ptr(), Self::mem_layout()); }
+    }
+}
+
+impl NetDevice for Rtl8139NetDevice {
+    fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), ErrorCode> {
+        if frame.len() > ETH_MAX_FRAME {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let buf = unsafe { self.mem.as_ptr().add(RTL_MEM_TX_BUF) };
+        let len = frame.len().max(ETH_MIN_FRAME);
+        unsafe {
+            ptr::copy_nonoverlapping(frame.as_ptr(), buf, frame.len());
+            ptr::write_bytes(buf.add(frame.len()), 0, len - frame.len());
+        }
+        fence(Ordering::SeqCst);
+
+        /* Writing the size clears OWN, starting the transfer */
+        let desc = self.tx_cur as u16 * 4;
+        self.tx_cur = (self.tx_cur + 1) % RTL_TX_DESCS;
+        outl(self.io_base + RTL_REG_TSAD0 + desc, buf as u32);
+        outl(self.io_base + RTL_REG_TSD0 + desc, len as u32);
+
+        for _ in 0..RTL_TIMEOUT {
+            let tsd = inl(self.io_base + RTL_REG_TSD0 + desc);
+            if (tsd & RTL_TSD_TOK) != 0 {
+                return Ok(());
+            }
+            if (tsd & (RTL_TSD_TABT | RTL_TSD_TUN)) != 0 {
+                return Err(ErrorCode::Unspecified);
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if (inb(self.io_base + RTL_REG_CR) & RTL_CR_BUFE) != 0 {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+
+        /* Each frame is preceded by a status word and a length that includes
+         * the CRC. WRAP mode lets a frame run past the end of the ring. */
+        let frame = unsafe { self.mem.as_ptr().add(self.rx_offset) };
+        let (status, length) = unsafe {
+            (u16::from_le(ptr::read_volatile(frame as *const u16)),
+             u16::from_le(ptr::read_volatile(frame.add(2) as *const u16)) as usize)
+        };
+
+        let mut result = None;
+        if (status & RTL_RX_ROK) != 0 && (RTL_CRC_SIZE..=(ETH_MAX_FRAME + RTL_CRC_SIZE)).contains(&length) {
+            let len = (length - RTL_CRC_SIZE).min(buf.len());
+            unsafe { ptr::copy_nonoverlapping(frame.add(4), buf.as_mut_ptr(), len); }
+            result = Some(len);
+        }
+
+        self.rx_offset = (self.rx_offset + length + 4 + 3) & !3;
+        self.rx_offset %= RTL_RX_RING_SIZE;
+        /* CAPR lags the actual read pointer by 16 bytes */
+        outw(self.io_base + RTL_REG_CAPR, (self.rx_offset as u16).wrapping_sub(16));
+
+        result
+    }
+}
+
+pub const RTL8139_PCI_VENDOR: u16 = 0x10EC;
+pub const RTL8139_PCI_DEVICE: u16 = 0x8139;
+
+const RTL_RX_RING_SIZE: usize = 8192; //< Receive ring, excluding WRAP overrun area
+const RTL_TX_DESCS: usize     = 4;
+const RTL_CRC_SIZE: usize     = 4;
+const RTL_TIMEOUT: usize      = 1_000_000;
+
+/* Layout of driver memory */
+const RTL_MEM_TX_BUF: usize = (RTL_RX_RING_SIZE + 16 + ETH_MAX_FRAME + 4).next_multiple_of(4);
+const RTL_MEM_SIZE: usize   = RTL_MEM_TX_BUF + ETH_MAX_FRAME;
+const RTL_MEM_ALIGN: usize  = 16;
+
+const RTL_REG_IDR0: u16    = 0x00; //< MAC address
+const RTL_REG_TSD0: u16    = 0x10; //< Transmit status of descriptor 0
+const RTL_REG_TSAD0: u16   = 0x20; //< Transmit start address of descriptor 0
+const RTL_REG_RBSTART: u16 = 0x30; //< Receive buffer start address
+const RTL_REG_CR: u16      = 0x37; //< Command
+const RTL_REG_CAPR: u16    = 0x38; //< Current address of packet read
+const RTL_REG_IMR: u16     = 0x3C; //< Interrupt mask
+const RTL_REG_TCR: u16     = 0x40; //< Transmit configuration
+const RTL_REG_RCR: u16     = 0x44; //< Receive configuration
+const RTL_REG_CONFIG1: u16 = 0x52;
+
+const RTL_CR_BUFE: u8 = 1 << 0; //< Receive buffer empty
+const RTL_CR_TE: u8   = 1 << 2; //< Transmitter enable
+const RTL_CR_RE: u8   = 1 << 3; //< Receiver enable
+const RTL_CR_RST: u8  = 1 << 4; //< Reset
+
+const RTL_RCR_APM: u32             = 1 << 1;  //< Accept physical match
+const RTL_RCR_AB: u32              = 1 << 3;  //< Accept broadcast
+const RTL_RCR_WRAP: u32            = 1 << 7;  //< Don't wrap frames at end of ring
+const RTL_RCR_MXDMA_UNLIMITED: u32 = 7 << 8;
+const RTL_RCR_RXFTH_NONE: u32      = 7 << 13; //< No receive FIFO threshold
+
+const RTL_TCR_MXDMA_2048: u32 = 7 << 8;
+const RTL_TCR_IFG_STD: u32    = 3 << 24; //< Standard inter-frame gap
+
+const RTL_TSD_TOK: u32  = 1 << 15; //< Transmit OK
+const RTL_TSD_TUN: u32  = 1 << 14; //< Transmit FIFO underrun
+const RTL_TSD_TABT: u32 = 1 << 30; //< Transmit aborted
+
+const RTL_RX_ROK: u16 = 1 << 0; //< Receive OK
@@ -0,0 +1,528 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+use core::fmt::Write;
+use core::mem;
+
+use crate::bios::BiosCall;
+use crate::errors::ErrorCode;
+use crate::io::output;
+use crate::net::Ipv4Addr;
+use crate::storage::fs::{File, FileAttribute, Filesystem};
+
+/// PXE API, as provided by the ROM that loaded us or another resident PXE stack
+pub struct Pxe {
+    entry_seg: u16, //< Segment of real-mode API entry point
+    entry_off: u16, //< Offset of real-mode API entry point
+}
+
+impl Pxe {
+    /// Search base memory for the `!PXE` structure, falling back to the older
+    /// `PXENV+` structure.
+    pub fn find() -> Option<Pxe> {
+        let mut pxenv = None;
+
+        for addr in (PXE_SCAN_START..PXE_SCAN_END).step_by(16) {
+            let ptr = addr as *const u8;
+            let sig = unsafe { core::slice::from_raw_parts(ptr, 6) };
+
+            if &sig[..4] == PXE_SIG && checksum_ok(ptr, unsafe { *ptr.add(4) } as usize) {
+                let pxe: PxeStruct = unsafe { core::ptr::read_unaligned(ptr as *const _) };
+                return Some(Pxe {
+                    entry_seg: pxe.entry_sp_seg,
+                    entry_off: pxe.entry_sp_off,
+                });
+            }
+
+            if pxenv.is_none() && sig == PXENV_SIG && checksum_ok(ptr, unsafe { *ptr.add(8) } as usize) {
+                pxenv = Some(unsafe { core::ptr::read_unaligned(ptr as *const PxenvStruct) });
+            }
+        }
+
+        /* !PXE wasn't found, PXE 2.1+ should still point to it from PXENV+ */
+        pxenv.map(|pxenv| {
+            if pxenv.version >= 0x0201 && pxenv.pxe_ptr_seg != 0 {
+                let pxe: PxeStruct = unsafe {
+                    core::ptr::read_unaligned(linear(pxenv.pxe_ptr_seg, pxenv.pxe_ptr_off) as *const _)
+                };
+                Pxe {
+                    entry_seg: pxe.entry_sp_seg,
+                    entry_off: pxe.entry_sp_off,
+                }
+            } else {
+                Pxe {
+                    entry_seg: pxenv.rm_entry_seg,
+                    entry_off: pxenv.rm_entry_off,
+                }
+            }
+        })
+    }
+
+    /// Call a PXE API function
+    ///
+    /// # Arguments
+    /// * opcode - API function number
+    /// * params - Parameter structure, beginning with a status word. Must
+    ///   reside below 64 KiB.
+    fn call<T>(&self, opcode: u16, params: &mut T) -> Result<(), ErrorCode> {
+        let addr = params as *mut T as usize;
+        if (addr + mem::size_of::<T>()) > 0x10000 {
+            println!("PXE parameters too high!");
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let mut bcall = BiosCall {
+            ebx: opcode as u32,
+            edi: addr as u32,
+            ..Default::default()
+        };
+
+        unsafe { bcall.far_call(self.entry_seg, self.entry_off); }
+
+        let status = unsafe { core::ptr::read_unaligned(addr as *const u16) };
+        if (bcall.eax & 0xFFFF) as u16 == PXENV_EXIT_SUCCESS && status == PXENV_STATUS_SUCCESS {
+            Ok(())
+        } else if status == PXENV_STATUS_TFTP_FILE_NOT_FOUND {
+            Err(ErrorCode::FileNotFound)
+        } else {
+            println!("PXE call {:04x} failed, status {:04x}", opcode, status);
+            Err(ErrorCode::ReadFailure)
+        }
+    }
+
+    /// Get boot server and gateway addresses from the cached DHCP/BOOTP reply
+    pub fn boot_server(&self) -> Result<(Ipv4Addr, Ipv4Addr), ErrorCode> {
+        for packet_type in [PXENV_PACKET_TYPE_CACHED_REPLY, PXENV_PACKET_TYPE_DHCP_ACK] {
+            let mut params = PxenvGetCachedInfo {
+                status: 0,
+                packet_type,
+                buffer_size: 0,
+                buffer_off: 0,
+                buffer_seg: 0,
+                buffer_limit: 0,
+            };
+            if self.call(PXENV_GET_CACHED_INFO, &mut params).is_err() {
+                continue;
+            }
+
+            /* PXE returns a pointer to its own copy of the packet */
+            let size = params.buffer_size as usize;
+            if size < BOOTP_FILE_OFFSET {
+                continue;
+            }
+            let packet = unsafe {
+                core::slice::from_raw_parts(linear(params.buffer_seg, params.buffer_off) as *const u8, size)
+            };
+
+            let server  = Ipv4Addr::from_slice(&packet[BOOTP_SIADDR_OFFSET..]);
+            let gateway = Ipv4Addr::from_slice(&packet[BOOTP_GIADDR_OFFSET..]);
+            if !server.is_unspecified() {
+                return Ok((server, gateway));
+            }
+        }
+
+        Err(ErrorCode::FileNotFound)
+    }
+
+    fn tftp_get_fsize(&self, server: Ipv4Addr, gateway: Ipv4Addr, path: &str) -> Result<usize, ErrorCode> {
+        let mut params = PxenvTftpGetFsize {
+            status: 0,
+            server: server.0,
+            gateway: gateway.0,
+            filename: tftp_filename(path)?,
+            file_size: 0,
+        };
+        self.call(PXENV_TFTP_GET_FSIZE, &mut params)?;
+
+        Ok(params.file_size as usize)
+    }
+
+    /// Open a file for reading, returning the negotiated packet size
+    fn tftp_open(&self, server: Ipv4Addr, gateway: Ipv4Addr, path: &str) -> Result<usize, ErrorCode> {
+        let mut params = PxenvTftpOpen {
+            status: 0,
+            server: server.0,
+            gateway: gateway.0,
+            filename: tftp_filename(path)?,
+            port: TFTP_PORT.to_be(),
+            packet_size: PXE_TFTP_PACKET_SIZE as u16,
+        };
+        self.call(PXENV_TFTP_OPEN, &mut params)?;
+
+        Ok(params.packet_size as usize)
+    }
+
+    /// Read the next packet of the open file, returning its length
+    fn tftp_read(&self, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        let addr = buf.as_mut_ptr() as usize;
+        let mut params = PxenvTftpRead {
+            status: 0,
+            packet_number: 0,
+            buffer_size: 0,
+            buffer_off: (addr & 0x0F) as u16,
+            buffer_seg: (addr >> 4) as u16,
+        };
+        self.call(PXENV_TFTP_READ, &mut params)?;
+
+        Ok((params.buffer_size as usize).min(buf.len()))
+    }
+
+    fn tftp_close(&self) {
+        let mut status: u16 = 0;
+        _ = self.call(PXENV_TFTP_CLOSE, &mut status);
+    }
+}
+
+/// Open TFTP transfer. The PXE API supports only one at a time, and only
+/// sequential reads.
+struct TftpSession {
+    server: Ipv4Addr,
+    path: String,
+    packet_size: usize,  //< Negotiated packet size
+    packet: Vec<u8>,     //< Data of most recently read packet
+    packet_len: usize,   //< Valid bytes in `packet`
+    packet_start: usize, //< Offset into file of `packet`
+    done: bool,          //< Final (short) packet has been read
+}
+
+/// Files served by a TFTP server, accessed through the PXE API.
+///
+/// Paths are relative to the TFTP root of the boot server, or of another
+/// server if prefixed by `//<IPv4 address>`, as in `tftp://10.0.2.2/kernel`.
+pub struct PxeTftpFilesystem {
+    pxe: Pxe,
+    server: Ipv4Addr,  //< Default server, from DHCP
+    gateway: Ipv4Addr, //< Relay agent/gateway, from DHCP
+
+    rootdir: PxeTftpFile,
+
+    session: RefCell<Option<TftpSession>>,
+
+    rc: Weak<RefCell<Self>>,
+}
+
+impl Filesystem for PxeTftpFilesystem {
+    fn get_root(&self) -> &dyn File {
+        &self.rootdir
+    }
+
+    fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        /* TFTP has no directories to be relative to */
+        let _ = start_dir;
+
+        let (server, path) = match path.strip_prefix("//") {
+            Some(rest) => {
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                match Ipv4Addr::parse(host) {
+                    Some(server) => (server, path),
+                    None         => {
+                        println!("TFTP server must be an IPv4 address: {}", host);
+                        return Err(ErrorCode::FileNotFound);
+                    }
+                }
+            },
+            None => (self.server, path.trim_start_matches('/'))
+        };
+
+        if path.is_empty() {
+            return Err(ErrorCode::FileNotFound);
+        }
+
+        /* No transfer may be open while querying the size */
+        self.close_session();
+        let size = match self.pxe.tftp_get_fsize(server, self.gateway, path) {
+            Ok(size)                      => size,
+            Err(ErrorCode::FileNotFound)  => return Err(ErrorCode::FileNotFound),
+            Err(_)                        => self.measure(server, path)?,
+        };
+
+        Ok(Box::new(PxeTftpFile {
+            server,
+            path: path.to_string(),
+            size,
+            attr: FileAttribute::File as u32,
+            fs: self.rc.clone(),
+        }))
+    }
+}
+
+impl PxeTftpFilesystem {
+    /// Create a filesystem using the boot server from the cached DHCP reply
+    pub fn new(pxe: Pxe) -> Result<Rc<RefCell<Self>>, ErrorCode> {
+        let (server, gateway) = pxe.boot_server()?;
+        println!("TFTP server: {}", server);
+
+        Ok(Rc::new_cyclic(|me| {
+            RefCell::new(PxeTftpFilesystem {
+                pxe,
+                server,
+                gateway,
+                rootdir: PxeTftpFile {
+                    server,
+                    path: String::new(),
+                    size: 0,
+                    attr: FileAttribute::Directory as u32,
+                    fs: me.clone(),
+                },
+                session: RefCell::new(None),
+                rc: me.clone(),
+            })
+        }))
+    }
+
+    fn close_session(&self) {
+        if self.session.borrow_mut().take().is_some() {
+            self.pxe.tftp_close();
+        }
+    }
+
+    fn open_session(&self, server: Ipv4Addr, path: &str) -> Result<(), ErrorCode> {
+        self.close_session();
+
+        let packet_size = self.pxe.tftp_open(server, self.gateway, path)?;
+        *self.session.borrow_mut() = Some(TftpSession {
+            server,
+            path: path.to_string(),
+            packet_size,
+            packet: vec![0; packet_size],
+            packet_len: 0,
+            packet_start: 0,
+            done: false,
+        });
+
+        Ok(())
+    }
+
+    /// Determine the size of a file by reading all of it, for servers that
+    /// don't support the transfer size option.
+    fn measure(&self, server: Ipv4Addr, path: &str) -> Result<usize, ErrorCode> {
+        self.open_session(server, path)?;
+
+        let mut session = self.session.borrow_mut();
+        let session = session.as_mut().unwrap();
+        while !session.done {
+            self.next_packet(session)?;
+        }
+
+        Ok(session.packet_start + session.packet_len)
+    }
+
+    fn next_packet(&self, session: &mut TftpSession) -> Result<(), ErrorCode> {
+        session.packet_start += session.packet_len;
+        session.packet_len = self.pxe.tftp_read(&mut session.packet)?;
+        session.done = session.packet_len < session.packet_size;
+
+        Ok(())
+    }
+
+    /// Read from a file, reopening it if data before the current packet is
+    /// requested
+    fn read(&self, server: Ipv4Addr, path: &str, offset: usize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let reopen = match &*self.session.borrow() {
+            Some(session) => session.server != server || session.path != path || offset < session.packet_start,
+            None          => true
+        };
+        if reopen {
+            self.open_session(server, path)?;
+        }
+
+        let mut session = self.session.borrow_mut();
+        let session = session.as_mut().unwrap();
+
+        let mut pos = 0;
+        while pos < buf.len() {
+            let abs = offset + pos;
+            let end = session.packet_start + session.packet_len;
+
+            if abs >= session.packet_start && abs < end {
+                let skip = abs - session.packet_start;
+                let len  = (end - abs).min(buf.len() - pos);
+                buf[pos..(pos + len)].copy_from_slice(&session.packet[skip..(skip + len)]);
+                pos += len;
+            } else if session.done {
+                return Err(ErrorCode::OutOfBounds);
+            } else {
+                self.next_packet(session)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct PxeTftpFile {
+    server: Ipv4Addr,
+    path: String,
+    size: usize,
+    attr: u32,
+
+    fs: Weak<RefCell<PxeTftpFilesystem>>,
+}
+
+impl File for PxeTftpFile {
+    fn get_size(&self) -> usize {
+        self.size
+    }
+
+    fn get_attr(&self) -> u32 {
+        self.attr
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if (offset as usize + buf.len()) > self.size {
+            println!("PxeTftpFile: Attempt to read past end of file");
+            return Err(ErrorCode::OutOfBounds);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let fs = self.fs.upgrade().expect("Could not upgrade PxeTftpFile::fs");
+        let fs = fs.borrow();
+        fs.read(self.server, &self.path, offset as usize, buf)
+    }
+
+    fn close(&self) {
+
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Convert a real-mode segment:offset pointer to a linear address
+fn linear(seg: u16, off: u16) -> usize {
+    ((seg as usize) << 4) + off as usize
+}
+
+/// Check that the bytes of a structure sum to zero
+fn checksum_ok(ptr: *const u8, len: usize) -> bool {
+    let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+    len > 0 && data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
+
+/// Convert a path to the NUL-terminated form used by the PXE TFTP API
+fn tftp_filename(path: &str) -> Result<[u8; PXE_TFTP_FILENAME_LEN], ErrorCode> {
+    let mut name = [0u8; PXE_TFTP_FILENAME_LEN];
+    if path.len() >= name.len() {
+        return Err(ErrorCode::OutOfBounds);
+    }
+    name[..path.len()].copy_from_slice(path.as_bytes());
+
+    Ok(name)
+}
+
+/// `!PXE` structure, only the fields used here
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct PxeStruct {
+    signature: [u8; 4],   //< "!PXE"
+    length: u8,           //< Length of structure in bytes
+    checksum: u8,         //< Makes the structure's bytes sum to zero
+    revision: u8,
+    _reserved: u8,
+    undi_rom_id: u32,     //< SEGOFF16 of UNDI ROM ID structure
+    base_rom_id: u32,     //< SEGOFF16 of base code ROM ID structure
+    entry_sp_off: u16,    //< Real-mode API entry point
+    entry_sp_seg: u16,
+}
+const _PXE_STRUCT_SZ_TEST: [u8; 20] = [0; mem::size_of::<PxeStruct>()];
+
+/// `PXENV+` structure, only the fields used here
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct PxenvStruct {
+    signature: [u8; 6], //< "PXENV+"
+    version: u16,       //< API version, BCD
+    length: u8,         //< Length of structure in bytes
+    checksum: u8,       //< Makes the structure's bytes sum to zero
+    rm_entry_off: u16,  //< Real-mode API entry point
+    rm_entry_seg: u16,
+    pm_offset: u32,
+    pm_selector: u16,
+    stack_seg: u16,
+    stack_size: u16,
+    bc_code_seg: u16,
+    bc_code_size: u16,
+    bc_data_seg: u16,
+    bc_data_size: u16,
+    undi_data_seg: u16,
+    undi_data_size: u16,
+    undi_code_seg: u16,
+    undi_code_size: u16,
+    pxe_ptr_off: u16,   //< Pointer to !PXE structure, PXE 2.1+
+    pxe_ptr_seg: u16,
+}
+const _PXENV_STRUCT_SZ_TEST: [u8; 44] = [0; mem::size_of::<PxenvStruct>()];
+
+#[repr(C, packed(1))]
+struct PxenvGetCachedInfo {
+    status: u16,
+    packet_type: u16,  //< PXENV_PACKET_TYPE_*
+    buffer_size: u16,  //< 0 to get a pointer to the PXE stack's copy
+    buffer_off: u16,
+    buffer_seg: u16,
+    buffer_limit: u16,
+}
+
+#[repr(C, packed(1))]
+struct PxenvTftpGetFsize {
+    status: u16,
+    server: [u8; 4],
+    gateway: [u8; 4],
+    filename: [u8; PXE_TFTP_FILENAME_LEN],
+    file_size: u32,
+}
+
+#[repr(C, packed(1))]
+struct PxenvTftpOpen {
+    status: u16,
+    server: [u8; 4],
+    gateway: [u8; 4],
+    filename: [u8; PXE_TFTP_FILENAME_LEN],
+    port: u16,        //< UDP port of server, network byte order
+    packet_size: u16, //< Requested, then negotiated, packet size
+}
+
+#[repr(C, packed(1))]
+struct PxenvTftpRead {
+    status: u16,
+    packet_number: u16,
+    buffer_size: u16, //< Bytes received
+    buffer_off: u16,  //< Buffer of at least the negotiated packet size
+    buffer_seg: u16,
+}
+
+const PXE_SCAN_START: usize = 0x10000;
+const PXE_SCAN_END: usize   = 0xA0000;
+const PXE_SIG: &[u8; 4]     = b"!PXE";
+const PXENV_SIG: &[u8; 6]   = b"PXENV+";
+
+const PXE_TFTP_FILENAME_LEN: usize = 128;
+const PXE_TFTP_PACKET_SIZE: usize  = 1432; //< Requested TFTP block size, fits a 1500 byte MTU
+const TFTP_PORT: u16               = 69;
+
+const PXENV_TFTP_OPEN: u16       = 0x0020;
+const PXENV_TFTP_CLOSE: u16      = 0x0021;
+const PXENV_TFTP_READ: u16       = 0x0022;
+const PXENV_TFTP_GET_FSIZE: u16  = 0x0025;
+const PXENV_GET_CACHED_INFO: u16 = 0x0071;
+
+const PXENV_EXIT_SUCCESS: u16               = 0x0000;
+const PXENV_STATUS_SUCCESS: u16             = 0x0000;
+const PXENV_STATUS_TFTP_FILE_NOT_FOUND: u16 = 0x003B;
+
+const PXENV_PACKET_TYPE_DHCP_ACK: u16     = 2;
+const PXENV_PACKET_TYPE_CACHED_REPLY: u16 = 3; //< Reply from the boot server
+
+const BOOTP_SIADDR_OFFSET: usize = 20;  //< Next server address
+const BOOTP_GIADDR_OFFSET: usize = 24;  //< Relay agent address
+const BOOTP_FILE_OFFSET: usize   = 108; //< Boot file name
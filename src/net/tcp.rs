@@ -0,0 +1,304 @@
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::mem;
+
+use crate::errors::ErrorCode;
+use crate::intr::pit::{self, Deadline};
+use crate::net::iface::{as_bytes, pseudo_checksum, read_struct, Ipv4Packet, NetInterface, IP_PROTO_TCP};
+use crate::net::{Ipv4Addr, UdpTransport};
+
+/// Client end of a TCP connection (RFC 793), just enough to fetch a file.
+///
+/// Data is sent a segment at a time, waiting for each to be acknowledged.
+/// Received data is only accepted in order; anything else is answered with a
+/// duplicate ACK so the peer retransmits it. The receive window is kept small
+/// enough to fit the receive buffers of the NICs we drive.
+pub struct TcpStream {
+    remote: Ipv4Addr,
+    remote_port: u16,
+    local_port: u16,
+
+    snd_una: u32,       //< Oldest unacknowledged sequence number
+    snd_nxt: u32,       //< Sequence number of next byte to send
+    rcv_nxt: u32,       //< Sequence number of next byte expected
+    rx: VecDeque<u8>,   //< Received data not yet read
+
+    state: TcpState,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TcpState {
+    Established,
+    PeerClosed, //< Peer sent FIN, no more data will be received
+    Reset,      //< Peer sent RST
+}
+
+impl TcpStream {
+    /// Open a connection
+    ///
+    /// # Arguments
+    /// * iface - Interface to connect through
+    /// * remote - Server address
+    /// * remote_port - Server port
+    pub fn connect(iface: &mut NetInterface, remote: Ipv4Addr, remote_port: u16) -> Result<TcpStream, ErrorCode> {
+        let local_port = iface.ephemeral_port();
+        let iss = (pit::millis() << 12) ^ ((local_port as u32) << 16);
+
+        let mut stream = TcpStream {
+            remote,
+            remote_port,
+            local_port,
+            snd_una: iss,
+            snd_nxt: iss,
+            rcv_nxt: 0,
+            rx: VecDeque::new(),
+            state: TcpState::Established,
+        };
+
+        for attempt in 0..TCP_RETRIES {
+            /* Announce our MSS, the default of 536 would be slow */
+            let mss = TCP_MSS as u16;
+            let opts = [TCP_OPT_MSS, 4, (mss >> 8) as u8, mss as u8];
+            stream.send_segment(iface, iss, TCP_FLAG_SYN, &opts, &[])?;
+
+            let deadline = Deadline::after_ms(TCP_TIMEOUT_MS << attempt.min(3));
+            while let Ok(pkt) = iface.recv_ipv4(deadline) {
+                let (hdr, _) = match stream.parse(&pkt) {
+                    Some(seg) => seg,
+                    None      => continue,
+                };
+                let flags = u16::from_be(hdr.offset_flags) & TCP_FLAGS_MASK;
+                let ack   = u32::from_be(hdr.ack);
+
+                if (flags & TCP_FLAG_ACK) == 0 || ack != iss.wrapping_add(1) {
+                    continue;
+                }
+                if (flags & TCP_FLAG_RST) != 0 {
+                    /* Nothing listening on the port */
+                    return Err(ErrorCode::ConnectionReset);
+                }
+                if (flags & TCP_FLAG_SYN) == 0 {
+                    continue;
+                }
+
+                stream.snd_una = ack;
+                stream.snd_nxt = ack;
+                stream.rcv_nxt = u32::from_be(hdr.seq).wrapping_add(1);
+                stream.send_ack(iface)?;
+
+                return Ok(stream);
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Send data, waiting for it all to be acknowledged
+    pub fn send(&mut self, iface: &mut NetInterface, data: &[u8]) -> Result<(), ErrorCode> {
+        for chunk in data.chunks(TCP_MSS) {
+            let seq = self.snd_nxt;
+            self.snd_nxt = seq.wrapping_add(chunk.len() as u32);
+
+            let mut acked = false;
+            for attempt in 0..TCP_RETRIES {
+                self.send_segment(iface, seq, TCP_FLAG_ACK | TCP_FLAG_PSH, &[], chunk)?;
+
+                let deadline = Deadline::after_ms(TCP_TIMEOUT_MS << attempt.min(3));
+                while self.snd_una != self.snd_nxt {
+                    let pkt = match iface.recv_ipv4(deadline) {
+                        Ok(pkt)                 => pkt,
+                        Err(ErrorCode::Timeout) => break,
+                        Err(e)                  => return Err(e),
+                    };
+                    self.handle_segment(iface, &pkt)?;
+                    if self.state == TcpState::Reset {
+                        return Err(ErrorCode::ConnectionReset);
+                    }
+                }
+
+                if self.snd_una == self.snd_nxt {
+                    acked = true;
+                    break;
+                }
+            }
+
+            if !acked {
+                return Err(ErrorCode::Timeout);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receive data, waiting until some is available
+    ///
+    /// Returns the number of bytes read into `buf`, or 0 once the peer has
+    /// closed the connection and all data has been read.
+    pub fn recv(&mut self, iface: &mut NetInterface, buf: &mut [u8]) -> Result<usize, ErrorCode> {
+        let deadline = Deadline::after_ms(TCP_RECV_TIMEOUT_MS);
+
+        while self.rx.is_empty() {
+            match self.state {
+                TcpState::Established => {},
+                TcpState::PeerClosed  => return Ok(0),
+                TcpState::Reset       => return Err(ErrorCode::ConnectionReset),
+            }
+
+            let pkt = iface.recv_ipv4(deadline)?;
+            self.handle_segment(iface, &pkt)?;
+        }
+
+        let len = buf.len().min(self.rx.len());
+        for (dst, src) in buf[..len].iter_mut().zip(self.rx.drain(..len)) {
+            *dst = src;
+        }
+
+        /* Tell the peer once the window has room for a full segment again */
+        let window = TCP_WINDOW - self.rx.len();
+        if self.state == TcpState::Established && window >= TCP_MSS && window - len < TCP_MSS {
+            self.send_ack(iface)?;
+        }
+
+        Ok(len)
+    }
+
+    /// Close the connection. If the peer is still sending, the connection is
+    /// reset instead.
+    pub fn close(self, iface: &mut NetInterface) {
+        let flags = match self.state {
+            TcpState::PeerClosed  => TCP_FLAG_FIN | TCP_FLAG_ACK,
+            TcpState::Established => TCP_FLAG_RST | TCP_FLAG_ACK,
+            TcpState::Reset       => return,
+        };
+
+        _ = self.send_segment(iface, self.snd_nxt, flags, &[], &[]);
+    }
+
+    /// Process a received packet, if it belongs to this connection
+    fn handle_segment(&mut self, iface: &mut NetInterface, pkt: &Ipv4Packet) -> Result<(), ErrorCode> {
+        let (hdr, payload) = match self.parse(pkt) {
+            Some(seg) => seg,
+            None      => return Ok(()),
+        };
+        let flags = u16::from_be(hdr.offset_flags) & TCP_FLAGS_MASK;
+        let seq   = u32::from_be(hdr.seq);
+
+        if (flags & TCP_FLAG_RST) != 0 {
+            self.state = TcpState::Reset;
+            return Ok(());
+        }
+
+        if (flags & TCP_FLAG_ACK) != 0 {
+            let ack = u32::from_be(hdr.ack);
+            if seq_after(ack, self.snd_una) && !seq_after(ack, self.snd_nxt) {
+                self.snd_una = ack;
+            }
+        }
+
+        if payload.is_empty() && (flags & (TCP_FLAG_SYN | TCP_FLAG_FIN)) == 0 {
+            return Ok(());
+        }
+
+        /* Accept in-order data that fits the window, and anything else is
+         * answered with the sequence number we are still waiting for */
+        if seq == self.rcv_nxt && self.state == TcpState::Established &&
+           self.rx.len() + payload.len() <= TCP_WINDOW {
+            self.rx.extend(payload);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(payload.len() as u32);
+
+            if (flags & TCP_FLAG_FIN) != 0 {
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+                self.state = TcpState::PeerClosed;
+            }
+        }
+
+        self.send_ack(iface)
+    }
+
+    /// Check a packet is a valid segment of this connection, and split it
+    /// into header and payload
+    fn parse<'a>(&self, pkt: &'a Ipv4Packet) -> Option<(TcpHeader, &'a [u8])> {
+        if pkt.protocol != IP_PROTO_TCP || pkt.src != self.remote {
+            return None;
+        }
+
+        let hdr: TcpHeader = read_struct(&pkt.payload)?;
+        let hdr_len = ((u16::from_be(hdr.offset_flags) >> 12) as usize) * 4;
+        if u16::from_be(hdr.src_port) != self.remote_port || u16::from_be(hdr.dst_port) != self.local_port ||
+           hdr_len < mem::size_of::<TcpHeader>() || hdr_len > pkt.payload.len() {
+            return None;
+        }
+        if pseudo_checksum(pkt.src, pkt.dst, IP_PROTO_TCP, &pkt.payload) != 0 {
+            return None;
+        }
+
+        Some((hdr, &pkt.payload[hdr_len..]))
+    }
+
+    fn send_ack(&self, iface: &mut NetInterface) -> Result<(), ErrorCode> {
+        self.send_segment(iface, self.snd_nxt, TCP_FLAG_ACK, &[], &[])
+    }
+
+    fn send_segment(&self, iface: &mut NetInterface, seq: u32, flags: u16, opts: &[u8],
+                    data: &[u8]) -> Result<(), ErrorCode> {
+        let hdr_len = mem::size_of::<TcpHeader>() + opts.len();
+        let window  = TCP_WINDOW.saturating_sub(self.rx.len());
+        let hdr = TcpHeader {
+            src_port: self.local_port.to_be(),
+            dst_port: self.remote_port.to_be(),
+            seq: seq.to_be(),
+            ack: if (flags & TCP_FLAG_ACK) != 0 { self.rcv_nxt.to_be() } else { 0 },
+            offset_flags: ((((hdr_len / 4) as u16) << 12) | flags).to_be(),
+            window: (window as u16).to_be(),
+            checksum: 0,
+            urgent: 0,
+        };
+
+        let mut segment = Vec::with_capacity(hdr_len + data.len());
+        segment.extend_from_slice(as_bytes(&hdr));
+        segment.extend_from_slice(opts);
+        segment.extend_from_slice(data);
+
+        let csum = pseudo_checksum(iface.addr, self.remote, IP_PROTO_TCP, &segment);
+        segment[16..18].copy_from_slice(&csum.to_be_bytes());
+
+        iface.send_ipv4(self.remote, IP_PROTO_TCP, &segment)
+    }
+}
+
+/// Check whether sequence number `a` comes after `b`
+fn seq_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+/// TCP header, without options. Fields are in network byte order.
+#[repr(C, packed(1))]
+struct TcpHeader {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    offset_flags: u16, //< Header length in words, and flags
+    window: u16,
+    checksum: u16,
+    urgent: u16,
+}
+const _TCP_HEADER_SZ_TEST: [u8; 20] = [0; mem::size_of::<TcpHeader>()];
+
+const TCP_MSS: usize    = 1460;       //< Maximum segment size for a 1500 byte MTU
+const TCP_WINDOW: usize = 4 * TCP_MSS; //< Fits the smallest NIC receive ring
+
+const TCP_RETRIES: u32         = 5;
+const TCP_TIMEOUT_MS: u32      = 1000;  //< Initial retransmission timeout, doubled on retries
+const TCP_RECV_TIMEOUT_MS: u32 = 10000; //< Time to wait for data before giving up
+
+const TCP_FLAG_FIN: u16   = 1 << 0;
+const TCP_FLAG_SYN: u16   = 1 << 1;
+const TCP_FLAG_RST: u16   = 1 << 2;
+const TCP_FLAG_PSH: u16   = 1 << 3;
+const TCP_FLAG_ACK: u16   = 1 << 4;
+const TCP_FLAGS_MASK: u16 = 0x3F;
+
+const TCP_OPT_MSS: u8 = 2;
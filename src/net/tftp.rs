@@ -0,0 +1,472 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use crate::errors::ErrorCode;
+use crate::intr::pit::Deadline;
+use crate::io::output;
+use crate::net::{Ipv4Addr, UdpDatagram, UdpTransport};
+use crate::storage::fs::{File, FileAttribute, Filesystem};
+
+/// Files served by a TFTP server (RFC 1350), with the block size and transfer
+/// size options (RFC 2348, RFC 2349).
+///
+/// Paths are relative to the TFTP root of the default server, or of another
+/// server if prefixed by `//<IPv4 address>`, as in `tftp://10.0.2.2/kernel`.
+///
+/// TFTP transfers are sequential, so one transfer is kept open and reads
+/// continue from where the previous one left off. Reading earlier data, or
+/// another file, restarts the transfer.
+pub struct TftpFilesystem {
+    transport: Rc<RefCell<dyn UdpTransport>>,
+    server: Ipv4Addr, //< Default server
+
+    rootdir: TftpFile,
+
+    session: RefCell<Option<TftpSession>>,
+
+    rc: Weak<RefCell<Self>>,
+}
+
+/// State of a transfer in progress
+struct TftpSession {
+    server: Ipv4Addr,
+    path: String,
+    local_port: u16,
+    remote_port: u16,      //< Port the server is sending from
+    blksize: usize,        //< Negotiated block size
+    tsize: Option<usize>,  //< File size, if the server reported it
+    block: u16,            //< Number of last block received, and acknowledged
+    data: Vec<u8>,         //< Data of last block received
+    data_start: usize,     //< Offset into file of `data`
+    done: bool,            //< Final (short) block has been received
+}
+
+impl Filesystem for TftpFilesystem {
+    fn get_root(&self) -> &dyn File {
+        &self.rootdir
+    }
+
+    fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        /* TFTP has no directories to be relative to */
+        let _ = start_dir;
+
+        let (server, path) = match path.strip_prefix("//") {
+            Some(rest) => {
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                match Ipv4Addr::parse(host) {
+                    Some(server) => (server, path),
+                    None         => {
+                        println!("TFTP server must be an IPv4 address: {}", host);
+                        return Err(ErrorCode::FileNotFound);
+                    }
+                }
+            },
+            None => (self.server, path.trim_start_matches('/'))
+        };
+
+        if path.is_empty() {
+            return Err(ErrorCode::FileNotFound);
+        }
+
+        /* Opening the file is the only way to find out if it exists. The
+         * transfer is left open for the first read. */
+        let mut transport = self.transport.borrow_mut();
+        let mut session = self.session.borrow_mut();
+        if let Some(old) = session.take() {
+            old.abort(&mut *transport);
+        }
+        let new = session.insert(TftpSession::open(&mut *transport, server, path)?);
+
+        let size = match new.tsize {
+            Some(size) => size,
+            None       => {
+                /* No transfer size option, count the whole file */
+                while !new.done {
+                    new.next_block(&mut *transport)?;
+                }
+                new.data_start + new.data.len()
+            }
+        };
+
+        Ok(Box::new(TftpFile {
+            server,
+            path: path.to_string(),
+            size,
+            attr: FileAttribute::File as u32,
+            fs: self.rc.clone(),
+        }))
+    }
+}
+
+impl TftpFilesystem {
+    /// Create a filesystem for files on a TFTP server
+    ///
+    /// # Arguments
+    /// * transport - UDP transport to reach the server through
+    /// * server - Default server
+    pub fn new(transport: Rc<RefCell<dyn UdpTransport>>, server: Ipv4Addr) -> Rc<RefCell<Self>> {
+        Rc::new_cyclic(|me| {
+            RefCell::new(TftpFilesystem {
+                transport,
+                server,
+                rootdir: TftpFile {
+                    server,
+                    path: String::new(),
+                    size: 0,
+                    attr: FileAttribute::Directory as u32,
+                    fs: me.clone(),
+                },
+                session: RefCell::new(None),
+                rc: me.clone(),
+            })
+        })
+    }
+
+    /// Read from a file, restarting the transfer if data before the current
+    /// block is requested
+    fn read(&self, server: Ipv4Addr, path: &str, offset: usize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let mut transport = self.transport.borrow_mut();
+        let mut session = self.session.borrow_mut();
+
+        let reopen = match &*session {
+            Some(cur) => cur.server != server || cur.path != path || offset < cur.data_start,
+            None      => true
+        };
+        if reopen {
+            if let Some(old) = session.take() {
+                old.abort(&mut *transport);
+            }
+            *session = Some(TftpSession::open(&mut *transport, server, path)?);
+        }
+        let session = session.as_mut().unwrap();
+
+        let mut pos = 0;
+        while pos < buf.len() {
+            let abs = offset + pos;
+            let end = session.data_start + session.data.len();
+
+            if abs >= session.data_start && abs < end {
+                let skip = abs - session.data_start;
+                let len  = (end - abs).min(buf.len() - pos);
+                buf[pos..(pos + len)].copy_from_slice(&session.data[skip..(skip + len)]);
+                pos += len;
+            } else if session.done {
+                return Err(ErrorCode::OutOfBounds);
+            } else {
+                session.next_block(&mut *transport)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TftpSession {
+    /// Send a read request, and wait for the server to accept it
+    fn open(transport: &mut dyn UdpTransport, server: Ipv4Addr, path: &str) -> Result<TftpSession, ErrorCode> {
+        let blksize = TFTP_BLKSIZE.to_string();
+        let mut rrq = Vec::new();
+        rrq.extend_from_slice(&TFTP_OP_RRQ.to_be_bytes());
+        for field in [path, "octet", "blksize", &blksize, "tsize", "0"] {
+            rrq.extend_from_slice(field.as_bytes());
+            rrq.push(0);
+        }
+
+        let mut session = TftpSession {
+            server,
+            path: path.to_string(),
+            local_port: transport.ephemeral_port(),
+            remote_port: 0,
+            blksize: TFTP_DEFAULT_BLKSIZE,
+            tsize: None,
+            block: 0,
+            data: Vec::new(),
+            data_start: 0,
+            done: false,
+        };
+
+        for _ in 0..TFTP_RETRIES {
+            transport.send_udp(server, session.local_port, TFTP_PORT, &rrq)?;
+
+            let deadline = Deadline::after_ms(TFTP_TIMEOUT_MS);
+            let dgram = loop {
+                match transport.recv_udp(session.local_port, deadline) {
+                    Ok(dgram) if dgram.src == server => break Some(dgram),
+                    Ok(_)                            => continue,
+                    Err(ErrorCode::Timeout)          => break None,
+                    Err(e)                           => return Err(e),
+                }
+            };
+            let dgram = match dgram {
+                Some(dgram) => dgram,
+                None        => continue,
+            };
+
+            /* The server replies from a new port for the rest of the transfer */
+            session.remote_port = dgram.src_port;
+            match opcode(&dgram) {
+                TFTP_OP_OACK => {
+                    if let Err(e) = session.parse_oack(&dgram.data[2..]) {
+                        session.send_error(transport, TFTP_ERR_OPTION, "Unacceptable options");
+                        return Err(e);
+                    }
+                    session.ack(transport, 0)?;
+                },
+                TFTP_OP_DATA => {
+                    /* Server doesn't support options, this is the first block */
+                    session.accept_block(transport, &dgram)?;
+                },
+                TFTP_OP_ERROR => return Err(tftp_error(&dgram, path)),
+                _             => return Err(ErrorCode::ReadFailure),
+            }
+
+            return Ok(session);
+        }
+
+        println!("TFTP: No response from {}", server);
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Receive the next block of the file
+    fn next_block(&mut self, transport: &mut dyn UdpTransport) -> Result<(), ErrorCode> {
+        let expect = self.block.wrapping_add(1);
+
+        for _ in 0..TFTP_RETRIES {
+            let deadline = Deadline::after_ms(TFTP_TIMEOUT_MS);
+            loop {
+                let dgram = match transport.recv_udp(self.local_port, deadline) {
+                    Ok(dgram)               => dgram,
+                    Err(ErrorCode::Timeout) => break,
+                    Err(e)                  => return Err(e),
+                };
+                if dgram.src != self.server || dgram.src_port != self.remote_port {
+                    continue;
+                }
+
+                match opcode(&dgram) {
+                    /* Earlier blocks are duplicates from a retransmission */
+                    TFTP_OP_DATA if block_number(&dgram) == Some(expect) => {
+                        return self.accept_block(transport, &dgram);
+                    },
+                    TFTP_OP_ERROR => return Err(tftp_error(&dgram, &self.path)),
+                    _             => continue,
+                }
+            }
+
+            /* Our acknowledgement may have been lost */
+            self.ack(transport, self.block)?;
+        }
+
+        println!("TFTP: Transfer of {} timed out", self.path);
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Take the data from a DATA packet, and acknowledge it
+    fn accept_block(&mut self, transport: &mut dyn UdpTransport, dgram: &UdpDatagram) -> Result<(), ErrorCode> {
+        let block = block_number(dgram).ok_or(ErrorCode::ReadFailure)?;
+        let data  = &dgram.data[4..];
+
+        self.data_start += self.data.len();
+        self.data.clear();
+        self.data.extend_from_slice(data);
+        self.block = block;
+        self.done  = data.len() < self.blksize;
+
+        self.ack(transport, block)
+    }
+
+    fn ack(&self, transport: &mut dyn UdpTransport, block: u16) -> Result<(), ErrorCode> {
+        let mut pkt = [0u8; 4];
+        pkt[..2].copy_from_slice(&TFTP_OP_ACK.to_be_bytes());
+        pkt[2..].copy_from_slice(&block.to_be_bytes());
+
+        transport.send_udp(self.server, self.local_port, self.remote_port, &pkt)
+    }
+
+    /// Parse the options the server accepted. A server may only lower the
+    /// block size, anything else can't be received.
+    fn parse_oack(&mut self, data: &[u8]) -> Result<(), ErrorCode> {
+        let mut fields = data.split(|&c| c == 0).map(|field| core::str::from_utf8(field).unwrap_or(""));
+
+        while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+            if name.eq_ignore_ascii_case("blksize") {
+                match value.parse() {
+                    Ok(blksize) if (TFTP_MIN_BLKSIZE..=TFTP_BLKSIZE).contains(&blksize) => self.blksize = blksize,
+                    _ => {
+                        println!("TFTP: Server chose unusable block size {}", value);
+                        return Err(ErrorCode::ReadFailure);
+                    }
+                }
+            } else if name.eq_ignore_ascii_case("tsize") {
+                self.tsize = value.parse().ok();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tell the server an unfinished transfer is being abandoned
+    fn abort(self, transport: &mut dyn UdpTransport) {
+        if self.done || self.remote_port == 0 {
+            return;
+        }
+
+        self.send_error(transport, TFTP_ERR_UNDEFINED, "Transfer aborted");
+    }
+
+    /// Send an ERROR packet, which ends the transfer
+    fn send_error(&self, transport: &mut dyn UdpTransport, code: u16, msg: &str) {
+        let mut pkt = Vec::new();
+        pkt.extend_from_slice(&TFTP_OP_ERROR.to_be_bytes());
+        pkt.extend_from_slice(&code.to_be_bytes());
+        pkt.extend_from_slice(msg.as_bytes());
+        pkt.push(0);
+
+        _ = transport.send_udp(self.server, self.local_port, self.remote_port, &pkt);
+    }
+}
+
+#[derive(Clone)]
+pub struct TftpFile {
+    server: Ipv4Addr,
+    path: String,
+    size: usize,
+    attr: u32,
+
+    fs: Weak<RefCell<TftpFilesystem>>,
+}
+
+impl File for TftpFile {
+    fn get_size(&self) -> usize {
+        self.size
+    }
+
+    fn get_attr(&self) -> u32 {
+        self.attr
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if (offset as usize + buf.len()) > self.size {
+            println!("TftpFile: Attempt to read past end of file");
+            return Err(ErrorCode::OutOfBounds);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        let fs = self.fs.upgrade().expect("Could not upgrade TftpFile::fs");
+        let fs = fs.borrow();
+        fs.read(self.server, &self.path, offset as usize, buf)
+    }
+
+    fn close(&self) {
+
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn opcode(dgram: &UdpDatagram) -> u16 {
+    match dgram.data.get(..2) {
+        Some(op) => u16::from_be_bytes([op[0], op[1]]),
+        None     => 0,
+    }
+}
+
+/// Get the block number of a DATA packet
+fn block_number(dgram: &UdpDatagram) -> Option<u16> {
+    dgram.data.get(2..4).map(|block| u16::from_be_bytes([block[0], block[1]]))
+}
+
+/// Report an ERROR packet, and convert it to an error code
+fn tftp_error(dgram: &UdpDatagram, path: &str) -> ErrorCode {
+    let code = block_number(dgram).unwrap_or(TFTP_ERR_UNDEFINED);
+    if code == TFTP_ERR_NOT_FOUND {
+        return ErrorCode::FileNotFound;
+    }
+
+    let msg = dgram.data.get(4..).unwrap_or(&[]);
+    let len = msg.iter().position(|&c| c == 0).unwrap_or(msg.len());
+    println!("TFTP: Error reading {}: {} ({})", path, String::from_utf8_lossy(&msg[..len]), code);
+
+    ErrorCode::ReadFailure
+}
+
+const TFTP_PORT: u16       = 69;
+const TFTP_RETRIES: usize  = 5;
+const TFTP_TIMEOUT_MS: u32 = 2000;
+
+const TFTP_DEFAULT_BLKSIZE: usize = 512;
+const TFTP_BLKSIZE: usize         = 1432; //< Requested block size, fits a 1500 byte MTU
+const TFTP_MIN_BLKSIZE: usize     = 8;    //< Smallest block size allowed by RFC 2348
+
+const TFTP_OP_RRQ: u16   = 1;
+const TFTP_OP_DATA: u16  = 3;
+const TFTP_OP_ACK: u16   = 4;
+const TFTP_OP_ERROR: u16 = 5;
+const TFTP_OP_OACK: u16  = 6;
+
+const TFTP_ERR_UNDEFINED: u16 = 0;
+const TFTP_ERR_NOT_FOUND: u16 = 1;
+const TFTP_ERR_OPTION: u16    = 8; //< Option negotiation failed (RFC 2347)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> TftpSession {
+        TftpSession {
+            server: Ipv4Addr([10, 0, 2, 2]),
+            path: "kernel".to_string(),
+            local_port: 49152,
+            remote_port: 1024,
+            blksize: TFTP_DEFAULT_BLKSIZE,
+            tsize: None,
+            block: 0,
+            data: Vec::new(),
+            data_start: 0,
+            done: false,
+        }
+    }
+
+    /// Parse an OACK carrying the given option names and values, returning
+    /// the block size and transfer size
+    fn oack(fields: &[&str]) -> Option<(usize, Option<usize>)> {
+        let mut data = Vec::new();
+        for field in fields {
+            data.extend_from_slice(field.as_bytes());
+            data.push(0);
+        }
+
+        let mut session = session();
+        session.parse_oack(&data).ok().map(|_| (session.blksize, session.tsize))
+    }
+
+    #[test]
+    fn oack_options() {
+        assert_eq!(oack(&["blksize", "1432", "tsize", "123456"]), Some((1432, Some(123456))));
+        assert_eq!(oack(&["TSIZE", "42", "BLKSIZE", "512"]), Some((512, Some(42))));
+        assert_eq!(oack(&["blksize", "8"]), Some((8, None)));
+
+        /* Options left out keep their defaults, unknown ones are ignored */
+        assert_eq!(oack(&["tsize", "100"]), Some((TFTP_DEFAULT_BLKSIZE, Some(100))));
+        assert_eq!(oack(&["timeout", "5"]), Some((TFTP_DEFAULT_BLKSIZE, None)));
+        assert_eq!(oack(&[]), Some((TFTP_DEFAULT_BLKSIZE, None)));
+    }
+
+    #[test]
+    fn oack_rejects_block_size() {
+        for blksize in ["7", "0", "1433", "65464", "big", ""] {
+            assert_eq!(oack(&["blksize", blksize]), None, "{}", blksize);
+        }
+    }
+}
@@ -0,0 +1,383 @@
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Write};
+
+use crate::intr::{interrupts_disable, interrupts_enable, interrupts_enabled};
+use crate::io::ioport::{inl, outl};
+use crate::io::output;
+
+/// Location of a PCI function
+#[derive(Clone, Copy, PartialEq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+impl PciAddress {
+    /// Value for the configuration address port to access the given register
+    fn config_address(&self, offset: u8) -> u32 {
+        PCI_CONFIG_ENABLE |
+            ((self.bus as u32) << 16) |
+            ((self.device as u32) << 11) |
+            ((self.function as u32) << 8) |
+            (offset as u32 & 0xFC)
+    }
+
+    /// Read a 32-bit configuration register
+    ///
+    /// # Arguments
+    /// * offset - Dword-aligned offset of register in configuration space
+    pub fn config_read32(&self, offset: u8) -> u32 {
+        outl(PCI_CONFIG_ADDRESS, self.config_address(offset));
+        inl(PCI_CONFIG_DATA)
+    }
+
+    /// Write a 32-bit configuration register
+    ///
+    /// # Arguments
+    /// * offset - Dword-aligned offset of register in configuration space
+    /// * value - Value to write
+    pub fn config_write32(&self, offset: u8, value: u32) {
+        outl(PCI_CONFIG_ADDRESS, self.config_address(offset));
+        outl(PCI_CONFIG_DATA, value);
+    }
+
+    /// Read the command register
+    fn command(&self) -> u16 {
+        (self.config_read32(PCI_REG_COMMAND) & 0xFFFF) as u16
+    }
+
+    /// Write the command register, without clearing any status bits
+    fn set_command(&self, command: u16) {
+        /* Status bits are write-1-to-clear, don't write them back */
+        self.config_write32(PCI_REG_COMMAND, command as u32);
+    }
+
+    /// Set bits in the command register, e.g. to enable I/O decoding
+    pub fn enable(&self, command: u16) {
+        self.set_command(self.command() | command);
+    }
+
+    /// Determine the size of the region decoded by a BAR, by writing all ones
+    /// and reading back which address bits are implemented.
+    ///
+    /// # Arguments
+    /// * offset - Offset of BAR in configuration space
+    /// * mask - Mask of address bits in the BAR
+    fn bar_size_mask(&self, offset: u8, mask: u32) -> u32 {
+        let orig = self.config_read32(offset);
+        self.config_write32(offset, 0xFFFFFFFF);
+        let probed = self.config_read32(offset);
+        self.config_write32(offset, orig);
+
+        probed & mask
+    }
+}
+
+/// Decoded base address register
+#[derive(Clone, Copy)]
+pub enum PciBar {
+    None,
+    Io {
+        port: u16, //< First I/O port
+    },
+    Memory {
+        addr: u64,          //< Physical base address
+        is_64: bool,        //< Upper half of the address is in the next BAR
+        prefetchable: bool, //< Region is prefetchable
+    },
+}
+
+/// PCI function, as found during enumeration
+#[derive(Clone, Copy)]
+pub struct PciDevice {
+    pub addr: PciAddress,
+
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,    //< Base class code
+    pub subclass: u8, //< Sub-class code
+    pub prog_if: u8,  //< Programming interface
+    pub revision: u8,
+
+    pub header_type: u8,   //< Header layout, without multi-function bit
+    pub bars: [PciBar; 6], //< Base address registers, only the first two for bridges
+    pub irq_line: u8,      //< Legacy IRQ routed to the function, 0xFF if none
+    pub irq_pin: u8,       //< Interrupt pin used (1 = INTA#), 0 if none
+}
+
+impl PciDevice {
+    fn read(addr: PciAddress) -> Option<PciDevice> {
+        let id = addr.config_read32(PCI_REG_ID);
+        if (id & 0xFFFF) == PCI_VENDOR_NONE {
+            return None;
+        }
+
+        let class  = addr.config_read32(PCI_REG_CLASS);
+        let header = addr.config_read32(PCI_REG_HEADER);
+        let irq    = addr.config_read32(PCI_REG_INTERRUPT);
+
+        let mut dev = PciDevice {
+            addr,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type: ((header >> 16) as u8) & PCI_HEADER_TYPE_MASK,
+            bars: [PciBar::None; 6],
+            irq_line: irq as u8,
+            irq_pin: (irq >> 8) as u8,
+        };
+
+        let n_bars = match dev.header_type {
+            PCI_HEADER_TYPE_DEVICE => 6,
+            PCI_HEADER_TYPE_BRIDGE => 2,
+            _                      => 0,
+        };
+        dev.read_bars(n_bars);
+
+        Some(dev)
+    }
+
+    /// Decode base address registers. They are only read, sizing them takes
+    /// the function off the bus, so that is left to `bar_size`.
+    ///
+    /// # Arguments
+    /// * count - Number of BARs present in the header
+    fn read_bars(&mut self, count: usize) {
+        let mut idx = 0;
+        while idx < count {
+            let offset = PCI_REG_BAR0 + (idx as u8 * 4);
+            let value  = self.addr.config_read32(offset);
+
+            if (value & PCI_BAR_IO) != 0 {
+                let port = (value & 0xFFFC) as u16;
+                if port != 0 {
+                    self.bars[idx] = PciBar::Io { port };
+                }
+            } else {
+                let is_64 = (value & PCI_BAR_MEM_TYPE_MASK) == PCI_BAR_MEM_TYPE_64 && (idx + 1) < count;
+                let mut addr = (value & 0xFFFFFFF0) as u64;
+                if is_64 {
+                    addr |= (self.addr.config_read32(offset + 4) as u64) << 32;
+                }
+
+                /* Unimplemented BARs read as zero */
+                if addr != 0 {
+                    self.bars[idx] = PciBar::Memory {
+                        addr,
+                        is_64,
+                        prefetchable: (value & PCI_BAR_MEM_PREFETCH) != 0,
+                    };
+                }
+
+                if is_64 {
+                    /* Upper half occupies the next BAR slot */
+                    idx += 1;
+                }
+            }
+
+            idx += 1;
+        }
+    }
+
+    /// Get the size of the region decoded by a BAR. Decoding is disabled while
+    /// the BAR is sized, so display devices, which the console may be on, and
+    /// bridges, which everything behind them is reached through, aren't sized.
+    ///
+    /// # Arguments
+    /// * bar - Index of base address register (0-5)
+    pub fn bar_size(&self, bar: usize) -> Option<u64> {
+        if self.class == PCI_CLASS_DISPLAY || self.class == PCI_CLASS_BRIDGE {
+            return None;
+        }
+
+        let offset = PCI_REG_BAR0 + (bar as u8 * 4);
+        /* I/O BARs decode at most 16 address bits */
+        let (mask, fixed, is_64) = match self.bars.get(bar)? {
+            PciBar::None                 => return None,
+            PciBar::Io { .. }            => (0xFFFC, 0xFFFF0000, false),
+            PciBar::Memory { is_64, .. } => (0xFFFFFFF0, 0, *is_64),
+        };
+
+        /* Nothing may use the function, or anything the all-ones address
+         * aliases, until its BAR is restored */
+        let enabled = interrupts_enabled();
+        interrupts_disable();
+        let cmd = self.addr.command();
+        self.addr.set_command(cmd & !(PCI_COMMAND_IO | PCI_COMMAND_MEMORY));
+
+        let mut probed = 0xFFFFFFFF00000000 | (self.addr.bar_size_mask(offset, mask) | fixed) as u64;
+        if is_64 {
+            probed = (probed & 0xFFFFFFFF) | ((self.addr.bar_size_mask(offset + 4, 0xFFFFFFFF) as u64) << 32);
+        }
+
+        self.addr.set_command(cmd);
+        if enabled {
+            interrupts_enable();
+        }
+
+        if (probed & mask as u64) == 0 {
+            return None;
+        }
+
+        Some((!probed).wrapping_add(1))
+    }
+
+    /// Get the I/O port base of an I/O space BAR, if it is one
+    ///
+    /// # Arguments
+    /// * bar - Index of base address register (0-5)
+    pub fn io_bar(&self, bar: usize) -> Option<u16> {
+        match self.bars.get(bar) {
+            Some(PciBar::Io { port, .. }) => Some(*port),
+            _                             => None
+        }
+    }
+
+    /// Get the base address of a memory space BAR, if it is one
+    ///
+    /// # Arguments
+    /// * bar - Index of base address register (0-5)
+    #[allow(dead_code)]
+    pub fn mem_bar(&self, bar: usize) -> Option<u64> {
+        match self.bars.get(bar) {
+            Some(PciBar::Memory { addr, .. }) => Some(*addr),
+            _                                 => None
+        }
+    }
+}
+
+/// Functions found on all PCI buses
+pub struct PciBus {
+    devices: Vec<PciDevice>,
+}
+
+impl PciBus {
+    /// Enumerate all functions on all buses, using configuration mechanism #1
+    pub fn scan() -> PciBus {
+        let mut devices = Vec::new();
+
+        /* Scan every bus number rather than following bridges, which also
+         * catches buses behind bridges the firmware didn't number in order. */
+        for bus in 0..=255 {
+            for device in 0..32 {
+                let func0 = match PciDevice::read(PciAddress { bus, device, function: 0 }) {
+                    Some(dev) => dev,
+                    None      => continue
+                };
+
+                let header = func0.addr.config_read32(PCI_REG_HEADER);
+                devices.push(func0);
+
+                if (header & PCI_HEADER_MULTIFUNCTION) != 0 {
+                    for function in 1..8 {
+                        if let Some(dev) = PciDevice::read(PciAddress { bus, device, function }) {
+                            devices.push(dev);
+                        }
+                    }
+                }
+            }
+        }
+
+        PciBus { devices }
+    }
+
+    /// All functions found during the scan
+    #[allow(dead_code)]
+    pub fn devices(&self) -> &[PciDevice] {
+        &self.devices
+    }
+
+    /// Find all functions with a given vendor ID and one of the given device IDs
+    ///
+    /// # Arguments
+    /// * vendor - Vendor ID to search for
+    /// * devices - Device IDs to accept
+    pub fn find_by_id(&self, vendor: u16, devices: &[u16]) -> Vec<&PciDevice> {
+        self.devices.iter()
+            .filter(|dev| dev.vendor_id == vendor && devices.contains(&dev.device_id))
+            .collect()
+    }
+
+    /// Find all functions with a given class, sub-class, and optionally
+    /// programming interface
+    #[allow(dead_code)]
+    pub fn find_by_class(&self, class: u8, subclass: u8, prog_if: Option<u8>) -> Vec<&PciDevice> {
+        self.devices.iter()
+            .filter(|dev| dev.class == class && dev.subclass == subclass &&
+                          prog_if.is_none_or(|pi| dev.prog_if == pi))
+            .collect()
+    }
+
+    /// Print an `lspci`-style listing of all functions
+    pub fn dump(&self) {
+        for dev in &self.devices {
+            let mut line = String::new();
+            _ = write!(line, "{} {:04x}:{:04x} [{:02x}{:02x}.{:02x}] rev {:02x}",
+                       dev.addr, dev.vendor_id, dev.device_id,
+                       dev.class, dev.subclass, dev.prog_if, dev.revision);
+            if dev.irq_pin != 0 {
+                _ = write!(line, " INT{}# IRQ {}", (b'A' + dev.irq_pin - 1) as char, dev.irq_line);
+            }
+            println!("{}", line);
+
+            for (idx, bar) in dev.bars.iter().enumerate() {
+                let mut line = match bar {
+                    PciBar::None => continue,
+                    PciBar::Io { port } => format!("    BAR{}: I/O {:04x}", idx, port),
+                    PciBar::Memory { addr, .. } => format!("    BAR{}: mem {:08x}", idx, addr),
+                };
+                if let Some(size) = dev.bar_size(idx) {
+                    _ = write!(line, " [size {:x}]", size);
+                }
+                if let PciBar::Memory { prefetchable: true, .. } = bar {
+                    line.push_str(" prefetchable");
+                }
+                println!("{}", line);
+            }
+        }
+    }
+}
+
+pub const PCI_COMMAND_IO: u16         = 1 << 0; //< Respond to I/O space accesses
+pub const PCI_COMMAND_MEMORY: u16     = 1 << 1; //< Respond to memory space accesses
+pub const PCI_COMMAND_BUS_MASTER: u16 = 1 << 2; //< Allow device to perform DMA
+
+const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
+const PCI_CONFIG_DATA: u16    = 0xCFC;
+const PCI_CONFIG_ENABLE: u32  = 1 << 31;
+
+const PCI_VENDOR_NONE: u32 = 0xFFFF; //< Vendor ID read from absent functions
+
+const PCI_REG_ID: u8        = 0x00; //< Vendor ID (low), device ID (high)
+const PCI_REG_COMMAND: u8   = 0x04; //< Command (low), status (high)
+const PCI_REG_CLASS: u8     = 0x08; //< Revision, prog IF, sub-class, class
+const PCI_REG_HEADER: u8    = 0x0C; //< Cache line size, latency timer, header type, BIST
+const PCI_REG_BAR0: u8      = 0x10; //< First base address register
+const PCI_REG_INTERRUPT: u8 = 0x3C; //< Interrupt line, interrupt pin
+
+const PCI_HEADER_MULTIFUNCTION: u32 = 1 << 23; //< Device has multiple functions
+const PCI_HEADER_TYPE_MASK: u8      = 0x7F;
+const PCI_HEADER_TYPE_DEVICE: u8    = 0x00; //< General device
+const PCI_HEADER_TYPE_BRIDGE: u8    = 0x01; //< PCI-to-PCI bridge
+
+const PCI_CLASS_DISPLAY: u8 = 0x03;
+const PCI_CLASS_BRIDGE: u8  = 0x06;
+
+const PCI_BAR_IO: u32             = 1 << 0; //< BAR maps I/O space
+const PCI_BAR_MEM_TYPE_MASK: u32  = 0x06;
+const PCI_BAR_MEM_TYPE_64: u32    = 0x04;   //< 64-bit memory BAR
+const PCI_BAR_MEM_PREFETCH: u32   = 1 << 3; //< Memory is prefetchable
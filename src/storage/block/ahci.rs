@@ -0,0 +1,389 @@
+extern crate alloc;
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt::Write;
+use core::ptr::{self, NonNull};
+
+use crate::errors::ErrorCode;
+use crate::io::output;
+use crate::pci::{self, PciAddress, PciBus, PciDevice};
+use crate::storage::block::{ata, BlockDevice, BLOCK_READ_ALIGN};
+
+/// SATA disk attached to an AHCI HBA port.
+///
+/// Commands are issued one at a time from command slot 0, and completion is
+/// detected by polling the command issue register.
+pub struct AhciBlockDevice {
+    port: usize,      //< Address of port registers
+    mem: NonNull<u8>, //< Command list, received FIS area, command table, bounce buffer
+    sectors: u64,     //< Number of addressable sectors
+    lba48: bool,      //< Device supports 48-bit LBA
+}
+
+impl AhciBlockDevice {
+    /// Start a port and identify the attached device
+    ///
+    /// # Arguments
+    /// * port - Address of port registers
+    fn new(port: usize) -> Result<AhciBlockDevice, ErrorCode> {
+        if (mmio_read(port + AHCI_PREG_SSTS) & AHCI_SSTS_DET_MASK) != AHCI_SSTS_DET_PRESENT {
+            return Err(ErrorCode::NoDevice);
+        }
+        if mmio_read(port + AHCI_PREG_SIG) != AHCI_SIG_ATA {
+            /* ATAPI, port multipliers, etc. */
+            return Err(ErrorCode::Unsupported);
+        }
+
+        Self::stop(port)?;
+
+        let mem = NonNull::new(unsafe { alloc_zeroed(Self::mem_layout()) }).ok_or(ErrorCode::NoSpace)?;
+        let mut dev = AhciBlockDevice {
+            port,
+            mem,
+            sectors: 0,
+            lba48: false,
+        };
+
+        let base = mem.as_ptr() as usize;
+        mmio_write(port + AHCI_PREG_CLB,  (base + AHCI_MEM_CL) as u32);
+        mmio_write(port + AHCI_PREG_CLBU, 0);
+        mmio_write(port + AHCI_PREG_FB,   (base + AHCI_MEM_FIS) as u32);
+        mmio_write(port + AHCI_PREG_FBU,  0);
+
+        /* Clear stale errors and interrupts, completion is polled */
+        mmio_write(port + AHCI_PREG_SERR, 0xFFFFFFFF);
+        mmio_write(port + AHCI_PREG_IE,   0);
+        mmio_write(port + AHCI_PREG_IS,   0xFFFFFFFF);
+
+        mmio_write(port + AHCI_PREG_CMD, mmio_read(port + AHCI_PREG_CMD) | AHCI_PCMD_FRE);
+        dev.wait_idle()?;
+        mmio_write(port + AHCI_PREG_CMD, mmio_read(port + AHCI_PREG_CMD) | AHCI_PCMD_ST);
+
+        dev.issue(AHCI_ATA_CMD_IDENTIFY, 0, 0, dev.bounce(), AHCI_SECTOR_SIZE)?;
+
+        let mut id = [0u16; 256];
+        let bounce = unsafe { core::slice::from_raw_parts(dev.bounce(), AHCI_SECTOR_SIZE) };
+        for (word, bytes) in id.iter_mut().zip(bounce.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        (dev.sectors, dev.lba48) = ata::identify_capacity(&id);
+
+        if dev.sectors == 0 {
+            /* CHS-only devices aren't supported */
+            return Err(ErrorCode::Unsupported);
+        }
+
+        Ok(dev)
+    }
+
+    /// Get the name of the n-th AHCI disk
+    pub fn name(index: usize) -> String {
+        format!("sd{}", index)
+    }
+
+    /// Find all disks attached to AHCI controllers on the PCI bus
+    ///
+    /// # Arguments
+    /// * pci - Functions found on the PCI bus
+    /// * exclude - Controller to leave alone, because the BIOS still uses it
+    pub fn probe_all(pci: &PciBus, exclude: Option<PciAddress>) -> Vec<(String, AhciBlockDevice)> {
+        let mut devs = Vec::new();
+
+        for hba in pci.find_by_class(AHCI_PCI_CLASS, AHCI_PCI_SUBCLASS, Some(AHCI_PCI_PROG_IF)) {
+            if exclude == Some(hba.addr) {
+                continue;
+            }
+
+            let abar = match Self::init_hba(hba) {
+                Ok(abar) => abar,
+                Err(e)   => {
+                    println!("Could not initialize AHCI controller {}: {}", hba.addr, e);
+                    continue;
+                }
+            };
+
+            let ports = mmio_read(abar + AHCI_REG_PI);
+            for idx in 0..AHCI_MAX_PORTS {
+                if (ports & (1 << idx)) == 0 {
+                    continue;
+                }
+
+                match AhciBlockDevice::new(abar + AHCI_PORT_BASE + (idx * AHCI_PORT_SIZE)) {
+                    Ok(dev) => devs.push((Self::name(devs.len()), dev)),
+                    Err(ErrorCode::NoDevice | ErrorCode::Unsupported) => {},
+                    Err(e)  => println!("Could not start AHCI port {}: {}", idx, e),
+                }
+            }
+        }
+
+        devs
+    }
+
+    /// Take ownership of an HBA and switch it to AHCI mode, returning the
+    /// address of its registers
+    fn init_hba(hba: &PciDevice) -> Result<usize, ErrorCode> {
+        let abar = match hba.mem_bar(AHCI_PCI_ABAR) {
+            Some(abar) if abar <= u32::MAX as u64 => abar as usize,
+            _                                     => return Err(ErrorCode::Unsupported)
+        };
+        hba.addr.enable(pci::PCI_COMMAND_MEMORY | pci::PCI_COMMAND_BUS_MASTER);
+
+        if (mmio_read(abar + AHCI_REG_CAP2) & AHCI_CAP2_BOH) != 0 {
+            /* Request ownership from the firmware */
+            mmio_write(abar + AHCI_REG_BOHC, mmio_read(abar + AHCI_REG_BOHC) | AHCI_BOHC_OOS);
+            wait_clear(abar + AHCI_REG_BOHC, AHCI_BOHC_BOS)?;
+        }
+
+        mmio_write(abar + AHCI_REG_GHC, mmio_read(abar + AHCI_REG_GHC) | AHCI_GHC_AE);
+        /* Interrupts are not used */
+        mmio_write(abar + AHCI_REG_GHC, mmio_read(abar + AHCI_REG_GHC) & !AHCI_GHC_IE);
+
+        Ok(abar)
+    }
+
+    fn mem_layout() -> Layout {
+        Layout::from_size_align(AHCI_MEM_SIZE, AHCI_MEM_ALIGN).unwrap()
+    }
+
+    /// Sector-sized, word-aligned buffer for unaligned transfers
+    fn bounce(&self) -> *mut u8 {
+        unsafe { self.mem.as_ptr().add(AHCI_MEM_BOUNCE) }
+    }
+
+    /// Stop command processing and FIS reception on a port
+    fn stop(port: usize) -> Result<(), ErrorCode> {
+        mmio_write(port + AHCI_PREG_CMD, mmio_read(port + AHCI_PREG_CMD) & !AHCI_PCMD_ST);
+        wait_clear(port + AHCI_PREG_CMD, AHCI_PCMD_CR)?;
+        mmio_write(port + AHCI_PREG_CMD, mmio_read(port + AHCI_PREG_CMD) & !AHCI_PCMD_FRE);
+        wait_clear(port + AHCI_PREG_CMD, AHCI_PCMD_FR)
+    }
+
+    /// Wait for the device to be ready to accept a command
+    fn wait_idle(&self) -> Result<(), ErrorCode> {
+        wait_clear(self.port + AHCI_PREG_TFD, AHCI_TFD_BSY | AHCI_TFD_DRQ)
+    }
+
+    /// Issue a device-to-host data command and wait for it to complete
+    ///
+    /// # Arguments
+    /// * cmd - ATA command
+    /// * lba - First sector
+    /// * count - Number of sectors, 0 for commands without a count
+    /// * buf - Word-aligned buffer to receive data
+    /// * len - Length of `buf` in bytes
+    fn issue(&self, cmd: u8, lba: u64, count: u16, buf: *mut u8, len: usize) -> Result<(), ErrorCode> {
+        let base  = self.mem.as_ptr() as usize;
+        let table = base + AHCI_MEM_CT;
+
+        /* 28-bit commands carry LBA bits 24-27 in the device register */
+        let device = if cmd == AHCI_ATA_CMD_READ_DMA {
+            AHCI_ATA_DEVICE_LBA | ((lba >> 24) as u8 & 0x0F)
+        } else {
+            AHCI_ATA_DEVICE_LBA
+        };
+
+        unsafe {
+            /* Command header for slot 0: FIS length in dwords, one PRD entry */
+            let header = (base + AHCI_MEM_CL) as *mut u32;
+            ptr::write_volatile(header,        AHCI_CFIS_DWORDS | (1 << 16));
+            ptr::write_volatile(header.add(1), 0);
+            ptr::write_volatile(header.add(2), table as u32);
+            ptr::write_volatile(header.add(3), 0);
+
+            /* Register host-to-device FIS */
+            let cfis = table as *mut u8;
+            ptr::write_bytes(cfis, 0, AHCI_CT_PRDT);
+            let fis: [u8; 14] = [
+                AHCI_FIS_TYPE_REG_H2D, AHCI_FIS_H2D_COMMAND, cmd, 0,
+                lba as u8, (lba >> 8) as u8, (lba >> 16) as u8, device,
+                (lba >> 24) as u8, (lba >> 32) as u8, (lba >> 40) as u8, 0,
+                count as u8, (count >> 8) as u8,
+            ];
+            for (idx, byte) in fis.iter().enumerate() {
+                ptr::write_volatile(cfis.add(idx), *byte);
+            }
+
+            let prd = (table + AHCI_CT_PRDT) as *mut u32;
+            ptr::write_volatile(prd,        buf as u32);
+            ptr::write_volatile(prd.add(1), 0);
+            ptr::write_volatile(prd.add(2), 0);
+            ptr::write_volatile(prd.add(3), (len - 1) as u32);
+        }
+
+        self.wait_idle()?;
+        mmio_write(self.port + AHCI_PREG_CI, 1);
+
+        for _ in 0..AHCI_TIMEOUT {
+            if (mmio_read(self.port + AHCI_PREG_IS) & AHCI_PIS_TFES) != 0 {
+                println!("AHCI command {:02x} at LBA {} failed, TFD {:08x}",
+                         cmd, lba, mmio_read(self.port + AHCI_PREG_TFD));
+                mmio_write(self.port + AHCI_PREG_IS, 0xFFFFFFFF);
+                /* Restart the port to clear the error */
+                Self::stop(self.port)?;
+                mmio_write(self.port + AHCI_PREG_SERR, 0xFFFFFFFF);
+                mmio_write(self.port + AHCI_PREG_CMD, mmio_read(self.port + AHCI_PREG_CMD) | AHCI_PCMD_FRE);
+                mmio_write(self.port + AHCI_PREG_CMD, mmio_read(self.port + AHCI_PREG_CMD) | AHCI_PCMD_ST);
+                return Err(ErrorCode::ReadFailure);
+            }
+            if (mmio_read(self.port + AHCI_PREG_CI) & 1) == 0 {
+                return Ok(());
+            }
+        }
+
+        /* Stop the port so the HBA can't write to `buf` later on */
+        _ = Self::stop(self.port);
+        println!("AHCI command {:02x} at LBA {} timed out", cmd, lba);
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Read sectors into a buffer
+    ///
+    /// # Arguments
+    /// * lba - First sector to read
+    /// * buf - Word-aligned buffer, at most `AHCI_MAX_XFER` bytes
+    fn read_sectors(&self, lba: u64, buf: *mut u8, len: usize) -> Result<(), ErrorCode> {
+        let count = (len / AHCI_SECTOR_SIZE) as u16;
+        if self.lba48 {
+            self.issue(AHCI_ATA_CMD_READ_DMA_EXT, lba, count, buf, len)
+        } else {
+            self.issue(AHCI_ATA_CMD_READ_DMA, lba & 0x0FFFFFFF, count, buf, len)
+        }
+    }
+}
+
+impl Drop for AhciBlockDevice {
+    fn drop(&mut self) {
+        /* Stop the port before releasing memory it may access */
+        _ = Self::stop(self.port);
+        unsafe { dealloc(self.mem.as_ptr(), Self::mem_layout()); }
+    }
+}
+
+impl BlockDevice for AhciBlockDevice {
+    fn get_size(&self) -> usize {
+        self.sectors.saturating_mul(AHCI_SECTOR_SIZE as u64).try_into().unwrap_or(usize::MAX)
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if ((offset as usize | buf.len()) & (BLOCK_READ_ALIGN - 1)) != 0 {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let first = offset as u64 / AHCI_SECTOR_SIZE as u64;
+        if first + (buf.len() / AHCI_SECTOR_SIZE) as u64 > self.sectors {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        if (buf.as_ptr() as usize & 1) != 0 {
+            /* DMA requires word alignment, go through the bounce buffer */
+            for (idx, sector) in buf.chunks_exact_mut(AHCI_SECTOR_SIZE).enumerate() {
+                self.read_sectors(first + idx as u64, self.bounce(), AHCI_SECTOR_SIZE)?;
+                sector.copy_from_slice(unsafe { core::slice::from_raw_parts(self.bounce(), AHCI_SECTOR_SIZE) });
+            }
+            return Ok(());
+        }
+
+        for (idx, chunk) in buf.chunks_mut(AHCI_MAX_XFER).enumerate() {
+            let lba = first + ((idx * AHCI_MAX_XFER) / AHCI_SECTOR_SIZE) as u64;
+            self.read_sectors(lba, chunk.as_mut_ptr(), chunk.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn mmio_read(addr: usize) -> u32 {
+    unsafe { ptr::read_volatile(addr as *const u32) }
+}
+
+fn mmio_write(addr: usize, value: u32) {
+    unsafe { ptr::write_volatile(addr as *mut u32, value) }
+}
+
+/// Wait for bits in a register to clear
+fn wait_clear(addr: usize, mask: u32) -> Result<(), ErrorCode> {
+    for _ in 0..AHCI_TIMEOUT {
+        if (mmio_read(addr) & mask) == 0 {
+            return Ok(());
+        }
+    }
+
+    Err(ErrorCode::Timeout)
+}
+
+const AHCI_PCI_CLASS: u8    = 0x01; //< Mass storage controller
+const AHCI_PCI_SUBCLASS: u8 = 0x06; //< Serial ATA controller
+const AHCI_PCI_PROG_IF: u8  = 0x01; //< AHCI 1.0
+const AHCI_PCI_ABAR: usize  = 5;    //< BAR holding HBA registers
+
+const AHCI_SECTOR_SIZE: usize = 512;
+const AHCI_MAX_XFER: usize    = 0x10000;   //< Most bytes transferred per command
+const AHCI_MAX_PORTS: usize   = 32;
+const AHCI_TIMEOUT: usize     = 1_000_000; //< Register polls before giving up
+
+/* Layout of per-port DMA memory */
+const AHCI_MEM_CL: usize     = 0;    //< Command list (1 KiB aligned)
+const AHCI_MEM_FIS: usize    = 1024; //< Received FIS area (256 byte aligned)
+const AHCI_MEM_CT: usize     = 1280; //< Command table for slot 0 (128 byte aligned)
+const AHCI_MEM_BOUNCE: usize = 1536; //< Bounce buffer
+const AHCI_MEM_SIZE: usize   = AHCI_MEM_BOUNCE + AHCI_SECTOR_SIZE;
+const AHCI_MEM_ALIGN: usize  = 1024;
+
+const AHCI_CT_PRDT: usize     = 0x80; //< Offset of PRD table in command table
+const AHCI_CFIS_DWORDS: u32   = 5;    //< Length of register H2D FIS
+
+/* HBA registers */
+const AHCI_REG_GHC: usize   = 0x04; //< Global HBA control
+const AHCI_REG_PI: usize    = 0x0C; //< Ports implemented
+const AHCI_REG_CAP2: usize  = 0x24; //< Extended capabilities
+const AHCI_REG_BOHC: usize  = 0x28; //< BIOS/OS handoff control
+const AHCI_PORT_BASE: usize = 0x100;
+const AHCI_PORT_SIZE: usize = 0x80;
+
+const AHCI_GHC_IE: u32   = 1 << 1;  //< Interrupt enable
+const AHCI_GHC_AE: u32   = 1 << 31; //< AHCI enable
+const AHCI_CAP2_BOH: u32 = 1 << 0;  //< BIOS/OS handoff supported
+const AHCI_BOHC_BOS: u32 = 1 << 0;  //< BIOS owned semaphore
+const AHCI_BOHC_OOS: u32 = 1 << 1;  //< OS owned semaphore
+
+/* Port registers */
+const AHCI_PREG_CLB: usize  = 0x00; //< Command list base
+const AHCI_PREG_CLBU: usize = 0x04; //< Command list base, upper 32 bits
+const AHCI_PREG_FB: usize   = 0x08; //< FIS base
+const AHCI_PREG_FBU: usize  = 0x0C; //< FIS base, upper 32 bits
+const AHCI_PREG_IS: usize   = 0x10; //< Interrupt status
+const AHCI_PREG_IE: usize   = 0x14; //< Interrupt enable
+const AHCI_PREG_CMD: usize  = 0x18; //< Command and status
+const AHCI_PREG_TFD: usize  = 0x20; //< Task file data
+const AHCI_PREG_SIG: usize  = 0x24; //< Device signature
+const AHCI_PREG_SSTS: usize = 0x28; //< SATA status
+const AHCI_PREG_SERR: usize = 0x30; //< SATA error
+const AHCI_PREG_CI: usize   = 0x38; //< Command issue
+
+const AHCI_PCMD_ST: u32  = 1 << 0;  //< Start processing command list
+const AHCI_PCMD_FRE: u32 = 1 << 4;  //< FIS receive enable
+const AHCI_PCMD_FR: u32  = 1 << 14; //< FIS receive running
+const AHCI_PCMD_CR: u32  = 1 << 15; //< Command list running
+
+const AHCI_PIS_TFES: u32 = 1 << 30; //< Task file error status
+
+const AHCI_TFD_DRQ: u32 = 1 << 3;
+const AHCI_TFD_BSY: u32 = 1 << 7;
+
+const AHCI_SSTS_DET_MASK: u32    = 0x0F;
+const AHCI_SSTS_DET_PRESENT: u32 = 0x03; //< Device present, PHY communication established
+
+const AHCI_SIG_ATA: u32 = 0x00000101; //< SATA disk
+
+const AHCI_FIS_TYPE_REG_H2D: u8 = 0x27;
+const AHCI_FIS_H2D_COMMAND: u8  = 0x80; //< FIS updates command register
+
+const AHCI_ATA_DEVICE_LBA: u8       = 0x40;
+const AHCI_ATA_CMD_READ_DMA: u8     = 0xC8;
+const AHCI_ATA_CMD_READ_DMA_EXT: u8 = 0x25;
+const AHCI_ATA_CMD_IDENTIFY: u8     = 0xEC;
@@ -0,0 +1,311 @@
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::errors::ErrorCode;
+use crate::io::ioport::{inb, inw, outb};
+use crate::io::output;
+use crate::storage::block::{BlockDevice, BLOCK_READ_ALIGN};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AtaChannel {
+    Primary,
+    Secondary,
+}
+
+impl AtaChannel {
+    /// Get the command block and control block base ports of the channel
+    fn ports(self) -> (u16, u16) {
+        match self {
+            AtaChannel::Primary   => (0x1F0, 0x3F6),
+            AtaChannel::Secondary => (0x170, 0x376),
+        }
+    }
+
+    /// Get the channel at a legacy command block base port
+    pub fn from_io_base(base: u16) -> Option<AtaChannel> {
+        [AtaChannel::Primary, AtaChannel::Secondary].into_iter()
+            .find(|channel| channel.ports().0 == base)
+    }
+}
+
+/// ATA hard disk accessed through polled PIO on a legacy IDE channel.
+///
+/// Interrupts from the channel are disabled (nIEN) while the device is in
+/// use, as completion is detected by polling the status register.
+pub struct AtaBlockDevice {
+    io_base: u16,   //< Base IO port of command block registers
+    ctrl_base: u16, //< IO port of device control/alternate status register
+    slave: bool,    //< Device is the slave on its channel
+
+    sectors: u64,   //< Number of addressable sectors
+    lba48: bool,    //< Device supports 48-bit LBA
+}
+
+impl AtaBlockDevice {
+    pub fn new(channel: AtaChannel, slave: bool) -> Result<AtaBlockDevice, ErrorCode> {
+        let (io_base, ctrl_base) = channel.ports();
+
+        let mut dev = AtaBlockDevice {
+            io_base,
+            ctrl_base,
+            slave,
+            sectors: 0,
+            lba48: false,
+        };
+
+        let id = dev.identify()?;
+
+        if (id[ATA_ID_CONFIG] & ATA_ID_CONFIG_NOT_ATA) != 0 {
+            return Err(ErrorCode::Unsupported);
+        }
+
+        (dev.sectors, dev.lba48) = identify_capacity(&id);
+
+        if dev.sectors == 0 {
+            /* CHS-only devices aren't supported */
+            return Err(ErrorCode::Unsupported);
+        }
+
+        Ok(dev)
+    }
+
+    /// Get the name of a device, `ata0` through `ata3`
+    pub fn name(channel: AtaChannel, slave: bool) -> String {
+        format!("ata{}", (channel as usize * 2) + slave as usize)
+    }
+
+    /// Find all ATA hard disks attached to the legacy IDE channels
+    ///
+    /// # Arguments
+    /// * exclude - Channel to leave alone, such as the one the BIOS boot drive is on
+    pub fn probe_all(exclude: Option<AtaChannel>) -> Vec<(String, AtaBlockDevice)> {
+        let mut devs = Vec::new();
+
+        for channel in [AtaChannel::Primary, AtaChannel::Secondary] {
+            if Some(channel) == exclude {
+                continue;
+            }
+
+            for slave in [false, true] {
+                if let Ok(dev) = AtaBlockDevice::new(channel, slave) {
+                    devs.push((Self::name(channel, slave), dev));
+                }
+            }
+        }
+
+        devs
+    }
+
+    /// Wait ~400ns for the device to update its status after selection
+    fn delay(&self) {
+        for _ in 0..4 {
+            inb(self.ctrl_base);
+        }
+    }
+
+    /// Select this device on its channel
+    ///
+    /// # Arguments
+    /// * head - Value for the lower bits of the drive/head register
+    fn select(&self, head: u8) {
+        let drv = if self.slave { ATA_DRIVE_SLAVE } else { 0 };
+        outb(self.io_base + ATA_REG_DRIVE, ATA_DRIVE_BASE | drv | head);
+        self.delay();
+    }
+
+    /// Wait until the device is no longer busy, returning its status
+    fn wait_not_busy(&self) -> Result<u8, ErrorCode> {
+        for _ in 0..ATA_TIMEOUT {
+            let status = inb(self.ctrl_base);
+            if (status & ATA_STATUS_BSY) == 0 {
+                return Ok(status);
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Wait until the device is ready to transfer a sector of data
+    fn wait_data(&self) -> Result<(), ErrorCode> {
+        for _ in 0..ATA_TIMEOUT {
+            let status = inb(self.ctrl_base);
+            if (status & ATA_STATUS_BSY) != 0 {
+                continue;
+            }
+            if (status & (ATA_STATUS_ERR | ATA_STATUS_DF)) != 0 {
+                return Err(ErrorCode::ReadFailure);
+            }
+            if (status & ATA_STATUS_DRQ) != 0 {
+                return Ok(());
+            }
+        }
+
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Read one sector's worth of data from the data register
+    fn read_data(&self, buf: &mut [u8]) {
+        for word in buf.chunks_exact_mut(2) {
+            word.copy_from_slice(&inw(self.io_base + ATA_REG_DATA).to_le_bytes());
+        }
+    }
+
+    /// Issue IDENTIFY DEVICE and return the identification data
+    fn identify(&self) -> Result<[u16; 256], ErrorCode> {
+        /* Polled operation only */
+        outb(self.ctrl_base, ATA_CTRL_NIEN);
+
+        self.select(0);
+        if inb(self.ctrl_base) == 0xFF {
+            /* Floating bus, no devices on this channel */
+            return Err(ErrorCode::NoDevice);
+        }
+
+        for reg in [ATA_REG_COUNT, ATA_REG_LBA_LO, ATA_REG_LBA_MID, ATA_REG_LBA_HI] {
+            outb(self.io_base + reg, 0);
+        }
+        outb(self.io_base + ATA_REG_CMD, ATA_CMD_IDENTIFY);
+        self.delay();
+
+        if inb(self.ctrl_base) == 0 {
+            return Err(ErrorCode::NoDevice);
+        }
+
+        self.wait_not_busy()?;
+        if inb(self.io_base + ATA_REG_LBA_MID) != 0 || inb(self.io_base + ATA_REG_LBA_HI) != 0 {
+            /* ATAPI or SATA signature, not a plain ATA device */
+            return Err(ErrorCode::Unsupported);
+        }
+        self.wait_data()?;
+
+        let mut data = [0u8; 512];
+        self.read_data(&mut data);
+
+        let mut id = [0u16; 256];
+        for (word, bytes) in id.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        Ok(id)
+    }
+
+    /// Read up to `ATA_MAX_SECTORS` sectors
+    ///
+    /// # Arguments
+    /// * lba - First sector to read
+    /// * buf - Buffer to read into, a multiple of the sector size in length
+    fn read_sectors(&self, lba: u64, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let count = buf.len() / ATA_SECTOR_SIZE;
+
+        if self.lba48 && (lba + count as u64) > ATA_LBA28_LIMIT {
+            self.select(0);
+            self.wait_not_busy()?;
+
+            /* High-order bytes are written first */
+            outb(self.io_base + ATA_REG_COUNT,   (count >> 8) as u8);
+            outb(self.io_base + ATA_REG_LBA_LO,  (lba >> 24) as u8);
+            outb(self.io_base + ATA_REG_LBA_MID, (lba >> 32) as u8);
+            outb(self.io_base + ATA_REG_LBA_HI,  (lba >> 40) as u8);
+            outb(self.io_base + ATA_REG_COUNT,   count as u8);
+            outb(self.io_base + ATA_REG_LBA_LO,  lba as u8);
+            outb(self.io_base + ATA_REG_LBA_MID, (lba >> 8) as u8);
+            outb(self.io_base + ATA_REG_LBA_HI,  (lba >> 16) as u8);
+            outb(self.io_base + ATA_REG_CMD, ATA_CMD_READ_EXT);
+        } else {
+            self.select(((lba >> 24) & 0x0F) as u8);
+            self.wait_not_busy()?;
+
+            /* A count of 0 means 256 sectors */
+            outb(self.io_base + ATA_REG_COUNT,   count as u8);
+            outb(self.io_base + ATA_REG_LBA_LO,  lba as u8);
+            outb(self.io_base + ATA_REG_LBA_MID, (lba >> 8) as u8);
+            outb(self.io_base + ATA_REG_LBA_HI,  (lba >> 16) as u8);
+            outb(self.io_base + ATA_REG_CMD, ATA_CMD_READ);
+        }
+
+        for sector in buf.chunks_exact_mut(ATA_SECTOR_SIZE) {
+            if let Err(e) = self.wait_data() {
+                println!("ATA read of LBA {} failed: {}", lba, e);
+                return Err(e);
+            }
+            self.read_data(sector);
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for AtaBlockDevice {
+    fn get_size(&self) -> usize {
+        self.sectors.saturating_mul(ATA_SECTOR_SIZE as u64).try_into().unwrap_or(usize::MAX)
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if ((offset as usize | buf.len()) & (BLOCK_READ_ALIGN - 1)) != 0 {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let first = offset as u64 / ATA_SECTOR_SIZE as u64;
+        if first + (buf.len() / ATA_SECTOR_SIZE) as u64 > self.sectors {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        for (idx, chunk) in buf.chunks_mut(ATA_MAX_SECTORS * ATA_SECTOR_SIZE).enumerate() {
+            self.read_sectors(first + (idx * ATA_MAX_SECTORS) as u64, chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the number of addressable sectors, and whether 48-bit LBA is supported,
+/// from IDENTIFY DEVICE data
+pub(super) fn identify_capacity(id: &[u16; 256]) -> (u64, bool) {
+    if (id[ATA_ID_CMDSET2] & ATA_ID_CMDSET2_LBA48) != 0 {
+        let sectors = id[ATA_ID_LBA48_SECTORS..(ATA_ID_LBA48_SECTORS + 4)].iter().rev()
+            .fold(0u64, |acc, &word| (acc << 16) | word as u64);
+        (sectors, true)
+    } else {
+        (id[ATA_ID_LBA28_SECTORS] as u64 | ((id[ATA_ID_LBA28_SECTORS + 1] as u64) << 16), false)
+    }
+}
+
+const ATA_SECTOR_SIZE: usize = 512;
+const ATA_MAX_SECTORS: usize = 256;            //< Most sectors transferred per command
+const ATA_LBA28_LIMIT: u64   = 1 << 28;        //< First sector not addressable with LBA28
+const ATA_TIMEOUT: usize     = 1_000_000;      //< Status register polls before giving up
+
+const ATA_REG_DATA: u16    = 0; //< Data register
+const ATA_REG_COUNT: u16   = 2; //< Sector count
+const ATA_REG_LBA_LO: u16  = 3; //< LBA bits 0-7 (24-31 for LBA48)
+const ATA_REG_LBA_MID: u16 = 4; //< LBA bits 8-15 (32-39 for LBA48)
+const ATA_REG_LBA_HI: u16  = 5; //< LBA bits 16-23 (40-47 for LBA48)
+const ATA_REG_DRIVE: u16   = 6; //< Drive/head select
+const ATA_REG_CMD: u16     = 7; //< Command (write)/status (read)
+
+const ATA_DRIVE_BASE: u8  = 0xE0; //< LBA addressing, obsolete bits set
+const ATA_DRIVE_SLAVE: u8 = 0x10; //< Select slave device
+
+const ATA_CTRL_NIEN: u8 = 0x02; //< Disable device interrupts
+
+const ATA_STATUS_ERR: u8 = 0x01; //< Error
+const ATA_STATUS_DRQ: u8 = 0x08; //< Data request
+const ATA_STATUS_DF: u8  = 0x20; //< Device fault
+const ATA_STATUS_BSY: u8 = 0x80; //< Busy
+
+const ATA_CMD_READ: u8     = 0x20; //< READ SECTORS
+const ATA_CMD_READ_EXT: u8 = 0x24; //< READ SECTORS EXT
+const ATA_CMD_IDENTIFY: u8 = 0xEC; //< IDENTIFY DEVICE
+
+/* Word indices into IDENTIFY DEVICE data */
+const ATA_ID_CONFIG: usize         = 0;   //< General configuration
+const ATA_ID_LBA28_SECTORS: usize  = 60;  //< Addressable sectors with LBA28 (2 words)
+const ATA_ID_CMDSET2: usize        = 83;  //< Command sets supported
+const ATA_ID_LBA48_SECTORS: usize  = 100; //< Addressable sectors with LBA48 (4 words)
+
+const ATA_ID_CONFIG_NOT_ATA: u16 = 1 << 15;
+const ATA_ID_CMDSET2_LBA48: u16  = 1 << 10;
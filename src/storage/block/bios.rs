@@ -1,22 +1,42 @@
 extern crate alloc;
 
 
+use core::mem;
 use core::ptr::{addr_of, addr_of_mut};
 use core::fmt::Write;
-use alloc::vec::Vec;
-use alloc::vec;
+use alloc::format;
+use alloc::string::String;
 
 use crate::errors::ErrorCode;
 use crate::storage::block::BlockDevice;
 use crate::bios::{self, BiosCall};
 use crate::io::output;
+use crate::pci::PciAddress;
+use crate::storage::block::ata::AtaChannel;
 
 pub struct BiosBlockDevice {
     bios_id: u8,            //< Bios drive ID
 
     size: usize,            //< Size of device in bytes
+    sector_size: usize,     //< Bytes per sector
     sectors_per_track: u16, //< Sectors per track
     n_heads: u16,           //< Number of heads
+    controller: BiosDriveController, //< Controller the drive is attached to
+}
+
+/// Controller behind a BIOS drive, as far as the BIOS reports it
+#[derive(Clone, Copy)]
+pub enum BiosDriveController {
+    /// Floppy drive on the legacy floppy controller
+    Floppy,
+    /// Drive on a PCI function, reported through EDD 3.0 device path info,
+    /// with the IDE channel it is on if it is an ATA drive
+    Pci(PciAddress, Option<AtaChannel>),
+    /// Drive on an ISA controller, with the IDE channel it is on if it is an
+    /// ATA drive
+    Isa(Option<AtaChannel>),
+    /// The BIOS did not report where the drive is attached
+    Unknown,
 }
 
 impl BiosBlockDevice {
@@ -28,12 +48,147 @@ impl BiosBlockDevice {
             Ok(BiosBlockDevice {
                 bios_id: id,
                 size: 2880 * 512,
+                sector_size: 512,
                 sectors_per_track: 18,
-                n_heads: 2
+                n_heads: 2,
+                controller: BiosDriveController::Floppy,
             })
         } else {
-            /* TODO */
-            Err(ErrorCode::Unsupported)
+            /* Hard disks and CDs are only supported through the INT 13h
+             * extensions, which also cover El Torito no-emulation CDs. */
+            if !Self::ext_present(id) {
+                println!("BIOS drive {:02x} does not support INT 13h extensions", id);
+                return Err(ErrorCode::Unsupported);
+            }
+
+            let params = Self::ext_get_params(id)?;
+            let sector_size = params.bytes_per_sector as usize;
+            if !(512..=BIOS_EXT_MAX_SECTOR_SIZE).contains(&sector_size) ||
+               !sector_size.is_power_of_two() {
+                println!("BIOS drive {:02x} has unsupported sector size {}", id, sector_size);
+                return Err(ErrorCode::Unsupported);
+            }
+
+            let size = params.sectors.saturating_mul(sector_size as u64);
+
+            Ok(BiosBlockDevice {
+                bios_id: id,
+                size: size.try_into().unwrap_or(usize::MAX),
+                sector_size,
+                sectors_per_track: 0,
+                n_heads: 0,
+                controller: params.controller(),
+            })
+        }
+    }
+
+    /// Get the controller the drive is attached to
+    pub fn controller(&self) -> BiosDriveController {
+        self.controller
+    }
+
+    /// Check for INT 13h extensions support (INT 13h, AH = 0x41)
+    fn ext_present(id: u8) -> bool {
+        let mut bcall = BiosCall {
+            int_n: 0x13,
+            eax:   0x4100,
+            ebx:   0x55AA,
+            edx:   id as u32,
+            ..Default::default()
+        };
+        unsafe { bcall.call(); }
+
+        ((bcall.eflags & bios::EFLAGS_CF) == 0) &&
+        ((bcall.ebx & 0xFFFF) == 0xAA55) &&
+        /* Fixed disk access subset: AH = 0x42-0x44, 0x47, 0x48 */
+        ((bcall.ecx & 0x0001) != 0)
+    }
+
+    /// Get drive parameters (INT 13h, AH = 0x48)
+    fn ext_get_params(id: u8) -> Result<BiosDriveParams, ErrorCode> {
+        let mut params = BiosDriveParams {
+            size: mem::size_of::<BiosDriveParams>() as u16,
+            ..Default::default()
+        };
+
+        if addr_of!(params) as usize > 0xFE00 {
+            println!("Data buffer too high!");
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let mut bcall = BiosCall {
+            int_n: 0x13,
+            eax:   0x4800,
+            edx:   id as u32,
+            esi:   addr_of_mut!(params) as u32,
+            ..Default::default()
+        };
+        unsafe { bcall.call(); }
+
+        if (bcall.eflags & bios::EFLAGS_CF) != 0 {
+            println!("Could not get parameters for BIOS drive {:02x}", id);
+            return Err(ErrorCode::ReadFailure);
+        }
+
+        Ok(params)
+    }
+
+    /// Read part of a sector via INT 13h extensions
+    ///
+    /// # Arguments
+    /// * offset - Sector-aligned offset of sector to read
+    /// * skip - Number of bytes at start of sector to skip
+    /// * buf - Buffer to copy sector data into
+    fn ext_read_sector(&self, offset: usize, skip: usize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let mut data: [u8; BIOS_EXT_MAX_SECTOR_SIZE] = [0; BIOS_EXT_MAX_SECTOR_SIZE];
+
+        if (addr_of!(data) as usize + BIOS_EXT_MAX_SECTOR_SIZE) > 0x10000 {
+            println!("Data buffer too high!");
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let mut dap = BiosDiskAddressPacket {
+            size:      mem::size_of::<BiosDiskAddressPacket>() as u8,
+            _reserved: 0,
+            count:     1,
+            offset:    addr_of_mut!(data) as u16,
+            segment:   0,
+            lba:       (offset / self.sector_size) as u64,
+        };
+
+        let mut attempts = 4;
+        while attempts > 0 {
+            attempts -= 1;
+
+            let mut bcall = BiosCall {
+                int_n: 0x13,
+                eax:   0x4200,
+                edx:   self.bios_id as u32,
+                esi:   addr_of_mut!(dap) as u32,
+                ..Default::default()
+            };
+
+            unsafe { bcall.call(); }
+
+            if (bcall.eflags & bios::EFLAGS_CF) == 0 {
+                buf.copy_from_slice(&data[skip..(skip + buf.len())]);
+                return Ok(());
+            }
+
+            /* Count is updated with the number of sectors actually read */
+            dap.count = 1;
+        }
+
+        println!("Read failure");
+        Err(ErrorCode::ReadFailure)
+    }
+
+    /// Get the mount table name for a BIOS drive ID
+    pub fn drive_name(id: u8) -> String {
+        match id {
+            0x00..=0x7F => format!("fd{}", id),
+            0x80..=0xDF => format!("hd{}", id - 0x80),
+            _           => format!("cd{}", id - 0xE0)
         }
     }
 
@@ -47,7 +202,12 @@ impl BiosBlockDevice {
         unsafe { bcall.call(); }
     }
 
-    fn floppy_read_sector(&self, offset: isize) -> Result<Vec<u8>, ErrorCode> {
+    /// Read a sector from a floppy disk
+    ///
+    /// # Arguments
+    /// * offset - Sector-aligned offset of sector to read
+    /// * buf - Buffer to copy sector data into
+    fn floppy_read_sector(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
         let offset = offset / 512;
 
         let track: u16 = (offset / self.sectors_per_track as isize) as u16;
@@ -78,7 +238,8 @@ impl BiosBlockDevice {
             unsafe { bcall.call(); }
 
             if (bcall.eflags & bios::EFLAGS_CF) == 0 {
-                return Ok(data.to_vec());
+                buf.copy_from_slice(&data[..buf.len()]);
+                return Ok(());
             }
 
             self.floppy_reset();
@@ -94,34 +255,123 @@ impl BlockDevice for BiosBlockDevice {
         self.size
     }
 
-    fn read(&self, offset: isize, size: usize) -> Result<Vec<u8>, ErrorCode> {
+    fn get_block_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let size = buf.len();
         if ((offset % 512) != 0) || ((size % 512) != 0) {
             /* Only sector-aligned reads are currently supported */
             return Err(ErrorCode::OutOfBounds);
         }
 
-        let mut data: Vec<u8> = vec!();
-
         let mut pos: usize = 0;
 
-        if self.bios_id < 0x80 {
-            while pos < size {
-                match self.floppy_read_sector(offset + pos as isize) {
-                    Ok(v) => {
-                        data.extend(v.iter());
-                        pos += 512;
-                    },
-                    Err(e) => {
-                        return Err(e)
-                    }
-                }
+        while pos < size {
+            /* Sectors may be larger than the 512-byte read granularity */
+            let abs = offset as usize + pos;
+            let sect_off = abs % self.sector_size;
+            let len = (self.sector_size - sect_off).min(size - pos);
+            let dest = &mut buf[pos..(pos + len)];
+
+            if self.bios_id < 0x80 {
+                self.floppy_read_sector((abs - sect_off) as isize, dest)?;
+            } else {
+                self.ext_read_sector(abs - sect_off, sect_off, dest)?;
             }
-        } else {
-            /* TODO: HDD */
-            return Err(ErrorCode::Unsupported);
+
+            pos += len;
         }
 
-        Ok(data)
+        Ok(())
+    }
+}
+
+/// Largest sector size supported for drives accessed via INT 13h extensions
+const BIOS_EXT_MAX_SECTOR_SIZE: usize = 2048;
+
+/// Disk address packet for INT 13h extended reads
+#[repr(C, packed(1))]
+struct BiosDiskAddressPacket {
+    size: u8,      //< Size of this packet
+    _reserved: u8,
+    count: u16,    //< Number of sectors to transfer
+    offset: u16,   //< Offset of transfer buffer
+    segment: u16,  //< Segment of transfer buffer
+    lba: u64,      //< First sector to transfer
+}
+const _BIOS_DAP_SZ_TEST: [u8; 16] = [0; mem::size_of::<BiosDiskAddressPacket>()];
+
+/// Result buffer for INT 13h, AH = 0x48, in the EDD 3.0 layout
+#[allow(dead_code)]
+#[derive(Default)]
+#[repr(C, packed(1))]
+struct BiosDriveParams {
+    size: u16,             //< Size of this buffer, set by caller, updated by BIOS
+    flags: u16,            //< Information flags
+    cylinders: u32,        //< Number of physical cylinders
+    heads: u32,            //< Number of physical heads
+    sectors_per_track: u32,//< Number of physical sectors per track
+    sectors: u64,          //< Total number of sectors
+    bytes_per_sector: u16, //< Bytes per sector
+    dpte: u32,             //< Far pointer to device parameter table extension
+    key: u16,              //< 0xBEDD if device path info is present
+    path_len: u8,          //< Length of device path info, from key to checksum
+    _reserved1: [u8; 3],
+    host_bus: [u8; 4],     //< Host bus type, "PCI" or "ISA" padded with spaces
+    interface: [u8; 8],    //< Interface type, e.g. "ATA" or "SATA"
+    interface_path: [u8; 8], //< Bus specific location of the controller
+    device_path: [u8; 8],  //< Interface specific location of the drive
+    _reserved2: u8,
+    checksum: u8,          //< Makes the device path info sum to zero
+}
+const _BIOS_DRIVE_PARAMS_SZ_TEST: [u8; 66] = [0; mem::size_of::<BiosDriveParams>()];
+
+impl BiosDriveParams {
+    /// Work out the drive's controller from the device path info
+    fn controller(&self) -> BiosDriveController {
+        let size = self.size as usize;
+        if size < mem::size_of::<BiosDriveParams>() || self.key != BIOS_EDD_PATH_KEY {
+            return BiosDriveController::Unknown;
+        }
+
+        /* Device path info starts at the key and ends with the checksum */
+        let raw = unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, mem::size_of::<BiosDriveParams>())
+        };
+        let sum = raw[BIOS_EDD_PATH_OFFSET..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        if sum != 0 {
+            return BiosDriveController::Unknown;
+        }
+
+        /* Covers ATAPI too, which shares the channel with ATA disks */
+        let ata = self.interface.starts_with(b"ATA");
+
+        match &self.host_bus[..3] {
+            b"PCI" => {
+                let channel = match self.interface_path[3] {
+                    0 if ata => Some(AtaChannel::Primary),
+                    1 if ata => Some(AtaChannel::Secondary),
+                    _ => None,
+                };
+
+                BiosDriveController::Pci(PciAddress {
+                    bus: self.interface_path[0],
+                    device: self.interface_path[1],
+                    function: self.interface_path[2],
+                }, channel)
+            },
+            b"ISA" => {
+                let base = u16::from_le_bytes([self.interface_path[0], self.interface_path[1]]);
+                BiosDriveController::Isa(if ata { AtaChannel::from_io_base(base) } else { None })
+            },
+            _ => BiosDriveController::Unknown,
+        }
     }
 }
 
+/// Key marking valid EDD 3.0 device path info
+const BIOS_EDD_PATH_KEY: u16 = 0xBEDD;
+/// Offset of the device path info in the drive parameters
+const BIOS_EDD_PATH_OFFSET: usize = 0x1E;
@@ -0,0 +1,201 @@
+extern crate alloc;
+
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+use crate::errors::ErrorCode;
+use crate::storage::block::{BlockDevice, BLOCK_READ_ALIGN};
+
+/// Cache hit/miss statistics
+#[derive(Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u32,     //< Block lookups satisfied from the cache
+    pub misses: u32,   //< Block lookups that required a device read
+    pub bypassed: u32, //< Reads too large to go through the cache
+}
+
+struct CacheEntry {
+    offset: usize,  //< Offset of cached block into device, usize::MAX if invalid
+    last_used: u32, //< Tick at which this entry was last used, for LRU eviction
+    data: Vec<u8>,
+}
+
+/// Least-recently-used read cache that wraps any `BlockDevice`.
+///
+/// Reads are split into device blocks, each of which is served from the cache
+/// if present. Reads large enough to flush a significant portion of the cache
+/// bypass it, so that bulk file data does not evict filesystem metadata.
+pub struct CachedBlockDevice {
+    dev: Rc<RefCell<dyn BlockDevice>>, //< Underlying block device
+
+    block_size: usize, //< Size of each cached block
+    capacity: usize,   //< Maximum number of cached blocks
+
+    entries: RefCell<Vec<CacheEntry>>,
+    tick: Cell<u32>,         //< Incremented on every lookup
+    stats: Cell<CacheStats>,
+}
+
+impl CachedBlockDevice {
+    /// Create a new cache
+    ///
+    /// # Arguments
+    /// * dev: Block device to cache
+    /// * capacity: Maximum number of blocks to cache
+    pub fn new(dev: Rc<RefCell<dyn BlockDevice>>, capacity: usize) -> Self {
+        let block_size = dev.borrow().get_block_size().max(BLOCK_READ_ALIGN);
+
+        CachedBlockDevice {
+            dev,
+            block_size,
+            capacity: capacity.max(1),
+            entries: RefCell::new(Vec::with_capacity(capacity)),
+            tick: Cell::new(0),
+            stats: Cell::new(CacheStats::default()),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    /// Drop all cached blocks
+    #[allow(dead_code)]
+    pub fn invalidate(&self) {
+        for entry in self.entries.borrow_mut().iter_mut() {
+            entry.offset = usize::MAX;
+        }
+    }
+
+    /// Copy part of a block into `buf`, reading the block into the cache first
+    /// if necessary.
+    ///
+    /// # Arguments
+    /// * base: Block-aligned offset of block
+    /// * skip: Number of bytes at start of block to skip
+    /// * buf: Buffer to copy data into
+    fn read_block(&self, base: usize, skip: usize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let tick = self.tick.get().wrapping_add(1);
+        self.tick.set(tick);
+
+        let mut stats = self.stats.get();
+        let mut entries = self.entries.borrow_mut();
+        let cached = entries.iter().position(|ent| ent.offset == base);
+
+        /* Counted before reading, so failed reads show up as misses */
+        match cached {
+            Some(_) => stats.hits += 1,
+            None    => stats.misses += 1,
+        }
+        self.stats.set(stats);
+
+        let idx = match cached {
+            Some(idx) => idx,
+            None => {
+                let idx = if entries.len() < self.capacity {
+                    entries.push(CacheEntry {
+                        offset: usize::MAX,
+                        last_used: 0,
+                        data: vec![0; self.block_size],
+                    });
+                    entries.len() - 1
+                } else {
+                    /* Evict least recently used entry. Ages are compared
+                     * relative to the current tick so wrapping is harmless. */
+                    let mut oldest = 0;
+                    for (idx, ent) in entries.iter().enumerate() {
+                        if tick.wrapping_sub(ent.last_used) > tick.wrapping_sub(entries[oldest].last_used) {
+                            oldest = idx;
+                        }
+                    }
+                    oldest
+                };
+
+                let entry = &mut entries[idx];
+                entry.offset = usize::MAX;
+                self.dev.borrow().read_into(base as isize, &mut entry.data)?;
+                entry.offset = base;
+
+                idx
+            }
+        };
+
+        let entry = &mut entries[idx];
+        entry.last_used = tick;
+        buf.copy_from_slice(&entry.data[skip..(skip + buf.len())]);
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for CachedBlockDevice {
+    fn get_size(&self) -> usize {
+        self.dev.borrow().get_size()
+    }
+
+    fn get_block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if ((offset as usize | buf.len()) & (BLOCK_READ_ALIGN - 1)) != 0 {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        if buf.len() > ((self.block_size * self.capacity) / 2) {
+            let mut stats = self.stats.get();
+            stats.bypassed += 1;
+            self.stats.set(stats);
+
+            return self.dev.borrow().read_into(offset, buf);
+        }
+
+        let mut pos = 0;
+        while pos < buf.len() {
+            let abs  = offset as usize + pos;
+            let skip = abs % self.block_size;
+            let len  = (self.block_size - skip).min(buf.len() - pos);
+
+            self.read_block(abs - skip, skip, &mut buf[pos..(pos + len)])?;
+
+            pos += len;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::block::ram::RamBlockDevice;
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let ram: Rc<RefCell<dyn BlockDevice>> = Rc::new(RefCell::new(RamBlockDevice::new(vec![0x5A; 4096])));
+        let cache = CachedBlockDevice::new(ram, 4);
+
+        let mut buf = [0u8; 512];
+        assert!(cache.read_into(512, &mut buf).is_ok());
+        assert!(cache.read_into(512, &mut buf).is_ok());
+        assert_eq!(buf, [0x5A; 512]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.bypassed), (1, 1, 0));
+    }
+
+    #[test]
+    fn counts_failed_reads_as_misses() {
+        let ram: Rc<RefCell<dyn BlockDevice>> = Rc::new(RefCell::new(RamBlockDevice::new(vec![0; 1024])));
+        let cache = CachedBlockDevice::new(ram, 4);
+
+        /* Past the end of the device */
+        let mut buf = [0u8; 512];
+        assert!(cache.read_into(2048, &mut buf).is_err());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 1));
+    }
+}
@@ -1,20 +1,74 @@
+pub mod ahci;
+pub mod ata;
 pub mod bios;
+pub mod cache;
+pub mod ram;
+pub mod virtio;
 
 extern crate alloc;
 
+use alloc::vec;
 use alloc::vec::Vec;
 
 use crate::errors::ErrorCode;
 
+/// Granularity of reads supported by all block devices
+pub const BLOCK_READ_ALIGN: usize = 512;
+
 #[allow(dead_code)]
 pub trait BlockDevice {
     fn get_size(&self) -> usize;
 
+    /// Native block size of the device, reads of whole blocks are cheapest
+    fn get_block_size(&self) -> usize {
+        BLOCK_READ_ALIGN
+    }
+
     /// Read bytes from storage device
     ///
     /// # Arguments
     /// * offset - Offset at which to start reading
     /// * size - How many bytes to read
-    fn read(&self, offset: isize, size: usize) -> Result<Vec<u8>, ErrorCode>;
+    fn read(&self, offset: isize, size: usize) -> Result<Vec<u8>, ErrorCode> {
+        let mut data = vec![0; size];
+        self.read_into(offset, &mut data)?;
+        Ok(data)
+    }
+
+    /// Read bytes from storage device directly into a caller-provided buffer
+    ///
+    /// # Arguments
+    /// * offset - Offset at which to start reading
+    /// * buf - Buffer to read into, its length determines how many bytes to read
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode>;
 }
 
+/// Read an arbitrary byte range from a block device, which itself only
+/// supports `BLOCK_READ_ALIGN`-aligned reads. The aligned portion of the range
+/// is read directly into `buf`, only the unaligned head and tail are bounced.
+pub fn read_unaligned(dev: &dyn BlockDevice, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+    let offset = offset as usize;
+    let mut pos = 0;
+
+    let head = offset % BLOCK_READ_ALIGN;
+    if head != 0 {
+        let sector = dev.read((offset - head) as isize, BLOCK_READ_ALIGN)?;
+        let len = (BLOCK_READ_ALIGN - head).min(buf.len());
+        buf[..len].copy_from_slice(&sector[head..(head + len)]);
+        pos = len;
+    }
+
+    let aligned = ((buf.len() - pos) / BLOCK_READ_ALIGN) * BLOCK_READ_ALIGN;
+    if aligned > 0 {
+        dev.read_into((offset + pos) as isize, &mut buf[pos..(pos + aligned)])?;
+        pos += aligned;
+    }
+
+    if pos < buf.len() {
+        let sector = dev.read((offset + pos) as isize, BLOCK_READ_ALIGN)?;
+        let len = buf.len() - pos;
+        buf[pos..].copy_from_slice(&sector[..len]);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,55 @@
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::String;
+#[cfg(test)]
+use alloc::vec::Vec;
+
+use crate::errors::ErrorCode;
+use crate::storage::block::BlockDevice;
+
+/// Block device backed by a range of memory, such as a disk image embedded in
+/// stage 2 or a file loaded into memory. Reads of any alignment are supported.
+pub struct RamBlockDevice {
+    data: Cow<'static, [u8]>, //< Disk image
+}
+
+impl RamBlockDevice {
+    /// Create a RAM disk owning an image built by a test
+    #[cfg(test)]
+    pub fn new(data: Vec<u8>) -> RamBlockDevice {
+        RamBlockDevice {
+            data: Cow::Owned(data),
+        }
+    }
+
+    /// Create a RAM disk from an image that lives for the life of the loader
+    pub fn from_static(data: &'static [u8]) -> RamBlockDevice {
+        RamBlockDevice {
+            data: Cow::Borrowed(data),
+        }
+    }
+
+    /// Get the name of the n-th RAM disk
+    pub fn name(index: usize) -> String {
+        format!("ram{}", index)
+    }
+}
+
+impl BlockDevice for RamBlockDevice {
+    fn get_size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let start = usize::try_from(offset).map_err(|_| ErrorCode::OutOfBounds)?;
+        match self.data.get(start..).and_then(|data| data.get(..buf.len())) {
+            Some(data) => {
+                buf.copy_from_slice(data);
+                Ok(())
+            },
+            None => Err(ErrorCode::OutOfBounds)
+        }
+    }
+}
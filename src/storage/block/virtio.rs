@@ -0,0 +1,302 @@
+extern crate alloc;
+
+use alloc::alloc::{alloc_zeroed, dealloc};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::fmt::Write;
+use core::mem;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::errors::ErrorCode;
+use crate::io::ioport::{inb, inl, inw, outb, outl, outw};
+use crate::io::output;
+use crate::pci::{self, PciAddress, PciBus, PciDevice};
+use crate::storage::block::{BlockDevice, BLOCK_READ_ALIGN};
+
+/// virtio-blk device accessed through the legacy PCI I/O interface.
+///
+/// A single virtqueue is used with one request in flight at a time, and
+/// completion is detected by polling the used ring.
+pub struct VirtioBlockDevice {
+    io_base: u16,  //< Base I/O port of legacy virtio registers
+    sectors: u64,  //< Capacity in 512-byte sectors
+
+    queue_size: u16,       //< Number of descriptors in the virtqueue
+    used_offset: usize,    //< Offset of used ring into ring memory
+    ring: NonNull<u8>,     //< Virtqueue memory, followed by request header and status
+    ring_layout: Layout,
+    last_used: Cell<u16>,  //< Used ring index up to which requests have completed
+}
+
+impl VirtioBlockDevice {
+    pub fn new(pci_dev: &PciDevice) -> Result<VirtioBlockDevice, ErrorCode> {
+        let io_base = pci_dev.io_bar(0).ok_or(ErrorCode::Unsupported)?;
+        pci_dev.addr.enable(pci::PCI_COMMAND_IO | pci::PCI_COMMAND_BUS_MASTER);
+
+        /* Reset, then acknowledge the device. No optional features are used. */
+        outb(io_base + VIRTIO_REG_STATUS, 0);
+        outb(io_base + VIRTIO_REG_STATUS, VIRTIO_STATUS_ACK);
+        outb(io_base + VIRTIO_REG_STATUS, VIRTIO_STATUS_ACK | VIRTIO_STATUS_DRIVER);
+        outl(io_base + VIRTIO_REG_GUEST_FEATURES, 0);
+
+        outw(io_base + VIRTIO_REG_QUEUE_SEL, 0);
+        let queue_size = inw(io_base + VIRTIO_REG_QUEUE_SIZE);
+        if queue_size < 3 {
+            outb(io_base + VIRTIO_REG_STATUS, VIRTIO_STATUS_FAILED);
+            return Err(ErrorCode::NoDevice);
+        }
+
+        /* Legacy virtqueue layout: descriptor table and available ring, then
+         * the used ring on the next page boundary. */
+        let n = queue_size as usize;
+        let used_offset = (mem::size_of::<VirtqDesc>() * n + 2 * (3 + n)).next_multiple_of(VIRTIO_PAGE_SIZE);
+        let ring_size = used_offset + (2 * 3 + mem::size_of::<VirtqUsedElem>() * n).next_multiple_of(VIRTIO_PAGE_SIZE);
+
+        let ring_layout = Layout::from_size_align(ring_size + VIRTIO_REQ_SIZE, VIRTIO_PAGE_SIZE).unwrap();
+        let ring = match NonNull::new(unsafe { alloc_zeroed(ring_layout) }) {
+            Some(ring) => ring,
+            None       => {
+                outb(io_base + VIRTIO_REG_STATUS, VIRTIO_STATUS_FAILED);
+                return Err(ErrorCode::NoSpace);
+            }
+        };
+
+        let dev = VirtioBlockDevice {
+            io_base,
+            sectors: inl(io_base + VIRTIO_REG_BLK_CAPACITY) as u64 |
+                     ((inl(io_base + VIRTIO_REG_BLK_CAPACITY + 4) as u64) << 32),
+            queue_size,
+            used_offset,
+            ring,
+            ring_layout,
+            last_used: Cell::new(0),
+        };
+
+        unsafe {
+            /* Completion is polled for, no interrupts are needed */
+            ptr::write_volatile(dev.avail_ring(), VIRTQ_AVAIL_F_NO_INTERRUPT);
+        }
+
+        outl(io_base + VIRTIO_REG_QUEUE_ADDR, (ring.as_ptr() as usize / VIRTIO_PAGE_SIZE) as u32);
+        outb(io_base + VIRTIO_REG_STATUS, VIRTIO_STATUS_ACK | VIRTIO_STATUS_DRIVER | VIRTIO_STATUS_DRIVER_OK);
+
+        Ok(dev)
+    }
+
+    /// Get the name of the n-th virtio-blk device
+    pub fn name(index: usize) -> String {
+        format!("vd{}", index)
+    }
+
+    /// Find and initialize all virtio-blk devices on the PCI bus
+    ///
+    /// # Arguments
+    /// * pci - Functions found on the PCI bus
+    /// * exclude - Function to leave alone, because the BIOS still uses it
+    pub fn probe_all(pci: &PciBus, exclude: Option<PciAddress>) -> Vec<(String, VirtioBlockDevice)> {
+        let mut devs = Vec::new();
+
+        for pci_dev in pci.find_by_id(VIRTIO_PCI_VENDOR, &[VIRTIO_PCI_DEVICE_BLK]) {
+            if exclude == Some(pci_dev.addr) {
+                continue;
+            }
+
+            match VirtioBlockDevice::new(pci_dev) {
+                Ok(dev) => devs.push((Self::name(devs.len()), dev)),
+                Err(e)  => println!("Could not initialize virtio-blk device: {}", e),
+            }
+        }
+
+        devs
+    }
+
+    fn descs(&self) -> *mut VirtqDesc {
+        self.ring.as_ptr() as *mut VirtqDesc
+    }
+
+    /// Available ring, as u16 words: flags, idx, ring[queue_size]
+    fn avail_ring(&self) -> *mut u16 {
+        unsafe { self.ring.as_ptr().add(mem::size_of::<VirtqDesc>() * self.queue_size as usize) as *mut u16 }
+    }
+
+    /// Used ring, as u16 words: flags, idx, followed by used elements
+    fn used_ring(&self) -> *mut u16 {
+        unsafe { self.ring.as_ptr().add(self.used_offset) as *mut u16 }
+    }
+
+    fn req_header(&self) -> *mut VirtioBlkReqHeader {
+        unsafe { self.ring.as_ptr().add(self.ring_layout.size() - VIRTIO_REQ_SIZE) as *mut VirtioBlkReqHeader }
+    }
+
+    fn req_status(&self) -> *mut u8 {
+        unsafe { (self.req_header() as *mut u8).add(mem::size_of::<VirtioBlkReqHeader>()) }
+    }
+
+    /// Perform a single read request
+    ///
+    /// # Arguments
+    /// * sector - First 512-byte sector to read
+    /// * buf - Buffer to read into, a multiple of 512 bytes in length
+    fn read_request(&self, sector: u64, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        unsafe {
+            ptr::write_volatile(self.req_header(), VirtioBlkReqHeader {
+                req_type: VIRTIO_BLK_T_IN,
+                reserved: 0,
+                sector,
+            });
+            ptr::write_volatile(self.req_status(), 0xFF);
+
+            let descs = self.descs();
+            ptr::write_volatile(descs, VirtqDesc {
+                addr: self.req_header() as u64,
+                len: mem::size_of::<VirtioBlkReqHeader>() as u32,
+                flags: VIRTQ_DESC_F_NEXT,
+                next: 1,
+            });
+            ptr::write_volatile(descs.add(1), VirtqDesc {
+                addr: buf.as_mut_ptr() as u64,
+                len: buf.len() as u32,
+                flags: VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+                next: 2,
+            });
+            ptr::write_volatile(descs.add(2), VirtqDesc {
+                addr: self.req_status() as u64,
+                len: 1,
+                flags: VIRTQ_DESC_F_WRITE,
+                next: 0,
+            });
+
+            /* Publish descriptor chain 0 in the available ring */
+            let avail = self.avail_ring();
+            let idx = ptr::read_volatile(avail.add(1));
+            ptr::write_volatile(avail.add(2 + (idx % self.queue_size) as usize), 0);
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(avail.add(1), idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+        }
+
+        outw(self.io_base + VIRTIO_REG_QUEUE_NOTIFY, 0);
+
+        let expected = self.last_used.get().wrapping_add(1);
+        let mut done = false;
+        for _ in 0..VIRTIO_TIMEOUT {
+            if unsafe { ptr::read_volatile(self.used_ring().add(1)) } == expected {
+                done = true;
+                break;
+            }
+            /* Reading the ISR also serves as a short delay */
+            inb(self.io_base + VIRTIO_REG_ISR);
+        }
+
+        if !done {
+            /* Reset the device so it can't write to `buf` later on */
+            outb(self.io_base + VIRTIO_REG_STATUS, 0);
+            println!("virtio-blk request for sector {} timed out", sector);
+            return Err(ErrorCode::Timeout);
+        }
+
+        self.last_used.set(expected);
+        fence(Ordering::SeqCst);
+
+        match unsafe { ptr::read_volatile(self.req_status()) } {
+            VIRTIO_BLK_S_OK => Ok(()),
+            status          => {
+                println!("virtio-blk read of sector {} failed: {}", sector, status);
+                Err(ErrorCode::ReadFailure)
+            }
+        }
+    }
+}
+
+impl Drop for VirtioBlockDevice {
+    fn drop(&mut self) {
+        /* Reset the device before releasing memory it may access */
+        outb(self.io_base + VIRTIO_REG_STATUS, 0);
+        unsafe { dealloc(self.ring.as_ptr(), self.ring_layout); }
+    }
+}
+
+impl BlockDevice for VirtioBlockDevice {
+    fn get_size(&self) -> usize {
+        self.sectors.saturating_mul(VIRTIO_BLK_SECTOR_SIZE as u64).try_into().unwrap_or(usize::MAX)
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if ((offset as usize | buf.len()) & (BLOCK_READ_ALIGN - 1)) != 0 {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let first = offset as u64 / VIRTIO_BLK_SECTOR_SIZE as u64;
+        if first + (buf.len() / VIRTIO_BLK_SECTOR_SIZE) as u64 > self.sectors {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        for (idx, chunk) in buf.chunks_mut(VIRTIO_BLK_MAX_XFER).enumerate() {
+            let sector = first + ((idx * VIRTIO_BLK_MAX_XFER) / VIRTIO_BLK_SECTOR_SIZE) as u64;
+            self.read_request(sector, chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[repr(C)]
+struct VirtqDesc {
+    addr: u64,  //< Physical address of buffer
+    len: u32,   //< Length of buffer
+    flags: u16, //< VIRTQ_DESC_F_*
+    next: u16,  //< Next descriptor in chain, if VIRTQ_DESC_F_NEXT is set
+}
+const _VIRTQ_DESC_SZ_TEST: [u8; 16] = [0; mem::size_of::<VirtqDesc>()];
+
+#[allow(dead_code)]
+#[repr(C)]
+struct VirtqUsedElem {
+    id: u32,  //< Head of completed descriptor chain
+    len: u32, //< Bytes written into the chain's buffers
+}
+const _VIRTQ_USED_ELEM_SZ_TEST: [u8; 8] = [0; mem::size_of::<VirtqUsedElem>()];
+
+#[repr(C)]
+struct VirtioBlkReqHeader {
+    req_type: u32, //< VIRTIO_BLK_T_*
+    reserved: u32,
+    sector: u64,   //< First 512-byte sector of request
+}
+const _VIRTIO_BLK_REQ_HEADER_SZ_TEST: [u8; 16] = [0; mem::size_of::<VirtioBlkReqHeader>()];
+
+const VIRTIO_PCI_VENDOR: u16     = 0x1AF4;
+const VIRTIO_PCI_DEVICE_BLK: u16 = 0x1001; //< Transitional virtio-blk device
+
+const VIRTIO_PAGE_SIZE: usize       = 4096;
+const VIRTIO_REQ_SIZE: usize        = 32;        //< Space reserved for request header and status
+const VIRTIO_TIMEOUT: usize         = 1_000_000; //< Used ring polls before giving up
+const VIRTIO_BLK_SECTOR_SIZE: usize = 512;
+const VIRTIO_BLK_MAX_XFER: usize    = 0x10000;   //< Most bytes transferred per request
+
+/* Legacy virtio PCI registers, relative to BAR0 */
+const VIRTIO_REG_GUEST_FEATURES: u16 = 0x04; //< Features accepted by driver
+const VIRTIO_REG_QUEUE_ADDR: u16     = 0x08; //< Page number of selected queue
+const VIRTIO_REG_QUEUE_SIZE: u16     = 0x0C; //< Size of selected queue
+const VIRTIO_REG_QUEUE_SEL: u16      = 0x0E; //< Queue select
+const VIRTIO_REG_QUEUE_NOTIFY: u16   = 0x10; //< Queue notify
+const VIRTIO_REG_STATUS: u16         = 0x12; //< Device status
+const VIRTIO_REG_ISR: u16            = 0x13; //< ISR status, cleared on read
+const VIRTIO_REG_BLK_CAPACITY: u16   = 0x14; //< Capacity in sectors (u64)
+
+const VIRTIO_STATUS_ACK: u8       = 1;    //< Guest has noticed the device
+const VIRTIO_STATUS_DRIVER: u8    = 2;    //< Guest knows how to drive the device
+const VIRTIO_STATUS_DRIVER_OK: u8 = 4;    //< Driver is ready
+const VIRTIO_STATUS_FAILED: u8    = 0x80; //< Driver has given up on the device
+
+const VIRTQ_DESC_F_NEXT: u16  = 1; //< Descriptor continues via `next`
+const VIRTQ_DESC_F_WRITE: u16 = 2; //< Buffer is device write-only
+
+const VIRTQ_AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+const VIRTIO_BLK_T_IN: u32 = 0; //< Read request
+const VIRTIO_BLK_S_OK: u8  = 0;
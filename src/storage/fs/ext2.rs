@@ -0,0 +1,589 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+use core::mem;
+use core::fmt::Write;
+use core::ptr;
+
+use crate::errors::ErrorCode;
+use crate::storage::{block::{self, BlockDevice}, fs::FileAttribute};
+use crate::io::output;
+use super::{File, Filesystem};
+
+
+pub struct Ext2Filesystem {
+    offset: isize, //< Offset of filesystem into block device
+
+    block_size: usize,       //< Number of bytes per block
+    inode_size: usize,       //< Size of on-disk inode structure
+    inodes_per_group: usize, //< Number of inodes in each block group
+    bgdt_offset: isize,      //< Offset into filesystem of block group descriptor table
+    desc_size: usize,        //< Size of a block group descriptor
+
+    rootdir: Ext2File, //< Ext2File representing root directory
+
+    block: Rc<RefCell<dyn BlockDevice>>, //< Underlying block device
+
+    rc: Weak<RefCell<Self>>,
+}
+
+impl Filesystem for Ext2Filesystem {
+    fn get_root(&self) -> &dyn super::File {
+        &self.rootdir
+    }
+
+    fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        let mut dir: Ext2File = match start_dir {
+            Some(start) if !path.starts_with('/') => {
+                match start.as_any().downcast_ref::<Ext2File>() {
+                    Some(file) => file.clone(),
+                    None       => return Err(ErrorCode::FileNotFound)
+                }
+            },
+            _ => self.rootdir.clone()
+        };
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if (dir.attr & FileAttribute::Directory as u32) == 0 {
+                return Err(ErrorCode::FileNotFound);
+            }
+
+            let ino = self.dir_lookup(&dir, name)?;
+            dir = self.open_inode(ino)?;
+        }
+
+        Ok(Box::new(dir))
+    }
+}
+
+impl Ext2Filesystem {
+    /// Check whether the block device contains an ext2/3/4 filesystem at the
+    /// given offset.
+    pub fn probe(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> bool {
+        match Self::read_superblock(blockdev, offset) {
+            Ok(sb) => sb.magic == EXT2_SUPERBLOCK_MAGIC,
+            Err(_) => false
+        }
+    }
+
+    pub fn init(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> Result<Rc<RefCell<Self>>, ErrorCode> {
+        let sb = Self::read_superblock(blockdev, offset)?;
+
+        if sb.magic != EXT2_SUPERBLOCK_MAGIC {
+            return Err(ErrorCode::Unsupported);
+        }
+
+        let (inode_size, incompat) = if sb.rev_level >= EXT2_DYNAMIC_REV {
+            (sb.inode_size as usize, sb.feature_incompat)
+        } else {
+            (EXT2_GOOD_OLD_INODE_SIZE, 0)
+        };
+
+        if (incompat & !EXT2_FEATURE_INCOMPAT_SUPPORTED) != 0 {
+            println!("ext2: Unsupported incompatible features: {:x}", incompat);
+            return Err(ErrorCode::Unsupported);
+        }
+
+        if (incompat & EXT3_FEATURE_INCOMPAT_RECOVER) != 0 {
+            /* Replaying needs writes, read what is on disk and hope the
+             * files we want weren't changed since the last clean unmount */
+            println!("ext2: Journal was not replayed, recent changes may be missing");
+        }
+
+        let desc_size = if ((incompat & EXT4_FEATURE_INCOMPAT_64BIT) != 0) && (sb.desc_size as usize >= EXT2_MIN_DESC_SIZE) {
+            sb.desc_size as usize
+        } else {
+            EXT2_MIN_DESC_SIZE
+        };
+
+        let block_size = EXT2_MIN_BLOCK_SIZE << sb.log_block_size;
+        let bgdt_offset = (sb.first_data_block as usize + 1) * block_size;
+
+        let fs = Rc::new_cyclic(|me| {
+            RefCell::new(Ext2Filesystem {
+                offset,
+                block_size,
+                inode_size,
+                inodes_per_group: sb.inodes_per_group as usize,
+                bgdt_offset: bgdt_offset as isize,
+                desc_size,
+                rootdir: Ext2File {
+                    inode: Ext2DataInode::default(),
+                    size: 0,
+                    attr: FileAttribute::Directory as u32,
+                    fs: me.clone()
+                },
+
+                block: Rc::clone(blockdev),
+                rc: me.clone(),
+            })
+        });
+
+        let root = fs.borrow().open_inode(EXT2_ROOT_INO)?;
+        fs.borrow_mut().rootdir = root;
+
+        Ok(fs)
+    }
+
+    fn read_superblock(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> Result<Ext2DataSuperblock, ErrorCode> {
+        let data = blockdev.borrow().read(offset + EXT2_SUPERBLOCK_OFFSET, mem::size_of::<Ext2DataSuperblock>())?;
+        Ok(unsafe { ptr::read(data.as_ptr() as *const _) })
+    }
+
+    /// Read a range of the filesystem that may not be sector-aligned
+    fn read_bytes(&self, off: isize, size: usize) -> Result<Vec<u8>, ErrorCode> {
+        let mut data = vec![0; size];
+        block::read_unaligned(&*self.block.borrow(), self.offset + off, &mut data)?;
+        Ok(data)
+    }
+
+    fn read_block(&self, block: u64) -> Result<Vec<u8>, ErrorCode> {
+        self.block.borrow().read(self.offset + (block as usize * self.block_size) as isize, self.block_size)
+    }
+
+    fn read_group_desc(&self, group: usize) -> Result<Ext2DataGroupDesc, ErrorCode> {
+        /* Without 64BIT, the upper halves aren't there and read as zero */
+        let mut raw = [0u8; mem::size_of::<Ext2DataGroupDesc>()];
+        let len = self.desc_size.min(raw.len());
+        let data = self.read_bytes(self.bgdt_offset + (group * self.desc_size) as isize, len)?;
+        raw[..len].copy_from_slice(&data);
+
+        Ok(unsafe { ptr::read(raw.as_ptr() as *const _) })
+    }
+
+    fn read_inode(&self, ino: u32) -> Result<Ext2DataInode, ErrorCode> {
+        if ino == 0 {
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let group = (ino as usize - 1) / self.inodes_per_group;
+        let index = (ino as usize - 1) % self.inodes_per_group;
+
+        let desc = self.read_group_desc(group)?;
+        let table = desc.inode_table as u64 | ((desc.inode_table_hi as u64) << 32);
+        let off = table.checked_mul(self.block_size as u64)
+            .and_then(|off| off.checked_add((index * self.inode_size) as u64))
+            .and_then(|off| isize::try_from(off).ok());
+        let off = match off {
+            Some(off) => off,
+            None      => {
+                println!("ext2: Inode table of group {} is beyond the addressable range", group);
+                return Err(ErrorCode::OutOfBounds);
+            }
+        };
+
+        let data = self.read_bytes(off, mem::size_of::<Ext2DataInode>())?;
+        Ok(unsafe { ptr::read(data.as_ptr() as *const _) })
+    }
+
+    fn open_inode(&self, ino: u32) -> Result<Ext2File, ErrorCode> {
+        let inode = self.read_inode(ino)?;
+
+        let attr = match inode.mode & EXT2_S_IFMT {
+            EXT2_S_IFDIR => FileAttribute::Directory as u32,
+            EXT2_S_IFREG => FileAttribute::File as u32,
+            /* Only support files and directories for now */
+            _            => return Err(ErrorCode::FileUnsupported)
+        };
+
+        Ok(Ext2File {
+            inode,
+            size: inode.size as usize,
+            attr,
+            fs: self.rc.clone()
+        })
+    }
+
+    /// Find the inode number of an entry within a directory
+    fn dir_lookup(&self, dir: &Ext2File, name: &str) -> Result<u32, ErrorCode> {
+        let mut lblock = 0;
+
+        while (lblock * self.block_size) < dir.size {
+            let buf = match self.map_block(&dir.inode, lblock)? {
+                Some(block) => self.read_block(block)?,
+                None        => {
+                    lblock += 1;
+                    continue;
+                }
+            };
+
+            let mut pos = 0;
+            while (pos + mem::size_of::<Ext2DataDirent>()) <= buf.len() {
+                let dirent: Ext2DataDirent = unsafe { ptr::read(buf.as_ptr().add(pos) as *const _) };
+
+                if (dirent.rec_len as usize) < mem::size_of::<Ext2DataDirent>() {
+                    println!("ext2: Corrupt directory entry");
+                    return Err(ErrorCode::ReadFailure);
+                }
+
+                let name_start = pos + mem::size_of::<Ext2DataDirent>();
+                let name_end   = name_start + dirent.name_len as usize;
+                if (dirent.inode != 0) &&
+                   (name_end <= buf.len()) &&
+                   (&buf[name_start..name_end] == name.as_bytes()) {
+                    return Ok(dirent.inode);
+                }
+
+                pos += dirent.rec_len as usize;
+            }
+
+            lblock += 1;
+        }
+
+        Err(ErrorCode::FileNotFound)
+    }
+
+    /// Map a logical block of an inode to a filesystem block
+    ///
+    /// Returns None for holes in sparse files.
+    fn map_block(&self, inode: &Ext2DataInode, lblock: usize) -> Result<Option<u64>, ErrorCode> {
+        if (inode.flags & EXT4_EXTENTS_FL) != 0 {
+            return self.map_block_extent(inode, lblock);
+        }
+
+        let ptrs = self.block_size / mem::size_of::<u32>();
+        let blocks = inode.block;
+
+        if lblock < EXT2_NDIR_BLOCKS {
+            return Ok(Self::block_nonzero(blocks[lblock]));
+        }
+
+        /* Walk through single, double, then triple indirect blocks */
+        let mut lblock = lblock - EXT2_NDIR_BLOCKS;
+        let mut span = ptrs;
+        for (level, &root) in blocks[EXT2_IND_BLOCK..].iter().enumerate() {
+            if lblock >= span {
+                lblock -= span;
+                span *= ptrs;
+                continue;
+            }
+
+            let mut block = root;
+            for depth in (0..=level).rev() {
+                if block == 0 {
+                    return Ok(None);
+                }
+                let data = self.read_block(block as u64)?;
+                let idx = (lblock / ptrs.pow(depth as u32)) % ptrs;
+                block = u32::from_le_bytes(data[(idx * 4)..(idx * 4 + 4)].try_into().unwrap());
+            }
+
+            return Ok(Self::block_nonzero(block));
+        }
+
+        Err(ErrorCode::OutOfBounds)
+    }
+
+    fn map_block_extent(&self, inode: &Ext2DataInode, lblock: usize) -> Result<Option<u64>, ErrorCode> {
+        let blocks = inode.block;
+        let mut node: Vec<u8> = blocks.iter().flat_map(|blk| blk.to_le_bytes()).collect();
+
+        loop {
+            let hdr: Ext4DataExtentHeader = unsafe { ptr::read(node.as_ptr() as *const _) };
+            if hdr.magic != EXT4_EXTENT_MAGIC {
+                println!("ext2: Bad extent header");
+                return Err(ErrorCode::ReadFailure);
+            }
+
+            let entries = (hdr.entries as usize).min((node.len() / EXT4_EXTENT_ENTRY_SIZE) - 1);
+            let entry_ptr = |idx: usize| unsafe { node.as_ptr().add((idx + 1) * EXT4_EXTENT_ENTRY_SIZE) };
+
+            if hdr.depth == 0 {
+                for idx in 0..entries {
+                    let ext: Ext4DataExtent = unsafe { ptr::read(entry_ptr(idx) as *const _) };
+                    let (len, uninit) = if ext.len > EXT4_EXTENT_INIT_MAX_LEN {
+                        (ext.len - EXT4_EXTENT_INIT_MAX_LEN, true)
+                    } else {
+                        (ext.len, false)
+                    };
+
+                    let first = ext.block as usize;
+                    if (first..(first + len as usize)).contains(&lblock) {
+                        if uninit {
+                            /* Uninitialized extents read back as zeros */
+                            return Ok(None);
+                        }
+                        let start = ((ext.start_hi as u64) << 32) | ext.start_lo as u64;
+                        return Ok(Some(start + (lblock - first) as u64));
+                    }
+                }
+
+                return Ok(None);
+            }
+
+            /* Follow the last index whose range starts at or before lblock */
+            let mut next: Option<u64> = None;
+            for idx in 0..entries {
+                let ext: Ext4DataExtentIdx = unsafe { ptr::read(entry_ptr(idx) as *const _) };
+                if ext.block as usize > lblock {
+                    break;
+                }
+                next = Some(((ext.leaf_hi as u64) << 32) | ext.leaf_lo as u64);
+            }
+
+            node = match next {
+                Some(block) => self.read_block(block)?,
+                None        => return Ok(None)
+            };
+        }
+    }
+
+    fn block_nonzero(block: u32) -> Option<u64> {
+        if block == 0 {
+            None
+        } else {
+            Some(block as u64)
+        }
+    }
+
+    pub fn get_block_size(&self) -> usize {
+        self.block_size
+    }
+}
+
+#[derive(Clone)]
+pub struct Ext2File {
+    inode: Ext2DataInode, //< Copy of on-disk inode
+
+    size: usize, //< File size
+
+    attr: u32,   //< File attributes
+
+    fs: Weak<RefCell<Ext2Filesystem>>,
+}
+
+impl File for Ext2File {
+    fn get_size(&self) -> usize {
+        self.size
+    }
+
+    fn get_attr(&self) -> u32 {
+        self.attr
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if (offset as usize + buf.len()) > self.size {
+            println!("Ext2File: Attempt to read past end of file");
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let fs = self.fs.upgrade().expect("Could not upgrade Ext2File::fs");
+        let fs = fs.borrow();
+
+        let block_size = fs.get_block_size();
+
+        let mut pos = 0;
+        while pos < buf.len() {
+            let foff = offset as usize + pos;
+            let boff = foff % block_size;
+            let len = (block_size - boff).min(buf.len() - pos);
+            let dest = &mut buf[pos..(pos + len)];
+
+            match fs.map_block(&self.inode, foff / block_size)? {
+                Some(blk) => {
+                    let off = fs.offset + ((blk as usize * block_size) + boff) as isize;
+                    block::read_unaligned(&*fs.block.borrow(), off, dest)?;
+                },
+                None => dest.fill(0)
+            }
+
+            pos += len;
+        }
+
+        Ok(())
+    }
+
+    fn close(&self) {
+
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+
+/// Offset of the superblock from the start of the filesystem
+const EXT2_SUPERBLOCK_OFFSET: isize = 1024;
+const EXT2_SUPERBLOCK_MAGIC: u16 = 0xEF53;
+const EXT2_MIN_BLOCK_SIZE: usize = 1024;
+const EXT2_GOOD_OLD_INODE_SIZE: usize = 128;
+const EXT2_MIN_DESC_SIZE: usize = 32;
+const EXT2_DYNAMIC_REV: u32 = 1;
+const EXT2_ROOT_INO: u32 = 2;
+
+const EXT2_NDIR_BLOCKS: usize = 12; //< Number of direct blocks in inode
+const EXT2_IND_BLOCK: usize   = 12; //< Index of single indirect block
+const EXT2_N_BLOCKS: usize    = 15; //< Total number of block pointers in inode
+
+const EXT2_FEATURE_INCOMPAT_FILETYPE: u32  = 0x0002; //< Directory entries record file type
+const EXT3_FEATURE_INCOMPAT_RECOVER: u32   = 0x0004; //< Journal needs recovery
+const EXT4_FEATURE_INCOMPAT_EXTENTS: u32   = 0x0040; //< Files use extent trees
+const EXT4_FEATURE_INCOMPAT_64BIT: u32     = 0x0080; //< 64-bit block numbers
+const EXT4_FEATURE_INCOMPAT_MMP: u32       = 0x0100; //< Multi-mount protection
+const EXT4_FEATURE_INCOMPAT_FLEX_BG: u32   = 0x0200; //< Flexible block groups
+const EXT4_FEATURE_INCOMPAT_CSUM_SEED: u32 = 0x2000; //< Metadata checksum seed in superblock
+const EXT4_FEATURE_INCOMPAT_LARGEDIR: u32  = 0x4000; //< Large directories
+/// Incompatible features that do not prevent reading the filesystem
+const EXT2_FEATURE_INCOMPAT_SUPPORTED: u32 = EXT2_FEATURE_INCOMPAT_FILETYPE |
+                                             EXT3_FEATURE_INCOMPAT_RECOVER  |
+                                             EXT4_FEATURE_INCOMPAT_EXTENTS  |
+                                             EXT4_FEATURE_INCOMPAT_64BIT    |
+                                             EXT4_FEATURE_INCOMPAT_MMP      |
+                                             EXT4_FEATURE_INCOMPAT_FLEX_BG  |
+                                             EXT4_FEATURE_INCOMPAT_CSUM_SEED |
+                                             EXT4_FEATURE_INCOMPAT_LARGEDIR;
+
+const EXT2_S_IFMT: u16  = 0xF000; //< File type mask
+const EXT2_S_IFREG: u16 = 0x8000; //< Regular file
+const EXT2_S_IFDIR: u16 = 0x4000; //< Directory
+
+const EXT4_EXTENTS_FL: u32 = 0x00080000; //< Inode uses extents
+const EXT4_EXTENT_MAGIC: u16 = 0xF30A;
+const EXT4_EXTENT_ENTRY_SIZE: usize = 12;
+/// Extents longer than this are uninitialized, with length offset by this value
+const EXT4_EXTENT_INIT_MAX_LEN: u16 = 32768;
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct Ext2DataSuperblock {
+    inodes_count: u32,      //< Total number of inodes
+    blocks_count: u32,      //< Total number of blocks
+    r_blocks_count: u32,    //< Number of blocks reserved for the superuser
+    free_blocks_count: u32, //< Number of free blocks
+    free_inodes_count: u32, //< Number of free inodes
+    first_data_block: u32,  //< Block containing the superblock
+    log_block_size: u32,    //< Block size is 1024 << log_block_size
+    log_frag_size: u32,     //< Fragment size is 1024 << log_frag_size
+    blocks_per_group: u32,  //< Number of blocks in each block group
+    frags_per_group: u32,   //< Number of fragments in each block group
+    inodes_per_group: u32,  //< Number of inodes in each block group
+    mtime: u32,             //< Last mount time
+    wtime: u32,             //< Last write time
+    mnt_count: u16,         //< Mounts since last check
+    max_mnt_count: u16,     //< Mounts allowed before a check is required
+    magic: u16,             //< Magic number, `EXT2_SUPERBLOCK_MAGIC`
+    state: u16,             //< Filesystem state
+    errors: u16,            //< Behaviour when detecting errors
+    minor_rev_level: u16,   //< Minor revision level
+    lastcheck: u32,         //< Time of last check
+    checkinterval: u32,     //< Maximum time between checks
+    creator_os: u32,        //< OS that created the filesystem
+    rev_level: u32,         //< Revision level
+    def_resuid: u16,        //< Default UID for reserved blocks
+    def_resgid: u16,        //< Default GID for reserved blocks
+    /* Only valid if rev_level >= EXT2_DYNAMIC_REV */
+    first_ino: u32,         //< First non-reserved inode
+    inode_size: u16,        //< Size of inode structure
+    block_group_nr: u16,    //< Block group containing this superblock
+    feature_compat: u32,    //< Compatible feature set
+    feature_incompat: u32,  //< Incompatible feature set
+    feature_ro_compat: u32, //< Read-only compatible feature set
+    uuid: [u8; 16],         //< Volume UUID
+    volume_name: [u8; 16],  //< Volume name
+    last_mounted: [u8; 64], //< Path volume was last mounted to
+    algo_bitmap: u32,       //< Compression algorithms used
+    prealloc_blocks: u8,    //< Blocks to preallocate for files
+    prealloc_dir_blocks: u8,//< Blocks to preallocate for directories
+    reserved_gdt_blocks: u16, //< Reserved GDT blocks for online growth
+    journal_uuid: [u8; 16], //< UUID of journal superblock
+    journal_inum: u32,      //< Inode number of journal file
+    journal_dev: u32,       //< Device number of journal file
+    last_orphan: u32,       //< Start of list of inodes to delete
+    hash_seed: [u32; 4],    //< HTREE hash seed
+    def_hash_version: u8,   //< Default hash version to use
+    jnl_backup_type: u8,    //< Journal backup type
+    desc_size: u16,         //< Size of group descriptors, if 64BIT feature is set
+    _reserved: [u8; 768],
+}
+const _EXT2_SUPERBLOCK_SZ_TEST: [u8; 1024] = [0; mem::size_of::<Ext2DataSuperblock>()];
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct Ext2DataGroupDesc {
+    block_bitmap: u32,      //< Block containing block bitmap
+    inode_bitmap: u32,      //< Block containing inode bitmap
+    inode_table: u32,       //< First block of inode table
+    free_blocks_count: u16, //< Number of free blocks in group
+    free_inodes_count: u16, //< Number of free inodes in group
+    used_dirs_count: u16,   //< Number of directories in group
+    _pad: u16,
+    _reserved: [u8; 12],
+    /* Only present with the 64BIT feature */
+    block_bitmap_hi: u32,   //< Upper 32 bits of block_bitmap
+    inode_bitmap_hi: u32,   //< Upper 32 bits of inode_bitmap
+    inode_table_hi: u32,    //< Upper 32 bits of inode_table
+    _reserved_hi: [u8; 20],
+}
+const _EXT2_GROUP_DESC_SZ_TEST: [u8; 64] = [0; mem::size_of::<Ext2DataGroupDesc>()];
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Default)]
+#[repr(C, packed(1))]
+struct Ext2DataInode {
+    mode: u16,        //< File type and permissions
+    uid: u16,         //< Owner UID
+    size: u32,        //< Lower 32 bits of size in bytes
+    atime: u32,       //< Last access time
+    ctime: u32,       //< Creation time
+    mtime: u32,       //< Last modification time
+    dtime: u32,       //< Deletion time
+    gid: u16,         //< Owner GID
+    links_count: u16, //< Number of hard links
+    blocks: u32,      //< Number of 512-byte sectors reserved for data
+    flags: u32,       //< Inode flags
+    osd1: u32,        //< OS-specific value
+    block: [u32; EXT2_N_BLOCKS], //< Block pointers, or extent tree root
+    generation: u32,  //< File version, used by NFS
+    file_acl: u32,    //< Extended attribute block
+    size_high: u32,   //< Upper 32 bits of size for regular files
+    faddr: u32,       //< Fragment address
+    osd2: [u8; 12],   //< OS-specific values
+}
+const _EXT2_INODE_SZ_TEST: [u8; EXT2_GOOD_OLD_INODE_SIZE] = [0; mem::size_of::<Ext2DataInode>()];
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct Ext2DataDirent {
+    inode: u32,    //< Inode number, 0 if entry is unused
+    rec_len: u16,  //< Length of this entry, including name
+    name_len: u8,  //< Length of name
+    file_type: u8, //< File type if FILETYPE feature is set, else upper bits of name_len
+}
+const _EXT2_DIRENT_SZ_TEST: [u8; 8] = [0; mem::size_of::<Ext2DataDirent>()];
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct Ext4DataExtentHeader {
+    magic: u16,      //< Magic number, `EXT4_EXTENT_MAGIC`
+    entries: u16,    //< Number of valid entries following header
+    max: u16,        //< Maximum number of entries that could follow header
+    depth: u16,      //< Depth of tree below this node, 0 for leaves
+    generation: u32, //< Generation of tree
+}
+const _EXT4_EXTENT_HEADER_SZ_TEST: [u8; EXT4_EXTENT_ENTRY_SIZE] = [0; mem::size_of::<Ext4DataExtentHeader>()];
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct Ext4DataExtentIdx {
+    block: u32,   //< First logical block covered by this index
+    leaf_lo: u32, //< Lower 32 bits of next-level node block
+    leaf_hi: u16, //< Upper 16 bits of next-level node block
+    _unused: u16,
+}
+const _EXT4_EXTENT_IDX_SZ_TEST: [u8; EXT4_EXTENT_ENTRY_SIZE] = [0; mem::size_of::<Ext4DataExtentIdx>()];
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct Ext4DataExtent {
+    block: u32,    //< First logical block covered by this extent
+    len: u16,      //< Number of blocks covered by this extent
+    start_hi: u16, //< Upper 16 bits of first physical block
+    start_lo: u32, //< Lower 32 bits of first physical block
+}
+const _EXT4_EXTENT_SZ_TEST: [u8; EXT4_EXTENT_ENTRY_SIZE] = [0; mem::size_of::<Ext4DataExtent>()];
@@ -2,7 +2,7 @@ extern crate alloc;
 
 use alloc::boxed::Box;
 use alloc::rc::{Rc, Weak};
-use alloc::vec;
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::any::Any;
 use core::cell::RefCell;
@@ -11,15 +11,14 @@ use core::fmt::Write;
 use core::ptr;
 
 use crate::errors::ErrorCode;
-use crate::storage::{block::BlockDevice, fs::FileAttribute};
+use crate::storage::{block::{self, BlockDevice}, fs::FileAttribute};
 use crate::io::output;
-use super::{File, Filesystem};
+use super::{DirEntry, DirIter, File, FileTime, Filesystem};
 
 
 pub struct FATFilesystem {
     offset: isize, //< Offset of filesystem into block device
 
-    sector_size: usize,    //< Number of bytes per sector
     cluster_size: usize,   //< Number of bytes per cluster
     fat_offset: isize,   //< Offset into filesystem of first FAT
     fat_size: usize,     //< Size of FAT in bytes
@@ -27,8 +26,6 @@ pub struct FATFilesystem {
 
     rootdir: FATFile, //< FATFile representing root directory
 
-    cache: Rc<RefCell<FATFilesystemCache>>, //< FAT cluster cache
-
     block: Rc<RefCell<dyn BlockDevice>>, //< Underlying block device
 
     rc: Weak<RefCell<Self>>,
@@ -40,11 +37,15 @@ impl Filesystem for FATFilesystem {
     }
 
     fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode> {
-        let mut dir: FATFile = if path.starts_with('/') || start_dir.is_none() {
-            self.rootdir.clone()
-        } else {
-            /* Not ideal, but is fine while we only have one FS */
-            start_dir.unwrap().as_any().downcast_ref::<FATFile>().unwrap().clone()
+        let mut dir: FATFile = match start_dir {
+            Some(start) if !path.starts_with('/') => {
+                /* Directories from other filesystems cannot be searched here */
+                match start.as_any().downcast_ref::<FATFile>() {
+                    Some(file) => file.clone(),
+                    None       => return Err(ErrorCode::FileNotFound)
+                }
+            },
+            _ => self.rootdir.clone()
         };
 
         let mut cpath = path;
@@ -63,46 +64,71 @@ impl Filesystem for FATFilesystem {
             }
         }
 
-        if cpath.len() > 11 {
-            /* Long filenames not yet supported */
-            return Err(ErrorCode::FileUnsupported);
-        }
-
-        let mut dent_off = dir.first_cluster;
+        for entry in self.iter_dir(&dir) {
+            let entry = entry?;
 
-        while dent_off > 0 {
-            let buf = match self.block.borrow().read(dent_off, self.cluster_size) {
-                Ok(data) => data,
-                Err(err) => return Err(err)
+            let long_match = match &entry.long_name {
+                Some(name) => Self::lfn_filename_eq(name, cpath),
+                None       => false
             };
 
-            for i in 0..(self.cluster_size / mem::size_of::<FATDataDirent>()) {
-                let dirent: FATDataDirent = unsafe { ptr::read(buf.as_ptr().add(i * mem::size_of::<FATDataDirent>()) as *const _) };
-
-                if (dirent.filename[0] == b'\0') || ((dirent.attr & FATDirentAttr::VolumeLabel as u8) != 0) {
-                    continue;
-                }
-
-                if Self::fat_filename_eq(&dirent.filename, cpath) {
-                    return Ok(Box::new(self.populate_file(dirent)));
-                }
-            }
-
-            match self.get_next_cluster(dent_off)? {
-                Some(off) => dent_off = off,
-                None      => return Err(ErrorCode::FileNotFound)
+            if long_match || Self::fat_filename_eq(&entry.dirent.filename, cpath) {
+                return Ok(Box::new(self.populate_file(entry.dirent)));
             }
-
         }
 
-
         Err(ErrorCode::FileNotFound)
     }
 
+    fn read_dir<'a>(&'a self, dir: &dyn File) -> Result<DirIter<'a>, ErrorCode> {
+        let dir = match dir.as_any().downcast_ref::<FATFile>() {
+            Some(file) => file.clone(),
+            None       => return Err(ErrorCode::FileNotFound)
+        };
+
+        if (dir.attr & FileAttribute::Directory as u32) == 0 {
+            return Err(ErrorCode::FileNotFound);
+        }
 
+        Ok(Box::new(self.iter_dir(&dir).map(|entry| {
+            entry.map(|entry| {
+                let name = match &entry.long_name {
+                    Some(name) => char::decode_utf16(name.iter().copied())
+                        .map(|ch| ch.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect(),
+                    None => Self::fat_filename_to_string(&entry.dirent)
+                };
+
+                let dirent = &entry.dirent;
+                DirEntry {
+                    name,
+                    size: dirent.filesize as usize,
+                    attr: Self::fat_attr_to_attr(dirent.attr),
+                    created: Self::fat_time(dirent.create_date, dirent.create_time),
+                    modified: Self::fat_time(dirent.date, dirent.time),
+                }
+            })
+        })))
+    }
 }
 
 impl FATFilesystem {
+    /// Check whether the block device contains a plausible FAT boot sector at
+    /// the given offset.
+    pub fn probe(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> bool {
+        let bs = match blockdev.borrow().read(offset, 512) {
+            Ok(data) => data,
+            Err(_)   => return false
+        };
+        let bs: FATDataBootsector = unsafe { core::ptr::read(bs.as_ptr() as *const _) };
+
+        let bytes_per_sector = bs.bytes_per_sector;
+        (bs.signature == FAT_BOOTSECTOR_SIGNATURE) &&
+        bytes_per_sector.is_power_of_two() && (bytes_per_sector >= 512) &&
+        bs.sectors_per_cluster.is_power_of_two() &&
+        (bs.fat_copies != 0) && (bs.reserved_sectors != 0)
+    }
+
     pub fn init(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> Result<Rc<RefCell<Self>>, ErrorCode> {
         let bs = blockdev.borrow().read(offset, 512)?;
         assert_eq!(bs.len(), 512);
@@ -117,7 +143,6 @@ impl FATFilesystem {
         Ok(Rc::new_cyclic(|me| {
             RefCell::new(FATFilesystem {
                 offset,
-                sector_size,
                 cluster_size: bs.sectors_per_cluster as usize * sector_size,
                 fat_offset,
                 fat_size,
@@ -128,7 +153,6 @@ impl FATFilesystem {
                     attr: FileAttribute::Directory as u32,
                     fs: me.clone()
                 },
-                cache: Rc::new(RefCell::new(FATFilesystemCache::new(4))),
 
                 block: Rc::clone(blockdev),
                 rc: me.clone(),
@@ -137,63 +161,97 @@ impl FATFilesystem {
     }
 
     fn populate_file(&self, dirent: FATDataDirent) -> FATFile {
+        FATFile {
+            first_cluster: self.data_offset + (dirent.start_cluster as isize - 2) * self.cluster_size as isize,
+            size: dirent.filesize as usize,
+            attr: Self::fat_attr_to_attr(dirent.attr),
+            fs: self.rc.clone()
+        }
+    }
+
+    fn fat_attr_to_attr(fat_attr: u8) -> u32 {
         let mut attr: u32 = 0;
 
-        if (dirent.attr & FATDirentAttr::Directory as u8) != 0 {
+        if (fat_attr & FATDirentAttr::Directory as u8) != 0 {
             attr |= FileAttribute::Directory as u32;
         } else {
             /* Only support files and directories for now */
             attr |= FileAttribute::File as u32;
         }
 
-        FATFile {
-            first_cluster: self.data_offset + (dirent.start_cluster as isize - 2) * self.cluster_size as isize,
-            size: dirent.filesize as usize,
-            attr,
-            fs: self.rc.clone()
+        if (fat_attr & FATDirentAttr::ReadOnly as u8) != 0 {
+            attr |= FileAttribute::ReadOnly as u32;
+        }
+        if (fat_attr & FATDirentAttr::Hidden as u8) != 0 {
+            attr |= FileAttribute::Hidden as u32;
+        }
+        if (fat_attr & FATDirentAttr::System as u8) != 0 {
+            attr |= FileAttribute::System as u32;
+        }
+
+        attr
+    }
+
+    /// Convert FAT date and time fields to a FileTime
+    ///
+    /// Returns None if the date is unset.
+    fn fat_time(date: u16, time: u16) -> Option<FileTime> {
+        if date == 0 {
+            return None;
         }
+
+        Some(FileTime {
+            year:   1980 + (date >> 9),
+            month:  ((date >> 5) & 0x0F) as u8,
+            day:    (date & 0x1F) as u8,
+            hour:   (time >> 11) as u8,
+            minute: ((time >> 5) & 0x3F) as u8,
+            second: ((time & 0x1F) * 2) as u8,
+        })
     }
 
-    fn read_fat_data(&self, off: usize, sz: usize) -> Result<u32, ErrorCode> {
-        let read_addr = off - (off % self.sector_size);
-
-        let mut cache = self.cache.borrow_mut();
-
-        let buf = match cache.find(read_addr) {
-            Some(data) => data,
-            None       => {
-                match self.block.borrow().read(read_addr as isize, self.cluster_size) {
-                    Ok(data) => {
-                        cache.add(read_addr, data.clone());
-                        data
-                    },
-                    Err(err) => return Err(err)
-                }
-            }
-        };
+    /// Iterate over runs of physically contiguous clusters in a cluster chain
+    ///
+    /// # Arguments
+    /// * first_cluster: Offset of first cluster in chain
+    /// * limit: Number of bytes from the start of the chain that are needed,
+    ///   runs are not extended past this point
+    fn cluster_runs(&self, first_cluster: isize, limit: usize) -> FATClusterRuns<'_> {
+        FATClusterRuns {
+            fs: self,
+            next: Some(first_cluster),
+            remaining: limit,
+        }
+    }
 
-        let pos = off - read_addr;
-        let value = u32::from_le_bytes(buf[pos..(pos+4)].try_into().unwrap());
+    /// Iterate over the in-use entries of a directory
+    fn iter_dir<'a>(&'a self, dir: &FATFile) -> FATDirIter<'a> {
+        FATDirIter {
+            fs: self,
+            cluster: Some(dir.first_cluster),
+            buf: Vec::new(),
+            idx: 0,
+            lfn: FATLongName::new(),
+        }
+    }
 
-        match sz {
-            1 => Ok(value & 0x000000FF),
-            2 => Ok(value & 0x0000FFFF),
-            3 => Ok(value & 0x00FFFFFF),
-            4 => Ok(value),
-            _ => Err(ErrorCode::Unspecified)
+    fn read_fat_data(&self, off: usize, sz: usize) -> Result<u32, ErrorCode> {
+        if !(1..=4).contains(&sz) {
+            return Err(ErrorCode::Unspecified);
         }
+
+        /* Sector caching is left to the underlying block device */
+        let mut buf = [0u8; 4];
+        block::read_unaligned(&*self.block.borrow(), off as isize, &mut buf[..sz])?;
+
+        Ok(u32::from_le_bytes(buf))
     }
 
     fn get_fat_entry(&self, cluster: usize) -> Result<u32, ErrorCode> {
         /* TODO: Support FAT16/FAT32 */
         let offset = self.fat_offset as usize + ((cluster * 3) / 2);
 
-        let mut entry = if (offset % self.cluster_size) == (self.cluster_size - 1) {
-            self.read_fat_data(offset, 1)? |
-            self.read_fat_data(offset + 1, 1)? << 8
-        } else {
-            self.read_fat_data(offset, 2)?
-        };
+        let mut entry = self.read_fat_data(offset, 2)?;
 
         if (cluster & 0x01) != 0 {
             entry >>= 4;
@@ -228,34 +286,113 @@ impl FATFilesystem {
         }
     }
 
+    /// Compare a short (8.3) directory entry filename against a path
+    /// component, ignoring case.
     fn fat_filename_eq(fat_name: &[u8; 11], filename: &str) -> bool {
-        let mut fidx = 0;
+        let name = match Self::fat_filename_from_str(filename) {
+            Some(name) => name,
+            None       => return false
+        };
 
-        for &ch in filename.as_bytes() {
-            if fidx >= 11 {
-                /* Requested filename longer than FAT filename */
-                return false;
-            }
-            if ch == b'.' {
-                while (fidx < 11) && (fat_name[fidx] == b' ') {
-                    fidx += 1;
-                }
-                continue;
-            }
-            if fat_name[fidx] != ch {
-                return false;
-            }
-            fidx += 1;
+        fat_name.iter().zip(name.iter()).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// Convert a short directory entry filename into a displayable name
+    fn fat_filename_to_string(dirent: &FATDataDirent) -> String {
+        let mut fat_name = dirent.filename;
+        if fat_name[0] == FAT_DIRENT_E5_ESCAPE {
+            fat_name[0] = FAT_DIRENT_DELETED;
+        }
+
+        /* Windows NT records all-lowercase base names and extensions here */
+        let lower_base = (dirent.nt_reserved & FAT_NT_LOWER_BASE) != 0;
+        let lower_ext  = (dirent.nt_reserved & FAT_NT_LOWER_EXT) != 0;
+
+        let convert = |chars: &[u8], lower: bool| -> String {
+            chars.iter()
+                .map(|&ch| if lower { ch.to_ascii_lowercase() } else { ch } as char)
+                .collect::<String>()
+                .trim_end_matches(' ')
+                .into()
+        };
+
+        let mut name = convert(&fat_name[..8], lower_base);
+        let ext = convert(&fat_name[8..], lower_ext);
+        if !ext.is_empty() {
+            name.push('.');
+            name.push_str(&ext);
+        }
+
+        name
+    }
+
+    /// Convert a path component into its padded, upper-case 8.3 directory
+    /// entry form.
+    ///
+    /// Returns None if the name cannot be represented as a short filename.
+    /// Short names are stored in an OEM codepage, which a UTF-8 path can't
+    /// name outside of ASCII, so names using other characters only match
+    /// their long filename.
+    fn fat_filename_from_str(filename: &str) -> Option<[u8; 11]> {
+        let mut fat_name = [b' '; 11];
+
+        /* Special entries, these cannot be split on the dot */
+        if (filename == ".") || (filename == "..") {
+            fat_name[..filename.len()].copy_from_slice(filename.as_bytes());
+            return Some(fat_name);
+        }
+
+        /* Trailing dots are not significant */
+        let filename = filename.trim_end_matches('.');
+
+        let (base, ext) = match filename.split_once('.') {
+            Some((base, ext)) => (base, ext),
+            None              => (filename, "")
+        };
+
+        if base.is_empty() || (base.len() > 8) || (ext.len() > 3) {
+            return None;
+        }
+
+        for (dest, &ch) in fat_name[..base.len()].iter_mut().zip(base.as_bytes()) {
+            *dest = Self::fat_filename_char(ch)?;
+        }
+        for (dest, &ch) in fat_name[8..(8 + ext.len())].iter_mut().zip(ext.as_bytes()) {
+            *dest = Self::fat_filename_char(ch)?;
+        }
+
+        Some(fat_name)
+    }
+
+    /// Normalize a single short filename character.
+    ///
+    /// Returns None if the character is not permitted in a short filename.
+    fn fat_filename_char(ch: u8) -> Option<u8> {
+        match ch {
+            0x00..=0x20 | 0x7F..=0xFF |
+            b'"' | b'*' | b'+' | b',' | b'.' | b'/' | b':' | b';' |
+            b'<' | b'=' | b'>' | b'?' | b'[' | b'\\' | b']' | b'|' => None,
+            _ => Some(ch.to_ascii_uppercase())
         }
+    }
 
-        while fidx < 11 {
-            if fat_name[fidx] != b' ' {
-                /* FAT filename longer than requested filename */
-                return false;
+    /// Compare a decoded VFAT long filename against a path component, ignoring
+    /// case.
+    fn lfn_filename_eq(lfn: &[u16], filename: &str) -> bool {
+        let mut lchars = char::decode_utf16(lfn.iter().copied());
+
+        for ch in filename.chars() {
+            match lchars.next() {
+                Some(Ok(lch)) => {
+                    if lch.to_lowercase().ne(ch.to_lowercase()) {
+                        return false;
+                    }
+                },
+                _ => return false
             }
-            fidx += 1;
         }
-        true
+
+        lchars.next().is_none()
     }
 
     pub fn get_cluster_size(&self) -> usize {
@@ -283,54 +420,45 @@ impl File for FATFile {
         self.attr
     }
 
-    fn read(&self, mut offset: isize, size: usize) -> Result<Vec<u8>, ErrorCode> {
-        if ((self.attr & FileAttribute::Directory as u32) == 0) && (offset as usize + size) > self.size {
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if ((self.attr & FileAttribute::Directory as u32) == 0) && (offset as usize + buf.len()) > self.size {
             println!("FATFile: Attempt to read past end of file");
             return Err(ErrorCode::OutOfBounds);
         }
 
-        let fs = self.fs.upgrade().expect("Could not upgrade FATFile::fs");
-
-        let mut clust = self.first_cluster;
-
-        let cluster_size = fs.borrow().get_cluster_size();
-        let block = fs.borrow().block.clone();
-
-        while offset as usize > cluster_size {
-            clust = match fs.borrow().get_next_cluster(clust) {
-                Ok(Some(cluster)) => cluster,
-                Ok(None)          => return Err(ErrorCode::Unspecified),
-                Err(err)          => return Err(err)
-            };
-            offset -= cluster_size as isize;
+        if buf.is_empty() {
+            return Ok(());
         }
 
-        let mut read_data: Vec<u8> = vec!();
+        let fs = self.fs.upgrade().expect("Could not upgrade FATFile::fs");
+        let fs = fs.borrow();
+        let block = fs.block.clone();
 
+        let mut skip = offset as usize;
         let mut pos = 0;
-        while pos < size {
-            let data = match block.borrow().read(clust, cluster_size) {
-                Ok(res)  => res,
-                Err(err) => return Err(err)
-            };
 
-            if (size - pos) <= (cluster_size - offset as usize) {
-                read_data.extend_from_slice(&data[offset as usize..(size-pos)]);
-            } else {
-                read_data.extend_from_slice(&data[offset as usize..(cluster_size - offset as usize)]);
+        for run in fs.cluster_runs(self.first_cluster, skip + buf.len()) {
+            let (run_off, run_len) = run?;
 
-                clust = match fs.borrow().get_next_cluster(clust) {
-                    Ok(Some(cluster)) => cluster,
-                    Ok(None)          => return Err(ErrorCode::Unspecified),
-                    Err(err)          => return Err(err)
-                };
+            if skip >= run_len {
+                /* Run lies entirely before the requested range */
+                skip -= run_len;
+                continue;
             }
 
-            offset = 0;
-            pos += cluster_size;
+            let len = (run_len - skip).min(buf.len() - pos);
+            block::read_unaligned(&*block.borrow(), run_off + skip as isize, &mut buf[pos..(pos + len)])?;
+
+            skip = 0;
+            pos += len;
+
+            if pos == buf.len() {
+                return Ok(());
+            }
         }
 
-        Ok(read_data)
+        /* Cluster chain ended before the requested range */
+        Err(ErrorCode::OutOfBounds)
     }
 
     fn close(&self) {
@@ -343,65 +471,144 @@ impl File for FATFile {
 }
 
 
-struct FATFilesystemCacheItem {
-    cluster: usize, //< Offset of cached cluster into filesystem
-    rank: u8,       //< Rank of entry, indicating which was last used
-    data: Vec<u8>
-}
-
-struct FATFilesystemCache {
-    items: Vec<FATFilesystemCacheItem>
+/// Iterator over runs of contiguous clusters, yielding the offset of each run
+/// into the filesystem and its length in bytes.
+struct FATClusterRuns<'a> {
+    fs: &'a FATFilesystem,
+    next: Option<isize>, //< Offset of first cluster of next run, None at end of chain
+    remaining: usize,    //< Number of bytes still needed by the caller
 }
 
-impl FATFilesystemCache {
-    fn new(count: usize) -> FATFilesystemCache {
-        let mut ffc = FATFilesystemCache {
-            items: vec!()
+impl FATClusterRuns<'_> {
+    fn next_run(&mut self) -> Result<Option<(isize, usize)>, ErrorCode> {
+        let start = match self.next {
+            Some(start) if self.remaining > 0 => start,
+            _                                 => return Ok(None)
         };
 
-        for _ in 0..count {
-            ffc.items.push(FATFilesystemCacheItem {
-                cluster: usize::MAX,
-                rank: 0,
-                data: vec!()
-            })
+        let cluster_size = self.fs.get_cluster_size();
+        let mut last = start;
+        let mut len = cluster_size;
+
+        self.next = None;
+        while len < self.remaining {
+            match self.fs.get_next_cluster(last)? {
+                Some(cluster) if cluster == (last + cluster_size as isize) => {
+                    last = cluster;
+                    len += cluster_size;
+                },
+                next => {
+                    self.next = next;
+                    break;
+                }
+            }
         }
 
-        ffc
+        /* If the caller needs nothing past this run, the chain is not followed
+         * any further and iteration ends here. */
+        self.remaining = self.remaining.saturating_sub(len);
+
+        Ok(Some((start, len)))
     }
+}
+
+impl Iterator for FATClusterRuns<'_> {
+    type Item = Result<(isize, usize), ErrorCode>;
 
-    fn touch(&mut self, index: usize) {
-        let old_rank = self.items[index].rank;
-        for item in self.items.iter_mut() {
-            if item.rank < old_rank {
-                item.rank += 1;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_run() {
+            Ok(run) => run.map(Ok),
+            Err(err) => {
+                self.next = None;
+                Some(Err(err))
             }
         }
-        self.items[index].rank = 0;
     }
+}
+
+/// Directory entry as read while iterating through a directory
+struct FATDirIterEntry {
+    dirent: FATDataDirent,        //< Short directory entry
+    long_name: Option<Vec<u16>>,  //< VFAT long filename, if present and valid
+}
+
+/// Iterator over the in-use entries of a FAT directory, skipping volume labels
+/// and deleted entries.
+struct FATDirIter<'a> {
+    fs: &'a FATFilesystem,
+    cluster: Option<isize>, //< Offset of cluster being iterated over, None at end
+    buf: Vec<u8>,           //< Contents of current cluster, empty if not yet read
+    idx: usize,             //< Index of next entry within cluster
+    lfn: FATLongName,       //< Long filename accumulator
+}
+
+impl FATDirIter<'_> {
+    fn next_dirent(&mut self) -> Result<Option<FATDirIterEntry>, ErrorCode> {
+        let per_cluster = self.fs.cluster_size / mem::size_of::<FATDataDirent>();
 
-    fn find(&mut self, cluster: usize) -> Option<Vec<u8>> {
-        for idx in 0..self.items.len() {
-            if self.items[idx].cluster == cluster {
-                self.touch(idx);
-                return Some(self.items[idx].data.clone());
+        while let Some(cluster) = self.cluster {
+            if self.buf.is_empty() {
+                self.buf = self.fs.block.borrow().read(cluster, self.fs.cluster_size)?;
+                self.idx = 0;
             }
+
+            while self.idx < per_cluster {
+                let off = self.idx * mem::size_of::<FATDataDirent>();
+                self.idx += 1;
+
+                let dirent: FATDataDirent = unsafe { ptr::read(self.buf.as_ptr().add(off) as *const _) };
+
+                if dirent.filename[0] == b'\0' {
+                    /* No further entries in this directory */
+                    self.cluster = None;
+                    return Ok(None);
+                }
+
+                if dirent.filename[0] == FAT_DIRENT_DELETED {
+                    self.lfn.reset();
+                    continue;
+                }
+
+                if dirent.attr == FAT_DIRENT_ATTR_LFN {
+                    let lfn_dirent: FATDataLFNDirent = unsafe { ptr::read(self.buf.as_ptr().add(off) as *const _) };
+                    self.lfn.push(&lfn_dirent);
+                    continue;
+                }
+
+                if (dirent.attr & FATDirentAttr::VolumeLabel as u8) != 0 {
+                    self.lfn.reset();
+                    continue;
+                }
+
+                let long_name = self.lfn.get(&dirent.filename).map(|name| name.to_vec());
+                self.lfn.reset();
+
+                return Ok(Some(FATDirIterEntry {
+                    dirent,
+                    long_name
+                }));
+            }
+
+            self.buf.clear();
+            self.cluster = self.fs.get_next_cluster(cluster)?;
         }
 
-        None
+        Ok(None)
     }
+}
 
-    fn add(&mut self, cluster: usize, data: Vec<u8>) {
-        let mut entry = 0;
-        /* Find the highest-ranked (oldest) entry */
-        for idx in 1..self.items.len() {
-            if self.items[idx].rank > self.items[entry].rank {
-                entry = idx;
+impl Iterator for FATDirIter<'_> {
+    type Item = Result<FATDirIterEntry, ErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_dirent() {
+            Ok(entry) => entry.map(Ok),
+            Err(err)  => {
+                /* Don't keep returning the same error */
+                self.cluster = None;
+                Some(Err(err))
             }
         }
-
-        self.items[entry].cluster = cluster;
-        self.items[entry].data    = data;
     }
 }
 
@@ -470,6 +677,7 @@ struct FATDataBootsector {
     signature: u16           //< Boot sector signature: 0x55, 0xAA
 }
 const _FAT_BOOTSECTOR_SZ_TEST: [u8; 512] = [0; mem::size_of::<FATDataBootsector>()];
+const FAT_BOOTSECTOR_SIGNATURE: u16 = 0xAA55;
 
 
 #[repr(u8)]
@@ -485,16 +693,480 @@ enum FATDirentAttr {
     Reserved    = (1 << 7)
 }
 
+/// Attribute value marking a VFAT long filename entry
+const FAT_DIRENT_ATTR_LFN: u8 = FATDirentAttr::ReadOnly as u8 |
+                                FATDirentAttr::Hidden as u8   |
+                                FATDirentAttr::System as u8   |
+                                FATDirentAttr::VolumeLabel as u8;
+
+/// First filename byte of a deleted directory entry
+const FAT_DIRENT_DELETED: u8 = 0xE5;
+/// First filename byte standing in for a literal 0xE5
+const FAT_DIRENT_E5_ESCAPE: u8 = 0x05;
+
+const FAT_NT_LOWER_BASE: u8 = 1 << 3; //< Base name is all lowercase
+const FAT_NT_LOWER_EXT: u8  = 1 << 4; //< Extension is all lowercase
+
 #[allow(dead_code)]
 #[repr(C, packed(1))]
 struct FATDataDirent {
-    filename: [u8; 11],  //< Short filename
-    attr: u8,            //< Attributes
-    _reserved: [u8; 10], //< Reserved: Used for VFAT, not yet supported
-    time: u16,           //< Modification time, in FAT time format
-    date: u16,           //< Modification date, in FAT date format
+    filename: [u8; 11],      //< Short filename
+    attr: u8,                //< Attributes
+    nt_reserved: u8,         //< Windows NT case information
+    create_time_fine: u8,    //< Creation time, 10 ms units
+    create_time: u16,        //< Creation time, in FAT time format
+    create_date: u16,        //< Creation date, in FAT date format
+    access_date: u16,        //< Last access date, in FAT date format
+    start_cluster_high: u16, //< Upper 16 bits of first cluster, FAT32 only
+    time: u16,               //< Modification time, in FAT time format
+    date: u16,               //< Modification date, in FAT date format
     start_cluster: u16,  //< First cluster of file, 0 if file is empty
     filesize: u32        //< Size of file in bytes
 }
 const _FAT_DIRENT_SZ_TEST: [u8; 32] = [0; mem::size_of::<FATDataDirent>()];
 
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct FATDataLFNDirent {
+    sequence: u8,        //< Sequence number, 0x40 set on the last (first stored) entry
+    name0: [u8; 10],     //< Characters 1-5 of this name fragment, UCS-2
+    attr: u8,            //< Attributes, always `FAT_DIRENT_ATTR_LFN`
+    ltype: u8,           //< Long entry type, 0 for name entries
+    checksum: u8,        //< Checksum of the associated short filename
+    name1: [u8; 12],     //< Characters 6-11 of this name fragment, UCS-2
+    _start_cluster: u16, //< Always 0
+    name2: [u8; 4],      //< Characters 12-13 of this name fragment, UCS-2
+}
+const _FAT_LFN_DIRENT_SZ_TEST: [u8; 32] = [0; mem::size_of::<FATDataLFNDirent>()];
+
+/// Number of UCS-2 characters held in a single LFN entry
+const FAT_LFN_CHARS_PER_ENTRY: usize = 13;
+/// Maximum number of LFN entries belonging to a single file
+const FAT_LFN_MAX_ENTRIES: usize = 20;
+const FAT_LFN_SEQ_LAST: u8 = 0x40;
+const FAT_LFN_SEQ_MSK: u8  = 0x1F;
+
+/// Accumulator for a chain of VFAT long filename entries, which are stored in
+/// reverse order directly preceeding the short entry they belong to.
+struct FATLongName {
+    name: [u16; FAT_LFN_CHARS_PER_ENTRY * FAT_LFN_MAX_ENTRIES],
+    len: usize,    //< Number of characters in name, 0 if no valid chain
+    next_seq: u8,  //< Sequence number expected for the next entry
+    checksum: u8,  //< Short filename checksum shared by all entries of chain
+}
+
+impl FATLongName {
+    fn new() -> Self {
+        FATLongName {
+            name: [0; FAT_LFN_CHARS_PER_ENTRY * FAT_LFN_MAX_ENTRIES],
+            len: 0,
+            next_seq: 0,
+            checksum: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.len      = 0;
+        self.next_seq = 0;
+    }
+
+    /// Add the next LFN entry of a chain. Out-of-sequence entries invalidate
+    /// the chain.
+    fn push(&mut self, dirent: &FATDataLFNDirent) {
+        let seq = dirent.sequence & FAT_LFN_SEQ_MSK;
+
+        if (dirent.sequence & FAT_LFN_SEQ_LAST) != 0 {
+            if (seq == 0) || (seq as usize > FAT_LFN_MAX_ENTRIES) {
+                self.reset();
+                return;
+            }
+            self.len      = seq as usize * FAT_LFN_CHARS_PER_ENTRY;
+            self.checksum = dirent.checksum;
+        } else if (self.next_seq == 0) ||
+                  (seq != self.next_seq) ||
+                  (dirent.checksum != self.checksum) {
+            self.reset();
+            return;
+        }
+
+        let base = (seq as usize - 1) * FAT_LFN_CHARS_PER_ENTRY;
+        let chars = dirent.name0.chunks(2)
+            .chain(dirent.name1.chunks(2))
+            .chain(dirent.name2.chunks(2));
+        for (idx, ch) in chars.enumerate() {
+            self.name[base + idx] = u16::from_le_bytes([ch[0], ch[1]]);
+        }
+
+        self.next_seq = seq - 1;
+    }
+
+    /// Get long filename, if a complete chain matching the given short filename
+    /// has been read.
+    fn get(&self, short_name: &[u8; 11]) -> Option<&[u16]> {
+        if (self.len == 0) || (self.next_seq != 0) ||
+           (Self::checksum(short_name) != self.checksum) {
+            return None;
+        }
+
+        /* Name is NUL-terminated and 0xFFFF-padded unless it fills the chain */
+        let len = self.name[..self.len].iter()
+            .position(|&ch| ch == 0x0000 || ch == 0xFFFF)
+            .unwrap_or(self.len);
+
+        Some(&self.name[..len])
+    }
+
+    fn checksum(short_name: &[u8; 11]) -> u8 {
+        short_name.iter().fold(0u8, |sum, &ch| {
+            sum.rotate_right(1).wrapping_add(ch)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::block::ram::RamBlockDevice;
+
+    const SECTOR: usize = 512;
+    const RESERVED: usize = 1;
+    const FAT_SECTORS: usize = 1;
+    const ROOT_ENTRIES: usize = 16;
+    const DATA_CLUSTERS: usize = 16;
+
+    /// File placed in a test image
+    struct TestFile {
+        name: &'static [u8; 11],    //< Directory entry name
+        long: Option<&'static str>, //< Long filename stored before the entry
+        path: &'static str,
+        clusters: &'static [usize], //< Cluster chain, in order
+        size: usize,
+    }
+
+    const CONTIG: TestFile = TestFile { name: b"CONTIG  BIN", long: None, path: "CONTIG.BIN", clusters: &[2, 3, 4, 5], size: 4 * 512 - 77 };
+    /* Runs of [6], [9], [7] and [10, 11], ending exactly at a cluster boundary */
+    const FRAG: TestFile   = TestFile { name: b"FRAG    BIN", long: None, path: "FRAG.BIN", clusters: &[6, 9, 7, 10, 11], size: 5 * 512 };
+    const TINY: TestFile   = TestFile { name: b"TINY    TXT", long: None, path: "TINY.TXT", clusters: &[8], size: 13 };
+    const KERNEL: TestFile = TestFile {
+        name: b"KERNEL~1ELF", long: Some("kernel-debug.elf"), path: "kernel-debug.elf", clusters: &[12, 13], size: 700,
+    };
+
+    /// Data stored at an offset into a file
+    fn pattern(seed: usize, pos: usize) -> u8 {
+        ((pos * 7 + pos / 251 + seed) % 256) as u8
+    }
+
+    fn set_fat12(fat: &mut [u8], cluster: usize, val: u16) {
+        let off = (cluster * 3) / 2;
+        let mut word = u16::from_le_bytes([fat[off], fat[off + 1]]);
+        if (cluster & 0x01) != 0 {
+            word = (word & 0x000F) | (val << 4);
+        } else {
+            word = (word & 0xF000) | (val & 0x0FFF);
+        }
+        fat[off..(off + 2)].copy_from_slice(&word.to_le_bytes());
+    }
+
+    /// Build the LFN entries for a long filename, in the order they are stored
+    fn lfn_dirents(long: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+        /* Offsets of the characters within an entry */
+        const CHAR_OFFSETS: [usize; FAT_LFN_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+        let mut chars: Vec<u16> = long.encode_utf16().collect();
+        if !chars.len().is_multiple_of(FAT_LFN_CHARS_PER_ENTRY) {
+            chars.push(0x0000);
+            chars.resize(chars.len().next_multiple_of(FAT_LFN_CHARS_PER_ENTRY), 0xFFFF);
+        }
+
+        let count = chars.len() / FAT_LFN_CHARS_PER_ENTRY;
+        (0..count).rev().map(|idx| {
+            let mut raw = [0u8; 32];
+            raw[0] = (idx + 1) as u8 | if (idx + 1) == count { FAT_LFN_SEQ_LAST } else { 0 };
+            raw[11] = FAT_DIRENT_ATTR_LFN;
+            raw[13] = FATLongName::checksum(short);
+
+            let part = &chars[(idx * FAT_LFN_CHARS_PER_ENTRY)..((idx + 1) * FAT_LFN_CHARS_PER_ENTRY)];
+            for (&off, ch) in CHAR_OFFSETS.iter().zip(part) {
+                raw[off..(off + 2)].copy_from_slice(&ch.to_le_bytes());
+            }
+            raw
+        }).collect()
+    }
+
+    /// Build a FAT12 image with one sector per cluster, holding the given files
+    fn build_image(files: &[&TestFile]) -> Vec<u8> {
+        let root_sectors = (ROOT_ENTRIES * mem::size_of::<FATDataDirent>()) / SECTOR;
+        let total = RESERVED + 2 * FAT_SECTORS + root_sectors + DATA_CLUSTERS;
+        let mut image = vec![0u8; total * SECTOR];
+
+        let bs = &mut image[..SECTOR];
+        bs[0x0B..0x0D].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+        bs[0x0D] = 1;
+        bs[0x0E..0x10].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+        bs[0x10] = 2;
+        bs[0x11..0x13].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+        bs[0x13..0x15].copy_from_slice(&(total as u16).to_le_bytes());
+        bs[0x15] = 0xF0;
+        bs[0x16..0x18].copy_from_slice(&(FAT_SECTORS as u16).to_le_bytes());
+        bs[510..512].copy_from_slice(&FAT_BOOTSECTOR_SIGNATURE.to_le_bytes());
+
+        let mut fat = vec![0u8; FAT_SECTORS * SECTOR];
+        set_fat12(&mut fat, 0, 0xFF0);
+        set_fat12(&mut fat, 1, 0xFFF);
+
+        let root = (RESERVED + 2 * FAT_SECTORS) * SECTOR;
+        let data = root + root_sectors * SECTOR;
+        let mut slot = 0;
+
+        for (idx, file) in files.iter().enumerate() {
+            for (pos, &cluster) in file.clusters.iter().enumerate() {
+                let next = file.clusters.get(pos + 1).map_or(0xFFF, |&next| next as u16);
+                set_fat12(&mut fat, cluster, next);
+
+                let start = pos * SECTOR;
+                let len = file.size.saturating_sub(start).min(SECTOR);
+                let off = data + (cluster - 2) * SECTOR;
+                for byte in 0..len {
+                    image[off + byte] = pattern(idx, start + byte);
+                }
+            }
+
+            for lfn in file.long.map(|long| lfn_dirents(long, file.name)).unwrap_or_default() {
+                image[(root + slot * 32)..(root + (slot + 1) * 32)].copy_from_slice(&lfn);
+                slot += 1;
+            }
+
+            let dirent = &mut image[(root + slot * 32)..(root + (slot + 1) * 32)];
+            slot += 1;
+            dirent[..11].copy_from_slice(file.name);
+            dirent[11] = FATDirentAttr::Archive as u8;
+            dirent[26..28].copy_from_slice(&(file.clusters[0] as u16).to_le_bytes());
+            dirent[28..32].copy_from_slice(&(file.size as u32).to_le_bytes());
+        }
+
+        for copy in 0..2 {
+            let off = (RESERVED + copy * FAT_SECTORS) * SECTOR;
+            image[off..(off + fat.len())].copy_from_slice(&fat);
+        }
+
+        image
+    }
+
+    fn mount(files: &[&TestFile]) -> Rc<RefCell<FATFilesystem>> {
+        let dev: Rc<RefCell<dyn BlockDevice>> = Rc::new(RefCell::new(RamBlockDevice::new(build_image(files))));
+        assert!(FATFilesystem::probe(&dev, 0));
+        FATFilesystem::init(&dev, 0).ok().unwrap()
+    }
+
+    /// Offsets around each cluster boundary, and the start and end of the file
+    fn interesting_offsets(size: usize) -> Vec<usize> {
+        let mut offsets = Vec::new();
+        for boundary in (0..=size).step_by(SECTOR).chain([size]) {
+            for off in boundary.saturating_sub(2)..=(boundary + 2) {
+                if off <= size && !offsets.contains(&off) {
+                    offsets.push(off);
+                }
+            }
+        }
+        offsets.push(size / 2);
+        offsets
+    }
+
+    /// Read every combination of interesting start and end offsets
+    fn check_reads(files: &[&TestFile], idx: usize) {
+        let fs = mount(files);
+        let file = fs.borrow().find_file(None, files[idx].path).ok().unwrap();
+        let size = files[idx].size;
+        assert_eq!(file.get_size(), size);
+
+        let offsets = interesting_offsets(size);
+        for &start in &offsets {
+            for &end in offsets.iter().filter(|&&end| end >= start) {
+                let mut buf = vec![0xAAu8; end - start];
+                assert!(file.read_into(start as isize, &mut buf).is_ok(), "read {}..{}", start, end);
+                for (pos, &byte) in buf.iter().enumerate() {
+                    assert_eq!(byte, pattern(idx, start + pos), "read {}..{}, byte {}", start, end, start + pos);
+                }
+            }
+
+            /* Reads past the end of the file are refused */
+            let mut buf = vec![0u8; size - start + 1];
+            assert!(file.read_into(start as isize, &mut buf).is_err(), "read {}..{}", start, size + 1);
+        }
+    }
+
+    #[test]
+    fn read_contiguous() {
+        check_reads(&[&CONTIG, &FRAG, &TINY], 0);
+    }
+
+    #[test]
+    fn read_fragmented() {
+        check_reads(&[&CONTIG, &FRAG, &TINY], 1);
+    }
+
+    #[test]
+    fn read_single_cluster() {
+        check_reads(&[&CONTIG, &FRAG, &TINY], 2);
+    }
+
+    #[test]
+    fn cluster_runs_merge_contiguous_clusters() {
+        let fs = mount(&[&CONTIG, &FRAG, &TINY]);
+        let fs = fs.borrow();
+        let cluster = |num: isize| fs.data_offset + (num - 2) * SECTOR as isize;
+
+        let runs: Vec<(isize, usize)> = fs.cluster_runs(cluster(6), FRAG.size).map(|run| run.ok().unwrap()).collect();
+        assert_eq!(runs, [(cluster(6), 512), (cluster(9), 512), (cluster(7), 512), (cluster(10), 1024)]);
+
+        let runs: Vec<(isize, usize)> = fs.cluster_runs(cluster(2), CONTIG.size).map(|run| run.ok().unwrap()).collect();
+        assert_eq!(runs, [(cluster(2), 2048)]);
+    }
+
+    #[test]
+    fn cluster_runs_stop_at_limit() {
+        let fs = mount(&[&CONTIG, &FRAG, &TINY]);
+        let fs = fs.borrow();
+        let cluster = |num: isize| fs.data_offset + (num - 2) * SECTOR as isize;
+
+        /* Only as much of the chain as is needed is followed */
+        let runs: Vec<(isize, usize)> = fs.cluster_runs(cluster(2), 513).map(|run| run.ok().unwrap()).collect();
+        assert_eq!(runs, [(cluster(2), 1024)]);
+
+        let runs: Vec<(isize, usize)> = fs.cluster_runs(cluster(6), 1025).map(|run| run.ok().unwrap()).collect();
+        assert_eq!(runs, [(cluster(6), 512), (cluster(9), 512), (cluster(7), 512)]);
+    }
+
+    /// Decode a chain of LFN entries followed by a short entry
+    fn long_name(entries: &[[u8; 32]], short: &[u8; 11]) -> Option<String> {
+        let mut lfn = FATLongName::new();
+        for raw in entries {
+            let dirent: FATDataLFNDirent = unsafe { ptr::read(raw.as_ptr() as *const _) };
+            lfn.push(&dirent);
+        }
+
+        lfn.get(short).map(|name| String::from_utf16(name).unwrap())
+    }
+
+    #[test]
+    fn lfn_chain_decodes() {
+        for long in ["kernel-debug.elf", "a.b", "thirteen.char", "a name spanning three entries"] {
+            let entries = lfn_dirents(long, b"KERNEL~1ELF");
+            assert_eq!(long_name(&entries, b"KERNEL~1ELF").as_deref(), Some(long));
+        }
+    }
+
+    #[test]
+    fn lfn_rejects_checksum_mismatch() {
+        let entries = lfn_dirents("kernel-debug.elf", b"KERNEL~1ELF");
+
+        /* Chain left behind by a tool that renamed the short entry */
+        assert_eq!(long_name(&entries, b"KERNEL~2ELF"), None);
+
+        /* Entries from different chains */
+        let mut mixed = entries.clone();
+        mixed[1][13] ^= 0x01;
+        assert_eq!(long_name(&mixed, b"KERNEL~1ELF"), None);
+    }
+
+    #[test]
+    fn lfn_rejects_broken_chains() {
+        let entries = lfn_dirents("a name spanning three entries", b"ANAMES~1   ");
+        assert_eq!(entries.len(), 3);
+
+        let reversed: Vec<[u8; 32]> = entries.iter().rev().copied().collect();
+        assert_eq!(long_name(&reversed, b"ANAMES~1   "), None);
+
+        /* Orphans, missing the first stored entry or one in the middle */
+        assert_eq!(long_name(&entries[1..], b"ANAMES~1   "), None);
+        assert_eq!(long_name(&[entries[0], entries[2]], b"ANAMES~1   "), None);
+
+        /* An incomplete chain doesn't spoil the one that follows */
+        let mut restarted = entries[..2].to_vec();
+        restarted.extend(lfn_dirents("kernel-debug.elf", b"KERNEL~1ELF"));
+        assert_eq!(long_name(&restarted, b"KERNEL~1ELF").as_deref(), Some("kernel-debug.elf"));
+    }
+
+    #[test]
+    fn find_by_long_name() {
+        let fs = mount(&[&CONTIG, &KERNEL, &TINY]);
+        let fs = fs.borrow();
+
+        for path in ["kernel-debug.elf", "KERNEL-DEBUG.ELF", "KERNEL~1.ELF"] {
+            let file = fs.find_file(None, path).ok().unwrap();
+            assert_eq!(file.get_size(), KERNEL.size, "{}", path);
+
+            let mut buf = vec![0u8; KERNEL.size];
+            assert!(file.read_into(0, &mut buf).is_ok());
+            assert!(buf.iter().enumerate().all(|(pos, &byte)| byte == pattern(1, pos)), "{}", path);
+        }
+
+        assert!(fs.find_file(None, "kernel-debug").is_err());
+        assert!(fs.find_file(None, "TINY.TXT").is_ok());
+    }
+
+    #[test]
+    fn short_name_normalization() {
+        let short = FATFilesystem::fat_filename_from_str;
+
+        assert_eq!(short("kernel.elf"), Some(*b"KERNEL  ELF"));
+        assert_eq!(short("Boot.Cfg"), Some(*b"BOOT    CFG"));
+        assert_eq!(short("README"), Some(*b"README     "));
+        assert_eq!(short("."), Some(*b".          "));
+        assert_eq!(short(".."), Some(*b"..         "));
+
+        /* Trailing dots are dropped, but only one dot may remain */
+        assert_eq!(short("README."), Some(*b"README     "));
+        assert_eq!(short("FILE.TXT.."), Some(*b"FILE    TXT"));
+        assert_eq!(short("A.B.C"), None);
+
+        /* Too long for 8.3, or no base name */
+        assert_eq!(short("TOOLONGNAME"), None);
+        assert_eq!(short("FILE.TEXT"), None);
+        assert_eq!(short(".profile"), None);
+    }
+
+    #[test]
+    fn short_name_illegal_chars() {
+        let short = FATFilesystem::fat_filename_from_str;
+
+        for name in ["A B", "A+B", "A,B", "A*", "A?", "A:B", "A;B", "A=B", "A[B]", "A|B", "A\\B", "\"A\"", "<A>"] {
+            assert_eq!(short(name), None, "{}", name);
+        }
+        assert_eq!(short("A-B_C~1.$$$"), Some(*b"A-B_C~1 $$$"));
+
+        /* Only ASCII can be matched against the OEM codepage */
+        assert_eq!(short("A\u{7F}"), None);
+        assert_eq!(short("caf\u{E9}"), None);
+        for ch in 0x7F..=0xFF {
+            assert_eq!(FATFilesystem::fat_filename_char(ch), None, "{:#x}", ch);
+        }
+    }
+
+    #[test]
+    fn short_name_compare() {
+        assert!(FATFilesystem::fat_filename_eq(b"KERNEL  ELF", "kernel.elf"));
+        assert!(FATFilesystem::fat_filename_eq(b"KERNEL  ELF", "KERNEL.ELF."));
+        assert!(!FATFilesystem::fat_filename_eq(b"KERNEL  ELF", "kernel.el"));
+        assert!(!FATFilesystem::fat_filename_eq(b"KERNEL  ELF", "kernel"));
+        assert!(!FATFilesystem::fat_filename_eq(b"KERNEL  ELF", "kern el.elf"));
+
+        /* 0x05 stands for a leading 0xE5, which no UTF-8 path can name */
+        assert!(!FATFilesystem::fat_filename_eq(b"\x05ABC    TXT", "\u{E5}ABC.TXT"));
+        assert!(!FATFilesystem::fat_filename_eq(b"\x05ABC    TXT", "\x05ABC.TXT"));
+    }
+
+    #[test]
+    fn short_name_display() {
+        let mut raw = [0u8; 32];
+        raw[..11].copy_from_slice(b"\x05ABC    TXT");
+        let dirent: FATDataDirent = unsafe { ptr::read(raw.as_ptr() as *const _) };
+        assert_eq!(FATFilesystem::fat_filename_to_string(&dirent), "\u{E5}ABC.TXT");
+
+        raw[..11].copy_from_slice(b"README     ");
+        raw[12] = FAT_NT_LOWER_BASE;
+        let dirent: FATDataDirent = unsafe { ptr::read(raw.as_ptr() as *const _) };
+        assert_eq!(FATFilesystem::fat_filename_to_string(&dirent), "readme");
+    }
+}
@@ -0,0 +1,430 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cell::RefCell;
+use core::mem;
+use core::fmt::Write;
+use core::ptr;
+
+use crate::errors::ErrorCode;
+use crate::storage::{block::{self, BlockDevice}, fs::FileAttribute};
+use crate::io::output;
+use super::{File, Filesystem};
+
+
+/// Source of filenames used when searching directories
+#[derive(Clone, Copy, PartialEq)]
+enum Iso9660Names {
+    /// Plain ISO9660 8.3-style names with version suffix
+    Iso,
+    /// UCS-2 names from the Joliet supplementary volume descriptor
+    Joliet,
+    /// POSIX names from Rock Ridge NM entries
+    RockRidge,
+}
+
+pub struct Iso9660Filesystem {
+    offset: isize, //< Offset of filesystem into block device
+
+    block_size: usize,   //< Logical block size
+    names: Iso9660Names, //< Which names to match against
+    susp_skip: usize,    //< Bytes to skip at start of each System Use area
+
+    rootdir: Iso9660File, //< Iso9660File representing root directory
+
+    block: Rc<RefCell<dyn BlockDevice>>, //< Underlying block device
+
+    rc: Weak<RefCell<Self>>,
+}
+
+impl Filesystem for Iso9660Filesystem {
+    fn get_root(&self) -> &dyn super::File {
+        &self.rootdir
+    }
+
+    fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        let mut dir: Iso9660File = match start_dir {
+            Some(start) if !path.starts_with('/') => {
+                match start.as_any().downcast_ref::<Iso9660File>() {
+                    Some(file) => file.clone(),
+                    None       => return Err(ErrorCode::FileNotFound)
+                }
+            },
+            _ => self.rootdir.clone()
+        };
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if (dir.attr & FileAttribute::Directory as u32) == 0 {
+                return Err(ErrorCode::FileNotFound);
+            }
+
+            dir = self.dir_lookup(&dir, name)?;
+        }
+
+        Ok(Box::new(dir))
+    }
+}
+
+impl Iso9660Filesystem {
+    /// Check whether the block device contains an ISO9660 filesystem at the
+    /// given offset.
+    pub fn probe(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> bool {
+        match blockdev.borrow().read(offset + ISO9660_VD_OFFSET, ISO9660_SECTOR_SIZE) {
+            Ok(data) => &data[1..6] == ISO9660_VD_ID,
+            Err(_)   => false
+        }
+    }
+
+    pub fn init(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> Result<Rc<RefCell<Self>>, ErrorCode> {
+        let mut primary: Option<Iso9660DataVolDesc> = None;
+        let mut joliet: Option<Iso9660DataVolDesc> = None;
+
+        for idx in 0..ISO9660_VD_MAX {
+            let data = blockdev.borrow().read(offset + ISO9660_VD_OFFSET + (idx * ISO9660_SECTOR_SIZE) as isize,
+                                              ISO9660_SECTOR_SIZE)?;
+            let vd: Iso9660DataVolDesc = unsafe { ptr::read(data.as_ptr() as *const _) };
+
+            if vd.id != *ISO9660_VD_ID {
+                return Err(ErrorCode::Unsupported);
+            }
+
+            match vd.vd_type {
+                ISO9660_VD_TYPE_PRIMARY => {
+                    if primary.is_none() {
+                        primary = Some(vd);
+                    }
+                },
+                ISO9660_VD_TYPE_SUPPLEMENTARY => {
+                    if Self::is_joliet(&vd) && joliet.is_none() {
+                        joliet = Some(vd);
+                    }
+                },
+                ISO9660_VD_TYPE_TERMINATOR => break,
+                _ => {}
+            }
+        }
+
+        let primary = match primary {
+            Some(vd) => vd,
+            None     => return Err(ErrorCode::Unsupported)
+        };
+
+        let block_size = primary.block_size_le as usize;
+        if (block_size < 512) || !block_size.is_power_of_two() {
+            return Err(ErrorCode::Unsupported);
+        }
+
+        let root: Iso9660DataDirRecord = unsafe { ptr::read(primary.root_record.as_ptr() as *const _) };
+
+        let fs = Rc::new_cyclic(|me| {
+            RefCell::new(Iso9660Filesystem {
+                offset,
+                block_size,
+                names: Iso9660Names::Iso,
+                susp_skip: 0,
+                rootdir: Iso9660File {
+                    extent: root.extent_le,
+                    size: root.size_le as usize,
+                    attr: FileAttribute::Directory as u32,
+                    fs: me.clone()
+                },
+
+                block: Rc::clone(blockdev),
+                rc: me.clone(),
+            })
+        });
+
+        /* Prefer Rock Ridge, as it can represent the original names exactly */
+        let susp_skip = fs.borrow().find_susp()?;
+        if let Some(skip) = susp_skip {
+            let mut fs = fs.borrow_mut();
+            fs.names     = Iso9660Names::RockRidge;
+            fs.susp_skip = skip;
+        } else if let Some(vd) = joliet {
+            let root: Iso9660DataDirRecord = unsafe { ptr::read(vd.root_record.as_ptr() as *const _) };
+            let mut fs = fs.borrow_mut();
+            fs.names = Iso9660Names::Joliet;
+            fs.rootdir.extent = root.extent_le;
+            fs.rootdir.size   = root.size_le as usize;
+        }
+
+        Ok(fs)
+    }
+
+    fn is_joliet(vd: &Iso9660DataVolDesc) -> bool {
+        (vd.escape[0] == 0x25) && (vd.escape[1] == 0x2F) &&
+        matches!(vd.escape[2], 0x40 | 0x43 | 0x45)
+    }
+
+    /// Look for the SUSP indicator in the root directory's "." entry, returning
+    /// the number of bytes to skip in each System Use area if present.
+    fn find_susp(&self) -> Result<Option<usize>, ErrorCode> {
+        let data = self.read_bytes(self.block_offset(self.rootdir.extent), self.block_size)?;
+        let rec: Iso9660DataDirRecord = unsafe { ptr::read(data.as_ptr() as *const _) };
+
+        let su = Self::system_use(&data[..rec.length as usize], &rec);
+        if (su.len() >= 7) && (&su[0..2] == b"SP") && (su[4] == 0xBE) && (su[5] == 0xEF) {
+            Ok(Some(su[6] as usize))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn block_offset(&self, block: u32) -> isize {
+        (block as usize * self.block_size) as isize
+    }
+
+    /// Read a range of the filesystem that may not be sector-aligned
+    fn read_bytes(&self, off: isize, size: usize) -> Result<Vec<u8>, ErrorCode> {
+        let mut data = vec![0; size];
+        self.read_bytes_into(off, &mut data)?;
+        Ok(data)
+    }
+
+    fn read_bytes_into(&self, off: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        block::read_unaligned(&*self.block.borrow(), self.offset + off, buf)
+    }
+
+    /// Get the System Use area of a directory record
+    fn system_use<'a>(record: &'a [u8], rec: &Iso9660DataDirRecord) -> &'a [u8] {
+        /* Name is padded so the System Use area starts on an even offset */
+        let start = (mem::size_of::<Iso9660DataDirRecord>() + rec.name_len as usize).next_multiple_of(2);
+
+        if start >= record.len() {
+            &[]
+        } else {
+            &record[start..]
+        }
+    }
+
+    /// Decode the name of a directory record according to the naming scheme
+    /// in use.
+    fn record_name(&self, record: &[u8], rec: &Iso9660DataDirRecord) -> String {
+        let name_start = mem::size_of::<Iso9660DataDirRecord>();
+        let raw = &record[name_start..(name_start + rec.name_len as usize)];
+
+        /* Special entries for the current and parent directory */
+        if raw == [0x00] {
+            return String::from(".");
+        } else if raw == [0x01] {
+            return String::from("..");
+        }
+
+        if self.names == Iso9660Names::RockRidge {
+            if let Some(name) = self.rock_ridge_name(record, rec) {
+                return name;
+            }
+        }
+
+        let mut name = if self.names == Iso9660Names::Joliet {
+            let chars = raw.chunks_exact(2).map(|ch| u16::from_be_bytes([ch[0], ch[1]]));
+            char::decode_utf16(chars).map(|ch| ch.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+        } else {
+            raw.iter().map(|&ch| ch as char).collect::<String>()
+        };
+
+        /* Strip version number, and the trailing dot of extension-less names */
+        if let Some(idx) = name.rfind(';') {
+            name.truncate(idx);
+        }
+        if name.ends_with('.') {
+            name.pop();
+        }
+
+        name
+    }
+
+    /// Get the Rock Ridge alternate name of a directory record, if present.
+    ///
+    /// NOTE: Continuation areas (CE entries) are not followed.
+    fn rock_ridge_name(&self, record: &[u8], rec: &Iso9660DataDirRecord) -> Option<String> {
+        let su = Self::system_use(record, rec);
+        let mut su = su.get(self.susp_skip..)?;
+
+        let mut name: Option<Vec<u8>> = None;
+
+        while su.len() >= 4 {
+            let len = su[2] as usize;
+            if (len < 4) || (len > su.len()) {
+                break;
+            }
+
+            if &su[0..2] == b"NM" && (len >= 5) {
+                let flags = su[4];
+                if (flags & (RRIP_NM_CURRENT | RRIP_NM_PARENT)) == 0 {
+                    name.get_or_insert_with(Vec::new).extend_from_slice(&su[5..len]);
+                }
+                if (flags & RRIP_NM_CONTINUE) == 0 {
+                    break;
+                }
+            } else if &su[0..2] == b"ST" {
+                break;
+            }
+
+            su = &su[len..];
+        }
+
+        name.and_then(|name| String::from_utf8(name).ok())
+    }
+
+    /// Find an entry within a directory
+    fn dir_lookup(&self, dir: &Iso9660File, name: &str) -> Result<Iso9660File, ErrorCode> {
+        let mut pos = 0;
+
+        while pos < dir.size {
+            let len = self.block_size.min(dir.size - pos);
+            let buf = self.read_bytes(self.block_offset(dir.extent) + pos as isize, len)?;
+
+            /* Records never cross a logical block boundary */
+            let mut off = 0;
+            while (off + mem::size_of::<Iso9660DataDirRecord>()) <= buf.len() {
+                let rec: Iso9660DataDirRecord = unsafe { ptr::read(buf.as_ptr().add(off) as *const _) };
+                let rec_len = rec.length as usize;
+                if rec_len == 0 {
+                    /* Remainder of block is padding */
+                    break;
+                }
+                if ((off + rec_len) > buf.len()) ||
+                   (rec_len < (mem::size_of::<Iso9660DataDirRecord>() + rec.name_len as usize)) {
+                    println!("iso9660: Corrupt directory record");
+                    return Err(ErrorCode::ReadFailure);
+                }
+
+                let record = &buf[off..(off + rec_len)];
+                /* Ignore case even for Rock Ridge names, as the other
+                 * filesystems do, so one config works on all of them */
+                if self.record_name(record, &rec).eq_ignore_ascii_case(name) {
+                    return Ok(Iso9660File {
+                        extent: rec.extent_le,
+                        size: rec.size_le as usize,
+                        attr: if (rec.flags & ISO9660_FLAG_DIRECTORY) != 0 {
+                            FileAttribute::Directory as u32
+                        } else {
+                            FileAttribute::File as u32
+                        },
+                        fs: self.rc.clone()
+                    });
+                }
+
+                off += rec_len;
+            }
+
+            pos += self.block_size;
+        }
+
+        Err(ErrorCode::FileNotFound)
+    }
+}
+
+#[derive(Clone)]
+pub struct Iso9660File {
+    extent: u32, //< First logical block of file data
+
+    size: usize, //< File size
+
+    attr: u32,   //< File attributes
+
+    fs: Weak<RefCell<Iso9660Filesystem>>,
+}
+
+impl File for Iso9660File {
+    fn get_size(&self) -> usize {
+        self.size
+    }
+
+    fn get_attr(&self) -> u32 {
+        self.attr
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        if (offset as usize + buf.len()) > self.size {
+            println!("Iso9660File: Attempt to read past end of file");
+            return Err(ErrorCode::OutOfBounds);
+        }
+
+        let fs = self.fs.upgrade().expect("Could not upgrade Iso9660File::fs");
+        let fs = fs.borrow();
+
+        /* File data is a single contiguous extent */
+        fs.read_bytes_into(fs.block_offset(self.extent) + offset, buf)
+    }
+
+    fn close(&self) {
+
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+
+/// Size of a CD sector, volume descriptors are always this size
+const ISO9660_SECTOR_SIZE: usize = 2048;
+/// Offset of first volume descriptor, following the 16-sector system area
+const ISO9660_VD_OFFSET: isize = 16 * ISO9660_SECTOR_SIZE as isize;
+/// Maximum number of volume descriptors to look through
+const ISO9660_VD_MAX: usize = 16;
+const ISO9660_VD_ID: &[u8; 5] = b"CD001";
+
+const ISO9660_VD_TYPE_PRIMARY: u8       = 1;
+const ISO9660_VD_TYPE_SUPPLEMENTARY: u8 = 2;
+const ISO9660_VD_TYPE_TERMINATOR: u8    = 255;
+
+const ISO9660_FLAG_DIRECTORY: u8 = 1 << 1; //< Record describes a directory
+
+const RRIP_NM_CONTINUE: u8 = 1 << 0; //< Name continues in next NM entry
+const RRIP_NM_CURRENT: u8  = 1 << 1; //< Name refers to the current directory
+const RRIP_NM_PARENT: u8   = 1 << 2; //< Name refers to the parent directory
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+#[repr(C, packed(1))]
+struct Iso9660DataVolDesc {
+    vd_type: u8,             //< Volume descriptor type
+    id: [u8; 5],             //< Standard identifier, `ISO9660_VD_ID`
+    version: u8,             //< Volume descriptor version
+    flags: u8,               //< Volume flags (supplementary only)
+    system_id: [u8; 32],     //< System identifier
+    volume_id: [u8; 32],     //< Volume identifier
+    _unused0: [u8; 8],
+    space_size_le: u32,      //< Number of logical blocks in volume
+    space_size_be: u32,
+    escape: [u8; 32],        //< Escape sequences (supplementary only), identifies Joliet
+    set_size_le: u16,        //< Number of volumes in set
+    set_size_be: u16,
+    seq_number_le: u16,      //< Number of this volume in set
+    seq_number_be: u16,
+    block_size_le: u16,      //< Logical block size
+    block_size_be: u16,
+    path_table_size_le: u32, //< Size of path table
+    path_table_size_be: u32,
+    path_tables: [u32; 4],   //< Locations of path tables
+    root_record: [u8; 34],   //< Directory record of root directory
+    _reserved: [u8; 1858],
+}
+const _ISO9660_VD_SZ_TEST: [u8; ISO9660_SECTOR_SIZE] = [0; mem::size_of::<Iso9660DataVolDesc>()];
+
+#[allow(dead_code)]
+#[repr(C, packed(1))]
+struct Iso9660DataDirRecord {
+    length: u8,          //< Length of this record
+    ext_attr_length: u8, //< Length of extended attribute record
+    extent_le: u32,      //< First logical block of extent
+    extent_be: u32,
+    size_le: u32,        //< Size of extent in bytes
+    size_be: u32,
+    date: [u8; 7],       //< Recording date and time
+    flags: u8,           //< File flags
+    unit_size: u8,       //< File unit size for interleaved files
+    gap_size: u8,        //< Interleave gap size
+    vol_seq_le: u16,     //< Volume sequence number
+    vol_seq_be: u16,
+    name_len: u8,        //< Length of name that directly follows this structure
+}
+const _ISO9660_DIR_RECORD_SZ_TEST: [u8; 33] = [0; mem::size_of::<Iso9660DataDirRecord>()];
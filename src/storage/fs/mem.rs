@@ -0,0 +1,59 @@
+use core::any::Any;
+
+use crate::errors::ErrorCode;
+use crate::storage::fs::{File, FileAttribute};
+
+/// File whose contents are held in memory, such as one received over a serial
+/// line rather than read from a filesystem
+#[derive(Clone)]
+pub struct MemFile {
+    data: &'static [u8], //< File contents, kept for as long as we run
+    attr: u32,
+}
+
+impl MemFile {
+    pub fn new(data: &'static [u8]) -> MemFile {
+        MemFile {
+            data,
+            attr: FileAttribute::File as u32 | FileAttribute::ReadOnly as u32,
+        }
+    }
+
+    /// Create an empty directory, to serve as the root of a filesystem that
+    /// has no real directories
+    pub fn empty_dir() -> MemFile {
+        MemFile {
+            data: &[],
+            attr: FileAttribute::Directory as u32,
+        }
+    }
+}
+
+impl File for MemFile {
+    fn get_size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn get_attr(&self) -> u32 {
+        self.attr
+    }
+
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode> {
+        let start = usize::try_from(offset).map_err(|_| ErrorCode::OutOfBounds)?;
+        match self.data.get(start..).and_then(|data| data.get(..buf.len())) {
+            Some(data) => {
+                buf.copy_from_slice(data);
+                Ok(())
+            },
+            None => Err(ErrorCode::OutOfBounds)
+        }
+    }
+
+    fn close(&self) {
+
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,12 +1,20 @@
 extern crate alloc;
 
+pub mod ext2;
 pub mod fat;
+pub mod iso9660;
+pub mod mem;
 
 use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::any::Any;
+use core::cell::RefCell;
 
 use crate::errors::ErrorCode;
+use crate::storage::block::BlockDevice;
 
 #[allow(dead_code)]
 pub trait File {
@@ -14,7 +22,18 @@ pub trait File {
 
     fn get_attr(&self) -> u32;
 
-    fn read(&self, offset: isize, size: usize) -> Result<Vec<u8>, ErrorCode>;
+    fn read(&self, offset: isize, size: usize) -> Result<Vec<u8>, ErrorCode> {
+        let mut data = vec![0; size];
+        self.read_into(offset, &mut data)?;
+        Ok(data)
+    }
+
+    /// Read file data directly into a caller-provided buffer
+    ///
+    /// # Arguments
+    /// * offset: Offset into file at which to start reading
+    /// * buf: Buffer to read into, its length determines how many bytes to read
+    fn read_into(&self, offset: isize, buf: &mut [u8]) -> Result<(), ErrorCode>;
 
     fn close(&self);
 
@@ -32,11 +51,78 @@ pub trait Filesystem {
     /// * dir: Directory to search from
     /// * path: Path to file
     fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode>;
+
+    /// List the entries of a directory
+    ///
+    /// # Arguments
+    /// * dir: Directory to list, must belong to this FS
+    fn read_dir<'a>(&'a self, dir: &dyn File) -> Result<DirIter<'a>, ErrorCode> {
+        let _ = dir;
+        Err(ErrorCode::Unsupported)
+    }
+}
+
+/// Iterator over the entries of a directory
+pub type DirIter<'a> = Box<dyn Iterator<Item = Result<DirEntry, ErrorCode>> + 'a>;
+
+/// Directory entry, as returned by `Filesystem::read_dir`
+#[allow(dead_code)]
+pub struct DirEntry {
+    pub name: String,               //< Name of entry, without path
+    pub size: usize,                //< Size of file in bytes
+    pub attr: u32,                  //< File attributes, see `FileAttribute`
+    pub created: Option<FileTime>,  //< Creation time, if recorded by the FS
+    pub modified: Option<FileTime>, //< Last modification time, if recorded by the FS
+}
+
+/// Date and time as recorded by a filesystem
+#[derive(Clone, Copy, Default)]
+pub struct FileTime {
+    pub year: u16,
+    pub month: u8,  //< 1-12
+    pub day: u8,    //< 1-31
+    pub hour: u8,   //< 0-23
+    pub minute: u8, //< 0-59
+    pub second: u8, //< 0-59
+}
+
+impl core::fmt::Display for FileTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+               self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
 }
 
 #[repr(u32)]
-enum FileAttribute {
+pub enum FileAttribute {
     File      = (1 << 0),
-    Directory = (1 << 1)
+    Directory = (1 << 1),
+    ReadOnly  = (1 << 2),
+    Hidden    = (1 << 3),
+    System    = (1 << 4),
 }
 
+
+/// Probe a block device for each compiled-in filesystem driver, and initialize
+/// whichever one claims it.
+///
+/// # Arguments
+/// * blockdev: Block device to probe
+/// * offset: Offset of filesystem into block device
+pub fn probe(blockdev: &Rc<RefCell<dyn BlockDevice>>, offset: isize) -> Result<Rc<RefCell<dyn Filesystem>>, ErrorCode> {
+    /* Probes are ordered from most to least specific signature, FAT has no
+     * magic number of its own so it must come last. */
+    if ext2::Ext2Filesystem::probe(blockdev, offset) {
+        return Ok(ext2::Ext2Filesystem::init(blockdev, offset)?);
+    }
+
+    if iso9660::Iso9660Filesystem::probe(blockdev, offset) {
+        return Ok(iso9660::Iso9660Filesystem::init(blockdev, offset)?);
+    }
+
+    if fat::FATFilesystem::probe(blockdev, offset) {
+        return Ok(fat::FATFilesystem::init(blockdev, offset)?);
+    }
+
+    Err(ErrorCode::UnsupportedFilesystem)
+}
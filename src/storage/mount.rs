@@ -0,0 +1,91 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use crate::errors::ErrorCode;
+use crate::storage::fs::{File, Filesystem};
+
+struct Mount {
+    name: String,                   //< Device/partition name, e.g. `fd0` or `hd0p1`
+    fs: Rc<RefCell<dyn Filesystem>>, //< Mounted filesystem
+}
+
+/// Table of mounted filesystems, keyed by device/partition name.
+///
+/// Paths may be prefixed by a device name, as in `hd0p1:/boot/kernel`. Paths
+/// without a prefix resolve against the default (first) mount, which is
+/// normally the boot device.
+pub struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+impl MountTable {
+    pub fn new() -> Self {
+        MountTable {
+            mounts: Vec::new(),
+        }
+    }
+
+    /// Add a filesystem to the mount table
+    ///
+    /// # Arguments
+    /// * name: Device/partition name to mount filesystem as
+    /// * fs: Filesystem to mount
+    pub fn mount(&mut self, name: &str, fs: Rc<RefCell<dyn Filesystem>>) -> Result<(), ErrorCode> {
+        if name.is_empty() || name.contains(['/', ':']) {
+            return Err(ErrorCode::OutOfBounds);
+        }
+        if self.get(name).is_some() {
+            return Err(ErrorCode::AlreadyExists);
+        }
+
+        self.mounts.push(Mount {
+            name: name.to_string(),
+            fs
+        });
+
+        Ok(())
+    }
+
+    /// Remove a filesystem from the mount table
+    #[allow(dead_code)]
+    pub fn unmount(&mut self, name: &str) -> Result<(), ErrorCode> {
+        match self.mounts.iter().position(|mnt| mnt.name == name) {
+            Some(idx) => {
+                self.mounts.remove(idx);
+                Ok(())
+            },
+            None => Err(ErrorCode::FileNotFound)
+        }
+    }
+
+    /// Get filesystem mounted under the given name
+    pub fn get(&self, name: &str) -> Option<Rc<RefCell<dyn Filesystem>>> {
+        self.mounts.iter().find(|mnt| mnt.name == name).map(|mnt| Rc::clone(&mnt.fs))
+    }
+
+    /// Find a file, optionally on a specific device
+    ///
+    /// # Arguments
+    /// * path: Path to file, in the form `[<device>:]<path>`
+    pub fn find_file(&self, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        match self.resolve(path) {
+            Some((mnt, path)) => mnt.fs.borrow().find_file(None, path),
+            None              => Err(ErrorCode::FileNotFound)
+        }
+    }
+
+    /// Split a path into the mount it refers to and the path within it
+    fn resolve<'a>(&self, path: &'a str) -> Option<(&Mount, &'a str)> {
+        match path.split_once(':') {
+            Some((name, rest)) if !name.contains('/') => {
+                self.mounts.iter().find(|mnt| mnt.name == name).map(|mnt| (mnt, rest))
+            },
+            _ => self.mounts.first().map(|mnt| (mnt, path))
+        }
+    }
+}
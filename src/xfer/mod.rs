@@ -0,0 +1,221 @@
+extern crate alloc;
+
+pub mod xmodem;
+pub mod ymodem;
+pub mod zmodem;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use crate::config::{Config, SerialPortConfig};
+use crate::errors::ErrorCode;
+use crate::io::output;
+use crate::io::serial::{self, SerialPort, SerialPortBase};
+use crate::storage::fs::mem::MemFile;
+use crate::storage::fs::{File, Filesystem};
+
+/// Protocol used to receive files over a serial line
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum XferProtocol {
+    Xmodem,
+    Ymodem,
+    Zmodem,
+}
+
+impl XferProtocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            XferProtocol::Xmodem => "xmodem",
+            XferProtocol::Ymodem => "ymodem",
+            XferProtocol::Zmodem => "zmodem",
+        }
+    }
+}
+
+/// Files received over a serial port when they are opened, for loading
+/// kernels onto machines with nothing but a serial console.
+///
+/// The path names the port to receive on, as in `xmodem://COM1`. The whole
+/// file is received into extended memory before it is returned.
+///
+/// Batch protocols deliver several files in one session, so their paths also
+/// name the file, as in `ymodem://COM1/initrd`. Opening a file not already
+/// received waits for a new batch, and every file in it is kept so that the
+/// kernel and its modules can be sent together.
+pub struct SerialXferFilesystem {
+    protocol: XferProtocol,
+    rootdir: MemFile,
+    ports: Rc<RefCell<XferPorts>>,
+}
+
+/// Serial ports opened for transfers, shared by the filesystem of each
+/// protocol
+#[derive(Default)]
+pub struct XferPorts {
+    ports: Vec<XferPort>,
+    settings: Vec<SerialPortConfig>, //< Line settings from the config file
+}
+
+struct XferPort {
+    base: SerialPortBase,
+    port: Rc<RefCell<SerialPort>>,    //< Port, which may also be the console's
+    received: Vec<(String, MemFile)>, //< Files received in batches, by name
+}
+
+/// File received in a batch
+pub struct ReceivedFile {
+    pub name: String,         //< File name, without any directory the sender gave
+    pub data: &'static [u8], //< File contents, staged in extended memory
+}
+
+impl Filesystem for SerialXferFilesystem {
+    fn get_root(&self) -> &dyn File {
+        &self.rootdir
+    }
+
+    fn find_file(&self, start_dir: Option<&dyn File>, path: &str) -> Result<Box<dyn File>, ErrorCode> {
+        /* Only port names, there are no directories */
+        let _ = start_dir;
+
+        let path = path.trim_matches('/');
+        let (port_name, file_name) = path.split_once('/').unwrap_or((path, ""));
+        let base = SerialPortBase::from_name(port_name).ok_or(ErrorCode::FileNotFound)?;
+
+        let xports = &mut *self.ports.borrow_mut();
+        let settings = xports.settings.iter().find(|sp| sp.port == base).copied()
+            .unwrap_or(SerialPortConfig::new(base));
+        let ports = &mut xports.ports;
+        let idx = match ports.iter().position(|port| port.base == base) {
+            Some(idx) => idx,
+            None      => {
+                let port = match serial::open_port(base, &settings.serial_config(XFER_RXFIFO_SZ, XFER_TXFIFO_SZ)) {
+                    Ok(port) => port,
+                    Err(e)   => {
+                        println!("Could not open {}: {}", port_name, e);
+                        return Err(e);
+                    }
+                };
+                ports.push(XferPort {
+                    base,
+                    port,
+                    received: Vec::new(),
+                });
+                ports.len() - 1
+            }
+        };
+        let xport = &mut ports[idx];
+
+        if let Some((_, file)) = xport.received.iter().find(|(name, _)| name.eq_ignore_ascii_case(file_name)) {
+            return Ok(Box::new(file.clone()));
+        }
+
+        println!("Waiting for {} transfer on {}", self.protocol.name().to_uppercase(), settings);
+
+        /* Holding the port keeps console output off it until we're done */
+        let port = Rc::clone(&xport.port);
+        let mut port = port.borrow_mut();
+        let before = port.stats();
+        let result = self.receive(&mut port, xport, file_name);
+
+        /* Worth knowing when chasing a flaky cable */
+        let stats = port.stats();
+        drop(port);
+        if stats.errors() != before.errors() || stats.breaks != before.breaks {
+            println!("Line errors on {}: {}", port_name, stats);
+        }
+
+        result
+    }
+}
+
+impl SerialXferFilesystem {
+    pub fn new(protocol: XferProtocol, ports: Rc<RefCell<XferPorts>>) -> SerialXferFilesystem {
+        SerialXferFilesystem {
+            protocol,
+            rootdir: MemFile::empty_dir(),
+            ports,
+        }
+    }
+
+    /// Receive files with the filesystem's protocol, and return the one wanted
+    ///
+    /// # Arguments
+    /// * port - Port of `xport`, borrowed for the transfer
+    /// * xport - Port to keep received files in
+    /// * file_name - File wanted from a batch, the first if empty
+    fn receive(&self, port: &mut SerialPort, xport: &mut XferPort, file_name: &str) -> Result<Box<dyn File>, ErrorCode> {
+        match self.protocol {
+            XferProtocol::Xmodem => {
+                let data = xmodem::receive(port)?;
+                println!("Received {} bytes", data.len());
+
+                Ok(Box::new(MemFile::new(data)))
+            },
+            XferProtocol::Ymodem => {
+                let batch = ymodem::receive_batch(port)?;
+                xport.store_batch(batch, file_name)
+            },
+            XferProtocol::Zmodem => {
+                /* A session that fails part way through a file is followed
+                 * by another, so the sender can resume it. The partial file
+                 * holds all free extended memory, so it is dropped once the
+                 * sessions are used up. */
+                let mut partial = None;
+                let mut sessions = 1;
+                let result = loop {
+                    match zmodem::receive_batch(port, &mut partial) {
+                        Err(_) if partial.is_some() && sessions < XFER_ZMODEM_SESSIONS => {
+                            sessions += 1;
+                            println!("Send again to resume the transfer");
+                        },
+                        result => break result,
+                    }
+                };
+                zmodem::discard_partial(&mut partial);
+
+                xport.store_batch(result?, file_name)
+            },
+        }
+    }
+}
+
+impl XferPorts {
+    /// Take the line settings of ports from the config. Ports already opened
+    /// keep the settings they were opened with.
+    pub fn configure(&mut self, config: &Config) {
+        self.settings = config.serial.clone();
+    }
+}
+
+impl XferPort {
+    /// Keep the files of a batch, and return the one wanted
+    fn store_batch(&mut self, batch: Vec<ReceivedFile>, file_name: &str) -> Result<Box<dyn File>, ErrorCode> {
+        println!("Received {} files", batch.len());
+
+        /* Without a file name, the first file of the batch is wanted */
+        let wanted = match batch.first() {
+            Some(first) if file_name.is_empty() => first.name.clone(),
+            _                                   => file_name.into(),
+        };
+        for file in batch {
+            self.received.retain(|(name, _)| !name.eq_ignore_ascii_case(&file.name));
+            self.received.push((file.name, MemFile::new(file.data)));
+        }
+
+        match self.received.iter().find(|(name, _)| name.eq_ignore_ascii_case(&wanted)) {
+            Some((_, file)) => Ok(Box::new(file.clone())),
+            None            => {
+                println!("{} was not in the batch", file_name);
+                Err(ErrorCode::FileNotFound)
+            }
+        }
+    }
+}
+
+const XFER_ZMODEM_SESSIONS: usize = 3;
+pub const XFER_RXFIFO_SZ: usize = 4096; //< Several 1 KiB blocks, so nothing is lost while we're busy
+const XFER_TXFIFO_SZ: usize = 64;
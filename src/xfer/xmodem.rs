@@ -0,0 +1,267 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::errors::ErrorCode;
+use crate::highmem::StagingBuffer;
+use crate::intr::pit::Deadline;
+use crate::io::output;
+use crate::io::serial::SerialPort;
+
+/// Receive a file with XMODEM.
+///
+/// CRC-16 is requested first, falling back to the original arithmetic
+/// checksum if the sender doesn't respond. Both 128 byte and 1 KiB (XMODEM-1K)
+/// blocks are accepted. XMODEM has no notion of file size, so the data
+/// includes whatever padding the sender added to fill the final block.
+pub fn receive(port: &mut SerialPort) -> Result<&'static [u8], ErrorCode> {
+    let mut rx = XmodemReceiver::new(port);
+    let mut data = StagingBuffer::new()?;
+
+    let first = rx.start(true)?;
+    rx.receive_file(first, &mut data)?;
+    rx.port.write_u8(ACK);
+
+    Ok(data.finish())
+}
+
+/// Packet received from an XMODEM or YMODEM sender
+pub enum Packet {
+    Data(u8, Vec<u8>), //< Block number and data
+    Eot,               //< End of file
+    Cancelled,         //< Sender aborted the transfer
+}
+
+/// Receiving end of the XMODEM packet layer, which YMODEM builds on
+pub struct XmodemReceiver<'a> {
+    pub port: &'a mut SerialPort,
+    pub crc: bool, //< Packets end in a CRC-16, rather than a checksum
+}
+
+impl<'a> XmodemReceiver<'a> {
+    pub fn new(port: &'a mut SerialPort) -> XmodemReceiver<'a> {
+        XmodemReceiver {
+            port,
+            crc: true,
+        }
+    }
+
+    /// Ask the sender to start, and wait for the first packet
+    ///
+    /// # Arguments
+    /// * allow_checksum - Fall back to checksum mode if the sender ignores
+    ///   requests for CRC mode
+    pub fn start(&mut self, allow_checksum: bool) -> Result<Packet, ErrorCode> {
+        for attempt in 0..XMODEM_START_RETRIES {
+            self.crc = !allow_checksum || attempt < XMODEM_CRC_ATTEMPTS;
+            self.port.write_u8(if self.crc { CRC_START } else { NAK });
+
+            match self.read_packet(XMODEM_START_TIMEOUT_MS) {
+                Ok(packet) => return Ok(packet),
+                Err(_)     => self.purge(),
+            }
+        }
+
+        self.cancel();
+        println!("XMODEM: No response from sender");
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Receive the data blocks of a file, starting with the packet returned
+    /// by `start`. The EOT ending the file is left for the caller to
+    /// acknowledge.
+    ///
+    /// # Arguments
+    /// * first - First packet of the file
+    /// * data - Buffer to append the file's data to
+    pub fn receive_file(&mut self, first: Packet, data: &mut StagingBuffer) -> Result<(), ErrorCode> {
+        let mut expect: u8 = 1;
+        let mut reported = 0;
+        let mut packet = first;
+
+        loop {
+            match packet {
+                Packet::Data(block, payload) => {
+                    if block == expect {
+                        if data.extend_from_slice(&payload).is_err() {
+                            self.cancel();
+                            println!("XMODEM: Out of memory after {} bytes", data.len());
+                            return Err(ErrorCode::NoSpace);
+                        }
+                        expect = expect.wrapping_add(1);
+
+                        if data.len() / XMODEM_PROGRESS_STEP > reported {
+                            reported = data.len() / XMODEM_PROGRESS_STEP;
+                            println!("  {} KiB received", data.len() / 1024);
+                        }
+                    } else if block != expect.wrapping_sub(1) {
+                        /* Not a retransmission of the last block, we've lost sync */
+                        self.cancel();
+                        println!("XMODEM: Expected block {}, got {}", expect, block);
+                        return Err(ErrorCode::ReadFailure);
+                    }
+
+                    self.port.write_u8(ACK);
+                },
+                Packet::Eot       => return Ok(()),
+                Packet::Cancelled => {
+                    println!("XMODEM: Cancelled by sender");
+                    return Err(ErrorCode::ReadFailure);
+                },
+            }
+
+            packet = self.next_packet()?;
+        }
+    }
+
+    /// Wait for the next packet, asking for corrupt or missing packets to be
+    /// sent again
+    pub fn next_packet(&mut self) -> Result<Packet, ErrorCode> {
+        for _ in 0..XMODEM_RETRIES {
+            match self.read_packet(XMODEM_BLOCK_TIMEOUT_MS) {
+                Ok(packet) => return Ok(packet),
+                Err(_)     => {
+                    self.purge();
+                    self.port.write_u8(NAK);
+                }
+            }
+        }
+
+        self.cancel();
+        println!("XMODEM: Too many errors, giving up");
+        Err(ErrorCode::Timeout)
+    }
+
+    /// Wait for a packet. Corrupt packets are reported as `ReadFailure`,
+    /// and the caller should NAK them.
+    ///
+    /// # Arguments
+    /// * timeout_ms - Time to wait for the start of the packet
+    pub fn read_packet(&mut self, timeout_ms: u32) -> Result<Packet, ErrorCode> {
+        let len = match self.read_byte(timeout_ms).ok_or(ErrorCode::Timeout)? {
+            SOH => XMODEM_BLOCK_SIZE,
+            STX => XMODEM_1K_BLOCK_SIZE,
+            EOT => return Ok(Packet::Eot),
+            CAN => {
+                /* A lone CAN may be line noise */
+                return match self.read_byte(XMODEM_BYTE_TIMEOUT_MS) {
+                    Some(CAN) => Ok(Packet::Cancelled),
+                    _         => Err(ErrorCode::ReadFailure),
+                };
+            },
+            _   => return Err(ErrorCode::ReadFailure),
+        };
+
+        let errors = self.port.stats().errors();
+        let trailer = if self.crc { 2 } else { 1 };
+        let mut packet = Vec::with_capacity(2 + len + trailer);
+        for _ in 0..(2 + len + trailer) {
+            packet.push(self.read_byte(XMODEM_BYTE_TIMEOUT_MS).ok_or(ErrorCode::Timeout)?);
+        }
+
+        let (block, inverse) = (packet[0], packet[1]);
+        let payload = &packet[2..(2 + len)];
+        let valid = if self.crc {
+            crc16(payload) == u16::from_be_bytes([packet[2 + len], packet[3 + len]])
+        } else {
+            payload.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == packet[2 + len]
+        };
+
+        /* Line errors may be corruption that the checksum misses. They are
+         * counted as bytes arrive rather than as they are read here, and the
+         * FIFO can hold several packets, so an error may belong to another
+         * packet. That's only a hint, failing a good packet costs a resend
+         * and the checksum is still the real check. */
+        if block != !inverse || !valid || self.port.stats().errors() != errors {
+            return Err(ErrorCode::ReadFailure);
+        }
+
+        Ok(Packet::Data(block, payload.to_vec()))
+    }
+
+    /// Wait for a byte from the sender. A break ends the wait as a timeout
+    /// does, so the packet being read is sent again.
+    pub fn read_byte(&mut self, timeout_ms: u32) -> Option<u8> {
+        let deadline = Deadline::after_ms(timeout_ms);
+        loop {
+            /* The line was held low, maybe by a cable being pulled, and what
+             * arrived around it can't be trusted */
+            if self.port.take_break() {
+                return None;
+            }
+            if let Some(byte) = self.port.read_u8() {
+                return Some(byte);
+            }
+            if deadline.expired() {
+                return None;
+            }
+        }
+    }
+
+    /// Discard input until the line goes quiet, so a NAK isn't sent in the
+    /// middle of a packet
+    pub fn purge(&mut self) {
+        let mut deadline = Deadline::after_ms(XMODEM_BYTE_TIMEOUT_MS);
+        while !deadline.expired() {
+            if self.port.read_u8().is_some() {
+                deadline = Deadline::after_ms(XMODEM_BYTE_TIMEOUT_MS);
+            }
+        }
+
+        /* A break in what was discarded says nothing about what follows */
+        _ = self.port.take_break();
+    }
+
+    /// Tell the sender to abort the transfer
+    pub fn cancel(&mut self) {
+        for _ in 0..XMODEM_CANCEL_COUNT {
+            self.port.write_u8(CAN);
+        }
+    }
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0)
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if (crc & 0x8000) != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+const XMODEM_BLOCK_SIZE: usize    = 128;
+const XMODEM_1K_BLOCK_SIZE: usize = 1024;
+
+const XMODEM_RETRIES: usize          = 10;
+const XMODEM_BLOCK_TIMEOUT_MS: u32   = 10000; //< Time to wait for the next packet
+const XMODEM_START_RETRIES: usize    = 40;    //< Requests to start, giving the user two minutes to start sending
+const XMODEM_START_TIMEOUT_MS: u32   = 3000;
+const XMODEM_CRC_ATTEMPTS: usize     = 20;    //< Requests for CRC mode before falling back to checksums
+const XMODEM_BYTE_TIMEOUT_MS: u32    = 1000;
+const XMODEM_CANCEL_COUNT: usize     = 5;
+const XMODEM_PROGRESS_STEP: usize    = 64 * 1024;
+
+const SOH: u8           = 0x01; //< Start of 128 byte block
+const STX: u8           = 0x02; //< Start of 1 KiB block
+const EOT: u8           = 0x04; //< End of transmission
+pub const ACK: u8       = 0x06;
+pub const NAK: u8       = 0x15;
+const CAN: u8           = 0x18; //< Cancel
+const CRC_START: u8     = b'C'; //< Request to start in CRC mode
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_check_value() {
+        /* CRC-16/XMODEM check value */
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(crc16(&[]), 0);
+    }
+}
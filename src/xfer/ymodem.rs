@@ -0,0 +1,110 @@
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::errors::ErrorCode;
+use crate::highmem::StagingBuffer;
+use crate::io::output;
+use crate::io::serial::SerialPort;
+use crate::xfer::xmodem::{Packet, XmodemReceiver, ACK, NAK};
+use crate::xfer::ReceivedFile;
+
+/// Receive a batch of files with YMODEM.
+///
+/// Each file is preceded by block 0, which holds its name and size, so the
+/// padding of the final block is removed. An empty block 0 ends the batch.
+pub fn receive_batch(port: &mut SerialPort) -> Result<Vec<ReceivedFile>, ErrorCode> {
+    let mut rx = XmodemReceiver::new(port);
+    let mut files = Vec::new();
+
+    loop {
+        /* YMODEM always uses CRCs */
+        let header = match rx.start(false)? {
+            Packet::Data(0, header) => header,
+            Packet::Cancelled       => {
+                println!("YMODEM: Cancelled by sender");
+                return Err(ErrorCode::ReadFailure);
+            },
+            _                       => {
+                rx.cancel();
+                println!("YMODEM: Expected file header");
+                return Err(ErrorCode::ReadFailure);
+            }
+        };
+        rx.port.write_u8(ACK);
+
+        let (name, size) = match parse_header(&header) {
+            Some(file) => file,
+            None       => {
+                /* Empty name, end of batch */
+                return Ok(files);
+            }
+        };
+        println!("YMODEM: Receiving {} ({} bytes)", name, size.map_or("unknown".to_string(), |size| size.to_string()));
+
+        /* The size tells us now if it won't fit, rather than at the last
+         * block that does */
+        let mut data = match StagingBuffer::new() {
+            Ok(data) => data,
+            Err(e)   => {
+                rx.cancel();
+                return Err(e);
+            }
+        };
+        if size.is_some_and(|size| size > data.capacity()) {
+            rx.cancel();
+            println!("YMODEM: {} does not fit in {} KiB of memory", name, data.capacity() / 1024);
+            return Err(ErrorCode::NoSpace);
+        }
+
+        let first = rx.start(false)?;
+        rx.receive_file(first, &mut data)?;
+
+        /* The sender repeats EOT after a NAK, guarding against noise that
+         * looks like EOT */
+        rx.port.write_u8(NAK);
+        match rx.next_packet()? {
+            Packet::Eot => rx.port.write_u8(ACK),
+            _           => {
+                rx.cancel();
+                println!("YMODEM: Expected EOT");
+                return Err(ErrorCode::ReadFailure);
+            }
+        }
+
+        if let Some(size) = size {
+            if data.len() < size {
+                println!("YMODEM: {} truncated, {} of {} bytes received", name, data.len(), size);
+                return Err(ErrorCode::ReadFailure);
+            }
+            data.truncate(size);
+        }
+
+        files.push(ReceivedFile {
+            name,
+            data: data.finish(),
+        });
+    }
+}
+
+/// Parse block 0, which holds the file name, followed by the size in decimal
+/// and optional fields separated by spaces. ZMODEM's ZFILE data has the same
+/// layout.
+pub fn parse_header(header: &[u8]) -> Option<(String, Option<usize>)> {
+    let mut fields = header.split(|&c| c == 0);
+
+    let path = String::from_utf8_lossy(fields.next()?).into_owned();
+    if path.is_empty() {
+        return None;
+    }
+    let name = path.rsplit('/').next().unwrap_or(&path).to_string();
+
+    let size = fields.next()
+        .and_then(|info| core::str::from_utf8(info).ok())
+        .and_then(|info| info.split(' ').next())
+        .and_then(|size| size.parse().ok());
+
+    Some((name, size))
+}
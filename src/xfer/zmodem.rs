@@ -0,0 +1,652 @@
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::errors::ErrorCode;
+use crate::highmem::StagingBuffer;
+use crate::intr::pit::Deadline;
+use crate::io::output;
+use crate::io::serial::SerialPort;
+use crate::xfer::xmodem::crc16;
+use crate::xfer::ymodem::parse_header;
+use crate::xfer::ReceivedFile;
+
+/// File whose transfer was interrupted, kept so the next session can resume
+/// it rather than starting over
+pub struct ZmodemPartial {
+    name: String,
+    size: Option<usize>,
+    data: StagingBuffer,
+}
+
+/// Receive a batch of files with ZMODEM.
+///
+/// Data is streamed without waiting for acknowledgements, and a corrupt
+/// subpacket is recovered from by asking the sender to continue from the last
+/// good offset with ZRPOS. If the session fails part way through a file, what
+/// was received is left in `partial`, and is resumed from if the sender offers
+/// the same file again. Offering a different file drops it.
+pub fn receive_batch(port: &mut SerialPort, partial: &mut Option<ZmodemPartial>) -> Result<Vec<ReceivedFile>, ErrorCode> {
+    let mut rx = ZmodemReceiver { port };
+    let mut files = Vec::new();
+    let mut current: Option<ZmodemPartial> = None;
+
+    let result = rx.receive(&mut files, &mut current, partial);
+    if result.is_err() {
+        if let Some(cur) = current.filter(|cur| !cur.data.is_empty()) {
+            println!("ZMODEM: Kept {} bytes of {} to resume", cur.data.len(), cur.name);
+            *partial = Some(cur);
+        }
+    }
+
+    result.map(|_| files)
+}
+
+/// Give up on resuming an interrupted file, freeing the memory it holds
+pub fn discard_partial(partial: &mut Option<ZmodemPartial>) {
+    if let Some(old) = partial.take() {
+        println!("ZMODEM: Dropped {} bytes of {}", old.data.len(), old.name);
+    }
+}
+
+/// Why reading from the sender failed
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ZError {
+    Timeout,
+    Corrupt,   //< Bad CRC or framing, the data should be sent again
+    Cancelled, //< Sender aborted the session
+}
+
+impl From<ZError> for ErrorCode {
+    fn from(e: ZError) -> ErrorCode {
+        match e {
+            ZError::Timeout   => ErrorCode::Timeout,
+            ZError::Corrupt   => ErrorCode::ReadFailure,
+            ZError::Cancelled => ErrorCode::ReadFailure,
+        }
+    }
+}
+
+/// Byte read from ZDLE-encoded data
+enum ZByte {
+    Byte(u8),
+    FrameEnd(u8), //< End of a data subpacket, one of `ZCRCE`, `ZCRCG`, `ZCRCQ` or `ZCRCW`
+}
+
+struct ZHeader {
+    typ: u8,
+    data: [u8; 4], //< Position, little endian, or flags, with ZF0 last
+    crc32: bool,   //< Header, and any data subpackets following it, use CRC-32
+}
+
+impl ZHeader {
+    fn pos(&self) -> usize {
+        u32::from_le_bytes(self.data) as usize
+    }
+}
+
+struct ZmodemReceiver<'a> {
+    port: &'a mut SerialPort,
+}
+
+impl ZmodemReceiver<'_> {
+    fn receive(&mut self, files: &mut Vec<ReceivedFile>, current: &mut Option<ZmodemPartial>,
+               partial: &mut Option<ZmodemPartial>) -> Result<(), ErrorCode> {
+        let mut started = false;
+        let mut errors = 0;
+        let mut reported = 0;
+
+        self.send_zrinit();
+        loop {
+            let timeout = if started { ZMODEM_TIMEOUT_MS } else { ZMODEM_START_TIMEOUT_MS };
+            let hdr = match self.read_header(timeout) {
+                Ok(hdr)                => hdr,
+                Err(ZError::Cancelled) => return self.cancelled(),
+                Err(e)                 => {
+                    errors += 1;
+                    if errors >= if started { ZMODEM_RETRIES } else { ZMODEM_START_RETRIES } {
+                        self.cancel();
+                        println!("ZMODEM: Too many errors, giving up");
+                        return Err(e.into());
+                    }
+
+                    match current {
+                        Some(cur) => self.send_hex_header(ZRPOS, pos_data(cur.data.len())),
+                        None      => self.send_zrinit(),
+                    }
+                    continue;
+                }
+            };
+            started = true;
+
+            match hdr.typ {
+                ZRQINIT => self.send_zrinit(),
+                ZSINIT  => {
+                    /* The attention string is only needed by senders that
+                     * can't be interrupted while streaming, we always stop
+                     * reading to reply */
+                    match self.read_subpacket(hdr.crc32) {
+                        Ok(_)                  => self.send_hex_header(ZACK, [0; 4]),
+                        Err(ZError::Cancelled) => return self.cancelled(),
+                        Err(_)                 => self.send_hex_header(ZNAK, [0; 4]),
+                    }
+                },
+                ZFILE   => {
+                    let info = match self.read_subpacket(hdr.crc32) {
+                        Ok((info, _))          => info,
+                        Err(ZError::Cancelled) => return self.cancelled(),
+                        Err(_)                 => {
+                            self.send_hex_header(ZNAK, [0; 4]);
+                            continue;
+                        }
+                    };
+                    let (name, size) = match parse_header(&info) {
+                        Some(file) => file,
+                        None       => {
+                            self.send_hex_header(ZSKIP, [0; 4]);
+                            continue;
+                        }
+                    };
+
+                    /* Resume where an earlier session left off, or where
+                     * we were if the sender repeated ZFILE */
+                    let data = match current.take().or_else(|| partial.take()) {
+                        Some(old) if old.name == name && old.size == size => {
+                            println!("ZMODEM: Resuming {} at {} bytes", name, old.data.len());
+                            old.data
+                        },
+                        old => {
+                            /* Only one file at a time can be received into
+                             * extended memory */
+                            if let Some(old) = old {
+                                println!("ZMODEM: Dropped {} bytes of {}", old.data.len(), old.name);
+                            }
+
+                            let data = match StagingBuffer::new() {
+                                Ok(data) => data,
+                                Err(e)   => {
+                                    self.cancel();
+                                    return Err(e);
+                                }
+                            };
+                            if size.is_some_and(|size| size > data.capacity()) {
+                                println!("ZMODEM: {} does not fit in {} KiB of memory", name, data.capacity() / 1024);
+                                self.send_hex_header(ZSKIP, [0; 4]);
+                                continue;
+                            }
+
+                            println!("ZMODEM: Receiving {}", name);
+                            data
+                        }
+                    };
+                    reported = data.len() / ZMODEM_PROGRESS_STEP;
+
+                    self.send_hex_header(ZRPOS, pos_data(data.len()));
+                    *current = Some(ZmodemPartial {
+                        name,
+                        size,
+                        data,
+                    });
+                },
+                ZDATA   => {
+                    let cur = match current {
+                        Some(cur) => cur,
+                        None      => {
+                            self.send_zrinit();
+                            continue;
+                        }
+                    };
+                    if hdr.pos() != cur.data.len() {
+                        self.purge();
+                        self.send_hex_header(ZRPOS, pos_data(cur.data.len()));
+                        continue;
+                    }
+
+                    loop {
+                        let (data, end) = match self.read_subpacket(hdr.crc32) {
+                            Ok(sub)                => sub,
+                            Err(ZError::Cancelled) => return self.cancelled(),
+                            Err(_)                 => {
+                                errors += 1;
+                                if errors >= ZMODEM_RETRIES {
+                                    self.cancel();
+                                    println!("ZMODEM: Too many errors, giving up");
+                                    return Err(ErrorCode::ReadFailure);
+                                }
+
+                                /* Ask for everything from the last good subpacket */
+                                self.purge();
+                                self.send_hex_header(ZRPOS, pos_data(cur.data.len()));
+                                break;
+                            }
+                        };
+                        errors = 0;
+
+                        if cur.data.extend_from_slice(&data).is_err() {
+                            self.cancel();
+                            println!("ZMODEM: Out of memory after {} bytes", cur.data.len());
+                            return Err(ErrorCode::NoSpace);
+                        }
+
+                        if cur.data.len() / ZMODEM_PROGRESS_STEP > reported {
+                            reported = cur.data.len() / ZMODEM_PROGRESS_STEP;
+                            println!("  {} KiB received", cur.data.len() / 1024);
+                        }
+
+                        match end {
+                            ZCRCG => {},
+                            ZCRCQ => self.send_hex_header(ZACK, pos_data(cur.data.len())),
+                            ZCRCW => {
+                                self.send_hex_header(ZACK, pos_data(cur.data.len()));
+                                break;
+                            },
+                            _     => break,
+                        }
+                    }
+                },
+                ZEOF    => {
+                    /* An EOF for an offset we haven't reached yet is stale,
+                     * the sender repeats it once it has caught up */
+                    if let Some(cur) = current.take_if(|cur| hdr.pos() == cur.data.len()) {
+                        if let Some(size) = cur.size.filter(|&size| size != cur.data.len()) {
+                            println!("ZMODEM: {} is {} bytes, expected {}", cur.name, cur.data.len(), size);
+                        }
+                        files.push(ReceivedFile {
+                            name: cur.name,
+                            data: cur.data.finish(),
+                        });
+                        self.send_zrinit();
+                    }
+                },
+                ZFIN    => {
+                    self.send_hex_header(ZFIN, [0; 4]);
+                    /* The sender ends with "OO" (over and out) */
+                    for _ in 0..2 {
+                        _ = self.read_byte(ZMODEM_BYTE_TIMEOUT_MS);
+                    }
+                    return Ok(());
+                },
+                ZCAN | ZABORT => return self.cancelled(),
+                ZFREECNT      => self.send_hex_header(ZACK, [0xFF; 4]),
+                ZCOMMAND      => {
+                    /* Commands aren't executed, report success so the sender
+                     * moves on */
+                    _ = self.read_subpacket(hdr.crc32);
+                    self.send_hex_header(ZCOMPL, [0; 4]);
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn cancelled(&mut self) -> Result<(), ErrorCode> {
+        println!("ZMODEM: Cancelled by sender");
+        Err(ErrorCode::ReadFailure)
+    }
+
+    /// Tell the sender we're ready, and what we support
+    fn send_zrinit(&mut self) {
+        /* A buffer size of 0 lets the sender stream the whole file */
+        self.send_hex_header(ZRINIT, [0, 0, 0, ZRINIT_CANFDX | ZRINIT_CANOVIO | ZRINIT_CANFC32]);
+    }
+
+    fn send_hex_header(&mut self, typ: u8, data: [u8; 4]) {
+        let mut hdr = [0u8; 7];
+        hdr[0] = typ;
+        hdr[1..5].copy_from_slice(&data);
+        let crc = crc16(&hdr[..5]);
+        hdr[5..].copy_from_slice(&crc.to_be_bytes());
+
+        for byte in [ZPAD, ZPAD, ZDLE, ZHEX] {
+            self.port.write_u8(byte);
+        }
+        for byte in hdr {
+            self.port.write_u8(HEX_DIGITS[(byte >> 4) as usize]);
+            self.port.write_u8(HEX_DIGITS[(byte & 0x0F) as usize]);
+        }
+        self.port.write_u8(b'\r');
+        self.port.write_u8(b'\n');
+        if typ != ZACK && typ != ZFIN {
+            self.port.write_u8(XON);
+        }
+    }
+
+    /// Wait for a header, skipping anything before it
+    fn read_header(&mut self, timeout_ms: u32) -> Result<ZHeader, ZError> {
+        let deadline = Deadline::after_ms(timeout_ms);
+
+        /* Find ZPAD, then ZDLE after any further ZPADs */
+        loop {
+            if deadline.expired() {
+                return Err(ZError::Timeout);
+            }
+            if self.read_byte(ZMODEM_BYTE_TIMEOUT_MS)? != ZPAD {
+                continue;
+            }
+
+            let mut byte = self.read_byte(ZMODEM_BYTE_TIMEOUT_MS)?;
+            while byte == ZPAD {
+                byte = self.read_byte(ZMODEM_BYTE_TIMEOUT_MS)?;
+            }
+            if byte == ZDLE {
+                break;
+            }
+        }
+
+        decode_header(|| self.read_byte(ZMODEM_BYTE_TIMEOUT_MS))
+    }
+
+    /// Read a data subpacket, returning its data and how it ended
+    fn read_subpacket(&mut self, crc32: bool) -> Result<(Vec<u8>, u8), ZError> {
+        let mut data = Vec::new();
+        let errors = self.port.stats().errors();
+
+        let end = loop {
+            match self.read_zdle()? {
+                ZByte::Byte(byte)    => {
+                    if data.len() >= ZMODEM_MAX_SUBPACKET {
+                        return Err(ZError::Corrupt);
+                    }
+                    data.push(byte);
+                },
+                ZByte::FrameEnd(end) => break end,
+            }
+        };
+
+        /* The CRC covers the frame end too */
+        data.push(end);
+        let valid = if crc32 {
+            let mut crc = [0u8; 4];
+            for byte in crc.iter_mut() {
+                *byte = self.read_zdle_byte()?;
+            }
+            crc32_ieee(&data) == u32::from_le_bytes(crc)
+        } else {
+            let crc = [self.read_zdle_byte()?, self.read_zdle_byte()?];
+            crc16(&data) == u16::from_be_bytes(crc)
+        };
+        data.pop();
+
+        /* Bytes the UART dropped or garbled may still leave a valid CRC.
+         * Errors are counted as bytes arrive, and the FIFO can hold several
+         * subpackets, so an error may belong to another one. That's only a
+         * hint, failing a good subpacket costs a ZRPOS. */
+        if !valid || self.port.stats().errors() != errors {
+            return Err(ZError::Corrupt);
+        }
+
+        Ok((data, end))
+    }
+
+    /// Read a ZDLE-encoded byte that must not be a frame end
+    fn read_zdle_byte(&mut self) -> Result<u8, ZError> {
+        decode_zdle_byte(|| self.read_byte(ZMODEM_BYTE_TIMEOUT_MS))
+    }
+
+    fn read_zdle(&mut self) -> Result<ZByte, ZError> {
+        decode_zdle(|| self.read_byte(ZMODEM_BYTE_TIMEOUT_MS))
+    }
+
+    /// Wait for a byte from the sender. A break fails the read as corrupt,
+    /// so the sender is asked to go back to the last good offset.
+    fn read_byte(&mut self, timeout_ms: u32) -> Result<u8, ZError> {
+        let deadline = Deadline::after_ms(timeout_ms);
+        loop {
+            if self.port.take_break() {
+                return Err(ZError::Corrupt);
+            }
+            if let Some(byte) = self.port.read_u8() {
+                return Ok(byte);
+            }
+            if deadline.expired() {
+                return Err(ZError::Timeout);
+            }
+        }
+    }
+
+    /// Discard whatever the sender is still streaming after an error
+    fn purge(&mut self) {
+        while self.port.read_u8().is_some() {}
+
+        /* A break in what was discarded says nothing about what follows */
+        _ = self.port.take_break();
+    }
+
+    /// Abort the session
+    fn cancel(&mut self) {
+        for _ in 0..ZMODEM_CANCEL_COUNT {
+            self.port.write_u8(CAN);
+        }
+        for _ in 0..ZMODEM_CANCEL_COUNT {
+            self.port.write_u8(BS);
+        }
+    }
+}
+
+/// Decode the rest of a header, following ZPAD ZDLE, and check its CRC
+///
+/// # Arguments
+/// * next - Source of bytes from the sender
+fn decode_header(mut next: impl FnMut() -> Result<u8, ZError>) -> Result<ZHeader, ZError> {
+    let mut hdr = [0u8; 9];
+    let crc32 = match next()? {
+        ZHEX   => {
+            for byte in hdr[..7].iter_mut() {
+                *byte = (hex_digit(next()?)? << 4) | hex_digit(next()?)?;
+            }
+            false
+        },
+        ZBIN   => {
+            for byte in hdr[..7].iter_mut() {
+                *byte = decode_zdle_byte(&mut next)?;
+            }
+            false
+        },
+        ZBIN32 => {
+            for byte in hdr[..9].iter_mut() {
+                *byte = decode_zdle_byte(&mut next)?;
+            }
+            true
+        },
+        _      => return Err(ZError::Corrupt),
+    };
+
+    let valid = if crc32 {
+        crc32_ieee(&hdr[..5]) == u32::from_le_bytes([hdr[5], hdr[6], hdr[7], hdr[8]])
+    } else {
+        crc16(&hdr[..5]) == u16::from_be_bytes([hdr[5], hdr[6]])
+    };
+    if !valid {
+        return Err(ZError::Corrupt);
+    }
+
+    Ok(ZHeader {
+        typ: hdr[0],
+        data: [hdr[1], hdr[2], hdr[3], hdr[4]],
+        crc32,
+    })
+}
+
+/// Decode a ZDLE-encoded byte that must not be a frame end
+fn decode_zdle_byte(next: impl FnMut() -> Result<u8, ZError>) -> Result<u8, ZError> {
+    match decode_zdle(next)? {
+        ZByte::Byte(byte)  => Ok(byte),
+        ZByte::FrameEnd(_) => Err(ZError::Corrupt),
+    }
+}
+
+/// Decode a ZDLE-encoded byte, skipping flow control characters
+///
+/// # Arguments
+/// * next - Source of bytes from the sender
+fn decode_zdle(mut next: impl FnMut() -> Result<u8, ZError>) -> Result<ZByte, ZError> {
+    let mut next_data = || loop {
+        match next()? {
+            /* Flow control characters are always escaped in data */
+            XON | XOFF | XON_PARITY | XOFF_PARITY => continue,
+            byte                                  => return Ok(byte),
+        }
+    };
+
+    let byte = next_data()?;
+    if byte != ZDLE {
+        return Ok(ZByte::Byte(byte));
+    }
+
+    let escaped = next_data()?;
+    match escaped {
+        ZCRCE | ZCRCG | ZCRCQ | ZCRCW   => Ok(ZByte::FrameEnd(escaped)),
+        ZRUB0                           => Ok(ZByte::Byte(0x7F)),
+        ZRUB1                           => Ok(ZByte::Byte(0xFF)),
+        /* CAN is ZDLE, so this is the start of a cancel sequence */
+        CAN                             => Err(ZError::Cancelled),
+        byte if (byte & 0x60) == 0x40   => Ok(ZByte::Byte(byte ^ 0x40)),
+        _                               => Err(ZError::Corrupt),
+    }
+}
+
+fn hex_digit(byte: u8) -> Result<u8, ZError> {
+    match byte & 0x7F {
+        digit @ b'0'..=b'9' => Ok(digit - b'0'),
+        digit @ b'a'..=b'f' => Ok(digit - b'a' + 10),
+        _                   => Err(ZError::Corrupt),
+    }
+}
+
+fn pos_data(pos: usize) -> [u8; 4] {
+    (pos as u32).to_le_bytes()
+}
+
+/// CRC-32 as used by Ethernet and ZIP, which ZMODEM uses for its 32-bit frames
+fn crc32_ieee(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if (crc & 1) != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+const ZMODEM_RETRIES: usize         = 10;
+const ZMODEM_START_RETRIES: usize   = 40;    //< ZRINITs to send, giving the user two minutes to start sending
+const ZMODEM_START_TIMEOUT_MS: u32  = 3000;
+const ZMODEM_TIMEOUT_MS: u32        = 10000; //< Time to wait for the next header
+const ZMODEM_BYTE_TIMEOUT_MS: u32   = 1000;
+const ZMODEM_MAX_SUBPACKET: usize   = 8192;  //< Larger than any sender uses
+const ZMODEM_CANCEL_COUNT: usize    = 8;
+const ZMODEM_PROGRESS_STEP: usize   = 64 * 1024;
+
+const ZPAD: u8   = b'*';
+const ZDLE: u8   = 0x18;
+const ZBIN: u8   = b'A'; //< Binary header with CRC-16
+const ZHEX: u8   = b'B'; //< Hex header with CRC-16
+const ZBIN32: u8 = b'C'; //< Binary header with CRC-32
+
+const CAN: u8         = 0x18;
+const BS: u8          = 0x08;
+const XON: u8         = 0x11;
+const XOFF: u8        = 0x13;
+const XON_PARITY: u8  = 0x91;
+const XOFF_PARITY: u8 = 0x93;
+
+/* Frame types */
+const ZRQINIT: u8  = 0;
+const ZRINIT: u8   = 1;
+const ZSINIT: u8   = 2;
+const ZACK: u8     = 3;
+const ZFILE: u8    = 4;
+const ZSKIP: u8    = 5;
+const ZNAK: u8     = 6;
+const ZABORT: u8   = 7;
+const ZFIN: u8     = 8;
+const ZRPOS: u8    = 9;
+const ZDATA: u8    = 10;
+const ZEOF: u8     = 11;
+const ZCOMPL: u8   = 15;
+const ZCAN: u8     = 16;
+const ZFREECNT: u8 = 17;
+const ZCOMMAND: u8 = 18;
+
+/* Data subpacket ends */
+const ZCRCE: u8 = b'h'; //< End of frame, header follows
+const ZCRCG: u8 = b'i'; //< Frame continues, no response expected
+const ZCRCQ: u8 = b'j'; //< Frame continues, ZACK expected
+const ZCRCW: u8 = b'k'; //< End of frame, ZACK expected
+
+const ZRUB0: u8 = b'l'; //< Escaped 0x7F
+const ZRUB1: u8 = b'm'; //< Escaped 0xFF
+
+const ZRINIT_CANFDX: u8  = 0x01; //< Full duplex
+const ZRINIT_CANOVIO: u8 = 0x02; //< Can receive while storing data
+const ZRINIT_CANFC32: u8 = 0x20; //< Supports CRC-32 frames
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Source of bytes from a slice, timing out at its end
+    fn source(bytes: &[u8]) -> impl FnMut() -> Result<u8, ZError> + '_ {
+        let mut iter = bytes.iter().copied();
+        move || iter.next().ok_or(ZError::Timeout)
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32_ieee(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_ieee(&[]), 0);
+    }
+
+    #[test]
+    fn decodes_zdle_escapes() {
+        let decode = |bytes: &[u8]| decode_zdle(source(bytes));
+
+        assert!(matches!(decode(b"A"), Ok(ZByte::Byte(b'A'))));
+        assert!(matches!(decode(&[ZDLE, b'X']), Ok(ZByte::Byte(ZDLE))));
+        assert!(matches!(decode(&[ZDLE, 0x4D]), Ok(ZByte::Byte(0x0D))));
+        assert!(matches!(decode(&[ZDLE, ZRUB0]), Ok(ZByte::Byte(0x7F))));
+        assert!(matches!(decode(&[ZDLE, ZRUB1]), Ok(ZByte::Byte(0xFF))));
+        assert!(matches!(decode(&[ZDLE, ZCRCW]), Ok(ZByte::FrameEnd(ZCRCW))));
+
+        /* Flow control is skipped, even between ZDLE and what it escapes */
+        assert!(matches!(decode(&[XON, ZDLE, XOFF_PARITY, b'X']), Ok(ZByte::Byte(ZDLE))));
+
+        assert!(matches!(decode(&[ZDLE, CAN]), Err(ZError::Cancelled)));
+        assert!(matches!(decode(&[ZDLE, b' ']), Err(ZError::Corrupt)));
+        assert!(matches!(decode(&[ZDLE]), Err(ZError::Timeout)));
+    }
+
+    #[test]
+    fn parses_hex_header() {
+        /* ZRINIT as sent by lrz */
+        let hdr = decode_header(source(b"B0100000023be50")).ok().unwrap();
+        assert_eq!(hdr.typ, ZRINIT);
+        assert_eq!(hdr.data, [0, 0, 0, 0x23]);
+        assert!(!hdr.crc32);
+
+        let hdr = decode_header(source(b"B0900100000eb1f")).ok().unwrap();
+        assert_eq!(hdr.typ, ZRPOS);
+        assert_eq!(hdr.pos(), 0x1000);
+
+        assert!(matches!(decode_header(source(b"B0100000023be51")), Err(ZError::Corrupt)));
+        assert!(matches!(decode_header(source(b"B01000000G3be50")), Err(ZError::Corrupt)));
+        assert!(matches!(decode_header(source(b"B01000000")), Err(ZError::Timeout)));
+    }
+
+    #[test]
+    fn parses_binary_header() {
+        let hdr = decode_header(source(&[ZBIN32, ZDATA, 0x34, 0x12, 0, 0, 0x54, 0xF7, 0x79, 0xEC])).ok().unwrap();
+        assert_eq!(hdr.typ, ZDATA);
+        assert_eq!(hdr.pos(), 0x1234);
+        assert!(hdr.crc32);
+
+        assert!(matches!(decode_header(source(&[ZBIN32, ZDATA, 0x34, 0x12, 0, 0, 0x54, 0xF7, 0x79, 0xED])),
+                         Err(ZError::Corrupt)));
+    }
+}
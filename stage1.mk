@@ -2,7 +2,9 @@
 
 S1_BUILDDIR = $(BUILDDIR)/stage1
 
-STAGE1  = $(S1_BUILDDIR)/stage1.bin
+STAGE1     = $(S1_BUILDDIR)/stage1.bin
+STAGE1_CD  = $(S1_BUILDDIR)/stage1_cd.bin
+STAGE1_PXE = $(S1_BUILDDIR)/stage1_pxe.bin
 
 S1_ASFLAGS = -I stage1 --32
 S1_LDFLAGS = -melf_i386 -T stage1/stage1.ld
@@ -17,8 +19,30 @@ $(STAGE1).o: stage1/stage1.s
 	$(Q) mkdir -p $(dir $@)
 	$(Q) $(AS) $(S1_ASFLAGS) -c -o $@ $<
 
+$(STAGE1_CD): $(STAGE1_CD).o
+	@echo -e "\033[32m    \033[1mLD\033[21m    \033[34m$<\033[0m"
+	$(Q) $(LD) $(S1_LDFLAGS) -o $(STAGE1_CD).elf $<
+	$(Q) $(OBJCOPY) -O binary --only-section=.text $(STAGE1_CD).elf $@
+
+$(STAGE1_CD).o: stage1/stage1_cd.s
+	@echo -e "\033[32m    \033[1mAS\033[21m    \033[34m$<\033[0m"
+	$(Q) mkdir -p $(dir $@)
+	$(Q) $(AS) $(S1_ASFLAGS) -c -o $@ $<
+
+$(STAGE1_PXE): $(STAGE1_PXE).o
+	@echo -e "\033[32m    \033[1mLD\033[21m    \033[34m$<\033[0m"
+	$(Q) $(LD) $(S1_LDFLAGS) -o $(STAGE1_PXE).elf $<
+	$(Q) $(OBJCOPY) -O binary --only-section=.text $(STAGE1_PXE).elf $@
+
+$(STAGE1_PXE).o: stage1/stage1_pxe.s
+	@echo -e "\033[32m    \033[1mAS\033[21m    \033[34m$<\033[0m"
+	$(Q) mkdir -p $(dir $@)
+	$(Q) $(AS) $(S1_ASFLAGS) -c -o $@ $<
+
 stage1_clean:
 	$(Q) rm -f $(STAGE1) $(STAGE1).o $(STAGE1).elf
+	$(Q) rm -f $(STAGE1_CD) $(STAGE1_CD).o $(STAGE1_CD).elf
+	$(Q) rm -f $(STAGE1_PXE) $(STAGE1_PXE).o $(STAGE1_PXE).elf
 
 .PHONY: stage1_clean
 
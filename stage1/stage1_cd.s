@@ -0,0 +1,147 @@
+.code16
+
+/* Source for El Torito no-emulation boot image. The BIOS loads the first 2048
+ * bytes, only the first 512 of which are used. Stage 2 directly follows in the
+ * boot file, starting at the second CD sector. */
+
+stack_top = 0x1000     /* Top of temporary stack (~2 KiB) */
+
+stage2_addr = 0x1200   /* Address to which to load stage 2 loader */
+
+cd_sector_size = 2048
+
+boot_image_start:
+    jmp start
+
+/* Boot information table, populated by `mkisofs -boot-info-table` */
+.skip (8 - (. - boot_image_start))
+bi.pvd_lba:    .long 0 /* 0x08: LBA of primary volume descriptor */
+bi.file_lba:   .long 0 /* 0x0c: LBA of boot file */
+bi.file_len:   .long 0 /* 0x10: Length of boot file in bytes */
+bi.checksum:   .long 0 /* 0x14: Checksum of boot file past this table */
+bi.reserved:   .skip 40
+
+
+.global start
+start:
+    cli
+    /* Setup segment registers */
+    xorw %ax, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+
+    /* Relocate to 0x1000 */
+    movw $0x7c00, %si
+    movw $0x1000, %di
+    movw $512, %cx
+    cld
+    rep movsb
+
+
+    /* Jump to the relocated code, and set CS to 0 */
+    ljmp $0, $1f
+1:
+    /* Setup stack */
+    movw $stack_top, %sp
+    movw %sp,        %bp
+    sti
+
+    movb %dl, boot_drive
+
+    /* Set video mode and clear screen */
+    movw $0x0003, %ax /* 80x25 */
+    int  $0x10
+
+    /* Print boot message */
+    movw $boot_message, %si
+    call msg_print
+
+    /* Stage 2 begins at the second sector of the boot file */
+    movl (bi.file_lba), %eax
+    incl %eax
+    movl %eax, (dap.lba)
+
+    /* Sectors remaining = ceil(file_len / sector_size) - 1 */
+    movl (bi.file_len), %eax
+    addl $(cd_sector_size - 1), %eax
+    shrl $11, %eax
+    decl %eax
+    jz   .load_done
+    movw %ax, %cx
+
+  .load_sector:
+    movb $3, %bl /* Setup attempts counter */
+  .read_retry:
+    movw $dap,       %si
+    movb $0x42,      %ah /* Extended read */
+    movb boot_drive, %dl
+    int  $0x13
+    jnc  .read_ok
+
+    decb %bl
+    jz   disk_error
+    jmp  .read_retry
+
+  .read_ok:
+    movw $sector_read_success_message, %si
+    call msg_print
+
+    /* Next sector, advance destination segment by one sector */
+    incl (dap.lba)
+    addw $(cd_sector_size >> 4), (dap.segment)
+    loop .load_sector
+
+  .load_done:
+    /* Jmp into stage2 */
+    movb boot_drive, %dl
+    ljmp $0, $stage2_addr
+
+
+disk_error:
+    movw $disk_err_message, %si
+    call msg_print
+    jmp .
+
+
+/* Print message.
+ *
+ * Parameters:
+ *   %si: Pointer to string to print.
+ */
+msg_print:
+    pusha
+    movb $0x0E, %ah /* Write character */
+    xorw %bx,   %bx /* Page, no color for text mode */
+.loop:
+    lodsb           /* Load character and increment %si */
+    cmpb $0, %al    /* Exit if character is null */
+    je   .end
+    int  $0x10      /* Print character */
+    jmp  .loop
+.end:
+    popa
+    ret
+
+/* INT 13h extensions disk address packet */
+.align 4
+dap:
+dap.size:    .byte 0x10
+dap.reserved:.byte 0x00
+dap.count:   .word 1                  /* Sectors to read per call */
+dap.offset:  .word 0x0000             /* Destination offset */
+dap.segment: .word (stage2_addr >> 4) /* Destination segment */
+dap.lba:     .quad 0                  /* Sector to read */
+
+boot_drive: .skip 1 /* Drive the BIOS tells us we booted from */
+
+boot_message:
+    .asciz "Stage1 (CD).\r\n"
+disk_err_message:
+    .asciz "Disk read error."
+sector_read_success_message:
+    .asciz "."
+
+/* Only the first 512 bytes are relocated, pad the rest of the CD sector */
+.skip (512 - (. - boot_image_start))
+.skip (cd_sector_size - 512)
@@ -0,0 +1,102 @@
+.code16
+
+/* Source for PXE network bootstrap program (NBP). The PXE ROM downloads the
+ * whole NBP to 0x7C00 and jumps to it, so stage 2 directly follows this
+ * 512-byte stub and only needs to be moved to where it expects to run. */
+
+reloc_addr = 0x0600    /* Address this stub relocates itself to */
+stack_top  = 0x1000    /* Top of temporary stack (~2 KiB) */
+
+stage2_addr = 0x1200   /* Address to which to move stage 2 loader */
+stage2_src  = 0x7E00   /* Address of stage 2 within the downloaded NBP */
+stage2_end  = 0x80000  /* End of memory owned by stage 2 */
+
+pxe_boot_drive = 0x7F  /* Boot "drive" number that tells stage 2 it was loaded via PXE */
+
+boot_image_start:
+.global start
+start:
+    cli
+    /* Setup segment registers */
+    xorw %ax, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    movw %ax, %ss
+
+    /* Relocate out of the way of stage 2 */
+    movw $0x7c00,     %si
+    movw $reloc_addr, %di
+    movw $512,        %cx
+    cld
+    rep movsb
+
+    /* Jump to the relocated code, and set CS to 0 */
+    ljmp $0, $(reloc_addr + (1f - boot_image_start))
+1:
+    /* Setup stack. The PXE ROM may leave junk in the high half of ESP, which
+     * stage 2 keeps using once in protected mode. */
+    movl $stack_top, %esp
+    movl %esp,       %ebp
+    sti
+
+    /* Set video mode and clear screen */
+    movw $0x0003, %ax /* 80x25 */
+    int  $0x10
+
+    /* Print boot message */
+    movw $(reloc_addr + (boot_message - boot_image_start)), %si
+    call msg_print
+
+    /* Move stage 2 down in 32 KiB chunks. The destination is always below the
+     * source, so a forward copy is safe. The size of stage 2 isn't known
+     * here, so everything up to the end of stage 2's memory is moved. */
+    cli
+    movw $(stage2_src >> 4),  %ax
+    movw $(stage2_addr >> 4), %bx
+    movw $((stage2_end - stage2_addr) / 0x8000), %dx
+  .copy_chunk:
+    movw %ax, %ds
+    movw %bx, %es
+    xorw %si, %si
+    xorw %di, %di
+    movw $0x8000, %cx
+    rep movsb
+    addw $0x800, %ax
+    addw $0x800, %bx
+    decw %dx
+    jnz  .copy_chunk
+
+    xorw %ax, %ax
+    movw %ax, %ds
+    movw %ax, %es
+    sti
+
+    /* Jmp into stage2 */
+    movb $pxe_boot_drive, %dl
+    ljmp $0, $stage2_addr
+
+
+/* Print message.
+ *
+ * Parameters:
+ *   %si: Pointer to string to print.
+ */
+msg_print:
+    pusha
+    movb $0x0E, %ah /* Write character */
+    xorw %bx,   %bx /* Page, no color for text mode */
+.loop:
+    lodsb           /* Load character and increment %si */
+    cmpb $0, %al    /* Exit if character is null */
+    je   .end
+    int  $0x10      /* Print character */
+    jmp  .loop
+.end:
+    popa
+    ret
+
+boot_message:
+    .asciz "Stage1 (PXE).\r\n"
+
+/* Stage 2 starts 512 bytes into the NBP */
+.skip (512 - (. - boot_image_start))
@@ -20,6 +20,12 @@ RUSTFLAGS        += -Z location-detail=none
 CARGO_FLAGS      += -Z build-std-features=panic_immediate_abort
 endif
 
+# Disk image to embed in stage 2 and mount as ram0
+ifneq ($(RAMDISK),)
+CARGO_FLAGS += --features=ramdisk
+export RLBOOT_RAMDISK = $(abspath $(RAMDISK))
+endif
+
 export RUSTFLAGS
 
 
@@ -65,6 +71,10 @@ $(S2_RUST_OBJ):
 stage2_check:
 	$(Q) $(CARGO) clippy $(CARGO_RELEASE) $(CARGO_FLAGS)
 
+# Unit tests build for the host rather than the bootloader target
+stage2_test:
+	$(Q) RUSTFLAGS= $(CARGO) test --lib
+
 
 $(S2_BUILDDIR)/%.o: $(S2_SRCDIR)/%.s
 	@echo -e "\033[32m    \033[1mAS\033[21m    \033[34m$<\033[0m"
@@ -76,7 +86,7 @@ stage2_clean:
 	$(Q) rm -f $(STAGE2) $(STAGE2).elf $(S2_OBJS) $(S2_DEPS)
 	$(Q) $(CARGO) clean --release
 
-.PHONY: stage2_clean stage2_check $(S2_RUST_OBJ)
+.PHONY: stage2_clean stage2_check stage2_test $(S2_RUST_OBJ)
 
 -include $(S2_DEPS)
 